@@ -9,11 +9,13 @@ use internet::{
 
 use pmc_core::engine::{ClassifierEngine, Rule};
 use pmc_core::expression::Expr;
-use pmc_core::packet::{Direction, Packet};
+use pmc_core::packet::{Direction, LinkType, Packet};
 
 use mac_address::mac_address_by_name;
 use pcap::{Active, Capture, Device, Linktype};
 
+use std::time::Duration;
+
 fn main() {
     let args: Vec<String> = std::env::args().collect();
     let interface = args.get(1).expect("An interface must be specified");
@@ -34,7 +36,8 @@ fn main() {
             Rule::new("Ipv4", Expr::value(IpVersion::V4)),
             Rule::new("Ipv6", Expr::value(IpVersion::V6)),
         ],
-    );
+    )
+    .expect("The rules use loaded classifiers");
 
     loop {
         if let Some(packet) = network.next() {
@@ -57,7 +60,7 @@ impl NetworkInspector {
         let device = Device::list()
             .unwrap()
             .into_iter()
-            .find(|device| &device.name == interface)
+            .find(|device| device.name == *interface)
             .unwrap();
 
         let capture = Capture::from_device(device)
@@ -88,22 +91,24 @@ impl NetworkInspector {
 
     fn next(&mut self) -> Option<Packet<'_>> {
         let pcap_packet = self.capture.next().unwrap();
-        if matches!(pcap_packet.data[12..14], [0x08, 0x00] | [0x86, 0xdd]) {
-            let direction = if pcap_packet.data[0..6] == self.interface_mac {
-                Direction::Downlink
-            } else if pcap_packet.data[6..12] == self.interface_mac {
-                Direction::Uplink
-            } else {
-                // The message do not belong to the expected interface
-                return None;
-            };
+        let direction = if pcap_packet.data[0..6] == self.interface_mac {
+            Direction::Downlink
+        } else if pcap_packet.data[6..12] == self.interface_mac {
+            Direction::Uplink
+        } else {
+            // The message do not belong to the expected interface
+            return None;
+        };
 
-            // IP packet over ethernet.
-            return Some(Packet {
-                data: &pcap_packet.data[14..],
-                direction,
-            });
-        }
-        None
+        // The whole frame is classified, starting at the Ethernet header.
+        Some(
+            Packet::new(pcap_packet.data, direction)
+                .with_timestamp(Duration::new(
+                    pcap_packet.header.ts.tv_sec as u64,
+                    pcap_packet.header.ts.tv_usec as u32 * 1000,
+                ))
+                .with_wire_len(pcap_packet.header.len as usize)
+                .with_link_type(LinkType::Ethernet),
+        )
     }
 }
@@ -0,0 +1,508 @@
+use crate::Config;
+
+use pmc_core::base::classifier::Classifier;
+
+pub struct DnsClassifier;
+impl<'a> Classifier<'a, Config> for DnsClassifier {
+    type Analyzer = analyzer::DnsAnalyzer<'a>;
+}
+
+/// Length prefix of the DNS messages sent over TCP.
+pub struct DnsTcpClassifier;
+impl<'a> Classifier<'a, Config> for DnsTcpClassifier {
+    type Analyzer = analyzer::DnsTcpAnalyzer;
+}
+
+mod analyzer {
+    use super::flow::DnsFlow;
+
+    use crate::{ClassifierId, Config, FlowKind, FlowSignature};
+
+    use pmc_core::base::analyzer::{
+        Analyzer, AnalyzerError, AnalyzerErrorKind, AnalyzerInfo, AnalyzerResult, UseFlow,
+    };
+    use pmc_core::packet::{Direction, Packet};
+
+    pub struct DnsTcpAnalyzer {
+        pub message_len: u16,
+    }
+
+    impl<'a> Analyzer<'a, Config> for DnsTcpAnalyzer {
+        const ID: ClassifierId = ClassifierId::DnsTcp;
+        const PREV_IDS: &'static [ClassifierId] = &[ClassifierId::Tcp];
+
+        type Flow = DnsFlow;
+
+        fn build(
+            _config: &Config,
+            &Packet { data, .. }: &'a Packet,
+            _flow: &DnsFlow,
+        ) -> AnalyzerResult<Self, ClassifierId> {
+            AnalyzerError::check_len(Self::ID, data, 2)?;
+            Ok(AnalyzerInfo {
+                analyzer: Self {
+                    message_len: u16::from_be_bytes(*array_ref![data, 0, 2]),
+                },
+                next_classifier_id: ClassifierId::Dns,
+                bytes_parsed: 2,
+            })
+        }
+    }
+
+    /// First question of the message.
+    struct Question {
+        name_offset: usize,
+        qtype: u16,
+    }
+
+    pub struct DnsAnalyzer<'a> {
+        /// The message from its header.
+        message: &'a [u8],
+        question: Option<Question>,
+        /// Response to a query seen before in the flow.
+        answered: bool,
+    }
+
+    impl<'a> DnsAnalyzer<'a> {
+        const HEADER_LEN: usize = 12;
+        /// Compression pointers followed in a name, enough for any valid name.
+        const MAX_POINTERS: usize = 64;
+
+        pub fn id(&self) -> u16 {
+            u16::from_be_bytes(*array_ref![self.message, 0, 2])
+        }
+
+        fn flags(&self) -> u16 {
+            u16::from_be_bytes(*array_ref![self.message, 2, 2])
+        }
+
+        pub fn is_response(&self) -> bool {
+            self.flags() & 0x8000 != 0
+        }
+
+        pub fn response_code(&self) -> u8 {
+            (self.flags() & 0x000F) as u8
+        }
+
+        pub fn answer_count(&self) -> u16 {
+            u16::from_be_bytes(*array_ref![self.message, 6, 2])
+        }
+
+        pub fn query_type(&self) -> Option<u16> {
+            self.question.as_ref().map(|question| question.qtype)
+        }
+
+        /// The response matches a query of its flow by transaction ID.
+        pub fn is_answer(&self) -> bool {
+            self.answered
+        }
+
+        /// Labels of the queried name, from the first one.
+        pub fn query_labels(&self) -> impl Iterator<Item = &'a [u8]> + 'a {
+            let message = self.message;
+            let mut offset = self.question.as_ref().map(|question| question.name_offset);
+            std::iter::from_fn(move || loop {
+                // The name was checked when the analyzer was built.
+                let position = offset?;
+                let len = message[position] as usize;
+                match len & 0xC0 {
+                    0xC0 => {
+                        let pointer = u16::from_be_bytes(*array_ref![message, position, 2]);
+                        offset = Some((pointer & 0x3FFF) as usize);
+                    }
+                    _ if len == 0 => {
+                        offset = None;
+                        return None;
+                    }
+                    _ => {
+                        offset = Some(position + 1 + len);
+                        return Some(&message[position + 1..position + 1 + len]);
+                    }
+                }
+            })
+        }
+
+        /// Queried name in lowercase, without the final dot.
+        pub fn query_name(&self) -> Option<String> {
+            self.question.as_ref()?;
+            let labels = self
+                .query_labels()
+                .map(|label| String::from_utf8_lossy(label).to_ascii_lowercase())
+                .collect::<Vec<_>>();
+            Some(labels.join("."))
+        }
+
+        /// Checks the name at `offset`, returning the offset after it.
+        /// Pointers can only go backwards, so they can not loop.
+        fn check_name(message: &[u8], offset: usize) -> Result<usize, AnalyzerError<ClassifierId>> {
+            let mut position = offset;
+            let mut end = None;
+            let mut pointers = 0;
+            loop {
+                AnalyzerError::check_len(Self::ID, message, position + 1)?;
+                let len = message[position] as usize;
+                match len & 0xC0 {
+                    0xC0 => {
+                        AnalyzerError::check_len(Self::ID, message, position + 2)?;
+                        let pointer = u16::from_be_bytes(*array_ref![message, position, 2]);
+                        let target = (pointer & 0x3FFF) as usize;
+                        pointers += 1;
+                        if target >= position || pointers > Self::MAX_POINTERS {
+                            return Err(AnalyzerError::new(
+                                Self::ID,
+                                AnalyzerErrorKind::Malformed,
+                                position,
+                            ));
+                        }
+                        end.get_or_insert(position + 2);
+                        position = target;
+                    }
+                    0x00 if len == 0 => break Ok(end.unwrap_or(position + 1)),
+                    0x00 => position += 1 + len,
+                    _ => {
+                        return Err(AnalyzerError::new(
+                            Self::ID,
+                            AnalyzerErrorKind::Unsupported,
+                            position,
+                        ))
+                    }
+                }
+            }
+        }
+    }
+
+    impl<'a> Analyzer<'a, Config> for DnsAnalyzer<'a> {
+        const ID: ClassifierId = ClassifierId::Dns;
+        const PREV_IDS: &'static [ClassifierId] = &[ClassifierId::Udp, ClassifierId::DnsTcp];
+
+        type Flow = DnsFlow;
+
+        fn update_flow_id(
+            signature: &mut FlowSignature,
+            _packet: &Packet,
+        ) -> UseFlow<ClassifierId> {
+            signature.kind = FlowKind::Dns;
+            UseFlow::Yes
+        }
+
+        fn build(
+            _config: &Config,
+            &Packet { data, .. }: &'a Packet,
+            flow: &DnsFlow,
+        ) -> AnalyzerResult<Self, ClassifierId> {
+            AnalyzerError::check_len(Self::ID, data, Self::HEADER_LEN)?;
+            let question_count = u16::from_be_bytes(*array_ref![data, 4, 2]);
+
+            let (question, bytes_parsed) = match question_count {
+                0 => (None, Self::HEADER_LEN),
+                _ => {
+                    let name_end = Self::check_name(data, Self::HEADER_LEN)?;
+                    AnalyzerError::check_len(Self::ID, data, name_end + 4)?;
+                    let question = Question {
+                        name_offset: Self::HEADER_LEN,
+                        qtype: u16::from_be_bytes(*array_ref![data, name_end, 2]),
+                    };
+                    (Some(question), name_end + 4)
+                }
+            };
+
+            let mut analyzer = Self {
+                message: data,
+                question,
+                answered: false,
+            };
+            analyzer.answered = analyzer.is_response() && flow.is_pending(analyzer.id());
+
+            Ok(AnalyzerInfo {
+                analyzer,
+                next_classifier_id: ClassifierId::None,
+                bytes_parsed,
+            })
+        }
+
+        fn update_flow(&self, _config: &Config, flow: &mut DnsFlow, _direction: Direction) {
+            match self.is_response() {
+                true => flow.answer(self.id()),
+                false => flow.query(self.id()),
+            }
+        }
+    }
+}
+
+mod flow {
+    /// Transaction IDs of the queries without response.
+    #[derive(Default)]
+    pub struct DnsFlow {
+        pending: Vec<u16>,
+    }
+
+    impl DnsFlow {
+        /// Queries remembered at the same time. The oldest one is forgotten first.
+        const MAX_PENDING: usize = 32;
+
+        pub fn query(&mut self, id: u16) {
+            if self.is_pending(id) {
+                return;
+            }
+            if self.pending.len() == Self::MAX_PENDING {
+                self.pending.remove(0);
+            }
+            self.pending.push(id);
+        }
+
+        pub fn answer(&mut self, id: u16) {
+            self.pending.retain(|&pending| pending != id);
+        }
+
+        pub fn is_pending(&self, id: u16) -> bool {
+            self.pending.contains(&id)
+        }
+    }
+}
+
+pub mod expression {
+    use super::analyzer::DnsAnalyzer;
+    use super::flow::DnsFlow;
+    use super::DnsClassifier;
+
+    use crate::Config;
+
+    use pmc_core::base::expression_value::ExpressionValue;
+    use pmc_core::expression::Expr;
+    use pmc_core::expression_value;
+    use pmc_core::language::{Literal, Operator, ValueCall, ValueRegistry};
+
+    use std::fmt;
+
+    #[expression_value(Dns, classifier = DnsClassifier, grant_by_flow, definition = "dns")]
+    pub fn dns(_packet: &DnsAnalyzer, _flow: &DnsFlow) -> bool {
+        true
+    }
+
+    /// Responses to a query seen in the same flow.
+    #[expression_value(DnsAnswered, classifier = DnsClassifier, definition = "dns.answered")]
+    pub fn dns_answered(packet: &DnsAnalyzer, _flow: &DnsFlow) -> bool {
+        packet.is_answer()
+    }
+
+    /// Labels of a dotted name, ignoring the final dot.
+    fn name_labels(name: &str) -> impl DoubleEndedIterator<Item = &[u8]> {
+        name.strip_suffix('.')
+            .unwrap_or(name)
+            .split('.')
+            .filter(|label| !label.is_empty())
+            .map(str::as_bytes)
+    }
+
+    // The text values are written by hand, the macro does not take generics.
+    /// The queried name is the expected one, ignoring the case.
+    #[derive(Debug)]
+    pub struct DnsQueryName<S = &'static str>(pub S);
+    impl<S> ExpressionValue<Config> for DnsQueryName<S>
+    where S: AsRef<str> + fmt::Debug + Send + Sync + 'static
+    {
+        type Classifier = DnsClassifier;
+
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("dns.qname").with_comparison(Operator::Equal, self.0.as_ref()))
+        }
+
+        fn check(&self, packet: &DnsAnalyzer, _flow: &DnsFlow) -> bool {
+            let mut expected = name_labels(self.0.as_ref());
+            let mut labels = packet.query_labels();
+            packet.query_type().is_some()
+                && loop {
+                    match (labels.next(), expected.next()) {
+                        (Some(label), Some(expected)) if label.eq_ignore_ascii_case(expected) => {}
+                        (None, None) => break true,
+                        _ => break false,
+                    }
+                }
+        }
+    }
+
+    /// The queried name is the expected one or one of its subdomains, ignoring the case.
+    #[derive(Debug)]
+    pub struct DnsQueryNameSuffix<S = &'static str>(pub S);
+    impl<S> ExpressionValue<Config> for DnsQueryNameSuffix<S>
+    where S: AsRef<str> + fmt::Debug + Send + Sync + 'static
+    {
+        type Classifier = DnsClassifier;
+
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("dns.qname").with_comparison(Operator::Match, self.0.as_ref()))
+        }
+
+        fn check(&self, packet: &DnsAnalyzer, _flow: &DnsFlow) -> bool {
+            let labels = packet.query_labels().collect::<Vec<_>>();
+            let mut labels = labels.iter().rev();
+            packet.query_type().is_some()
+                && name_labels(self.0.as_ref()).rev().all(|expected| {
+                    labels
+                        .next()
+                        .is_some_and(|label| label.eq_ignore_ascii_case(expected))
+                })
+        }
+    }
+
+    const QUERY_TYPES: &[(&str, u16)] = &[
+        ("A", 1),
+        ("NS", 2),
+        ("CNAME", 5),
+        ("SOA", 6),
+        ("PTR", 12),
+        ("MX", 15),
+        ("TXT", 16),
+        ("AAAA", 28),
+        ("SRV", 33),
+        ("HTTPS", 65),
+        ("ANY", 255),
+    ];
+
+    const RESPONSE_CODES: &[(&str, u16)] = &[
+        ("NOERROR", 0),
+        ("FORMERR", 1),
+        ("SERVFAIL", 2),
+        ("NXDOMAIN", 3),
+        ("NOTIMP", 4),
+        ("REFUSED", 5),
+    ];
+
+    /// Name of the number if it has one.
+    fn named(names: &[(&'static str, u16)], value: u16) -> Literal {
+        match names.iter().find(|(_, named_value)| *named_value == value) {
+            Some((name, _)) => Literal::Str(name.to_string()),
+            None => Literal::Int(value.into()),
+        }
+    }
+
+    fn named_number(call: &ValueCall, names: &[(&str, u16)]) -> Result<u16, String> {
+        call.expect_args(0)?;
+        let value = call.compared_by(Operator::Equal)?;
+        if let Ok(number) = value.as_u16() {
+            return Ok(number);
+        }
+        let name = value.as_text()?;
+        names
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .map(|&(_, number)| number)
+            .ok_or_else(|| format!("Unknown name '{}'", name))
+    }
+
+    // The numbers are written by name in the definitions, which the macro does not do.
+    /// Type of the first question. In rules, the common types can be written by name.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct DnsQueryType(pub u16);
+    impl DnsQueryType {
+        pub const A: Self = Self(1);
+        pub const AAAA: Self = Self(28);
+    }
+    impl ExpressionValue<Config> for DnsQueryType {
+        type Classifier = DnsClassifier;
+
+        fn definition(&self) -> Option<ValueCall> {
+            let qtype = named(QUERY_TYPES, self.0);
+            Some(ValueCall::new("dns.qtype").with_comparison(Operator::Equal, qtype))
+        }
+
+        fn check(&self, packet: &DnsAnalyzer, _flow: &DnsFlow) -> bool {
+            packet.query_type() == Some(self.0)
+        }
+    }
+
+    /// Response code of a response.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct DnsResponseCode(pub u8);
+    impl DnsResponseCode {
+        pub const NO_ERROR: Self = Self(0);
+        pub const NX_DOMAIN: Self = Self(3);
+    }
+    impl ExpressionValue<Config> for DnsResponseCode {
+        type Classifier = DnsClassifier;
+
+        fn definition(&self) -> Option<ValueCall> {
+            let rcode = named(RESPONSE_CODES, self.0.into());
+            Some(ValueCall::new("dns.rcode").with_comparison(Operator::Equal, rcode))
+        }
+
+        fn check(&self, packet: &DnsAnalyzer, _flow: &DnsFlow) -> bool {
+            packet.is_response() && packet.response_code() == self.0
+        }
+    }
+
+    // The values over a user function are written by hand, the macro does not take generics.
+    pub struct DnsAnswerCount<F>(pub F);
+    impl<F> fmt::Debug for DnsAnswerCount<F> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+            write!(f, "DnsAnswerCount(USER_FN)")
+        }
+    }
+    impl<F> ExpressionValue<Config> for DnsAnswerCount<F>
+    where F: Fn(u16) -> bool + Send + Sync + 'static
+    {
+        type Classifier = DnsClassifier;
+
+        fn check(&self, packet: &DnsAnalyzer, _flow: &DnsFlow) -> bool {
+            self.0(packet.answer_count())
+        }
+    }
+
+    // The macro only writes definitions compared by `==`, not by the operator of the value.
+    /// Answer count compared with a fixed value.
+    /// Unlike [`DnsAnswerCount`], it can be stored as a rule definition.
+    #[derive(Debug)]
+    pub struct DnsAnswerCountCmp(pub Operator, pub u16);
+    impl ExpressionValue<Config> for DnsAnswerCountCmp {
+        type Classifier = DnsClassifier;
+
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("dns.answers").with_comparison(self.0, self.1))
+        }
+
+        fn check(&self, packet: &DnsAnalyzer, _flow: &DnsFlow) -> bool {
+            self.0.compare(packet.answer_count(), self.1)
+        }
+    }
+
+    pub(crate) fn register(registry: ValueRegistry<Config>) -> ValueRegistry<Config> {
+        registry
+            .with("dns", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(Dns))
+            })
+            .with("dns.answered", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(DnsAnswered))
+            })
+            .with("dns.qname", |call| {
+                call.expect_args(0)?;
+                let comparison = call
+                    .comparison
+                    .as_ref()
+                    .ok_or("Expected a comparison with a name")?;
+                let name = comparison.value.as_str()?.to_string();
+                match comparison.operator {
+                    Operator::Equal => Ok(Expr::value(DnsQueryName(name))),
+                    Operator::Match => Ok(Expr::value(DnsQueryNameSuffix(name))),
+                    _ => Err("Expected the operator == or ~".to_string()),
+                }
+            })
+            .with("dns.qtype", |call| {
+                let qtype = named_number(call, QUERY_TYPES)?;
+                Ok(Expr::value(DnsQueryType(qtype)))
+            })
+            .with("dns.rcode", |call| {
+                let rcode = named_number(call, RESPONSE_CODES)?;
+                match rcode < 16 {
+                    true => Ok(Expr::value(DnsResponseCode(rcode as u8))),
+                    false => Err(format!("The response code {} does not fit in 4 bits", rcode)),
+                }
+            })
+            .with("dns.answers", |call| {
+                call.expect_args(0)?;
+                let (operator, count) = call.ordered_comparison()?;
+                Ok(Expr::value(DnsAnswerCountCmp(operator, count.as_u16()?)))
+            })
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{ClassifierId, Config};
+
+use pmc_core::base::classifier::Classifier;
+
+use std::fmt;
+use std::str::FromStr;
+
+pub struct EthernetClassifier;
+impl<'a> Classifier<'a, Config> for EthernetClassifier {
+    type Analyzer = analyzer::EthernetAnalyzer<'a>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, g)
+    }
+}
+
+/// Parses six hexadecimal bytes separated by `:` or `-`.
+impl FromStr for MacAddr {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let error = || format!("'{}' is not a valid MAC address", text);
+        let mut bytes = [0; 6];
+        let mut parts = text.split([':', '-']);
+        for byte in bytes.iter_mut() {
+            let part = parts.next().ok_or_else(error)?;
+            if part.len() != 2 {
+                return Err(error());
+            }
+            *byte = u8::from_str_radix(part, 16).map_err(|_| error())?;
+        }
+
+        match parts.next() {
+            Some(_) => Err(error()),
+            None => Ok(Self(bytes)),
+        }
+    }
+}
+
+/// Classifier of the protocol identified by an EtherType, after a link header.
+pub(crate) fn ether_type_classifier(ether_type: u16) -> ClassifierId {
+    match ether_type {
+        0x0800 | 0x86DD => ClassifierId::Ip,
+        0x8100 | 0x88A8 | 0x9100 => ClassifierId::Vlan,
+        0x8847 | 0x8848 => ClassifierId::Mpls,
+        _ => ClassifierId::None,
+    }
+}
+
+mod analyzer {
+    use super::flow::EthernetFlow;
+    use super::MacAddr;
+
+    use crate::{ClassifierId, Config};
+
+    use pmc_core::base::analyzer::{Analyzer, AnalyzerError, AnalyzerInfo, AnalyzerResult};
+    use pmc_core::packet::Packet;
+
+    pub struct EthernetAnalyzer<'a> {
+        pub header: &'a [u8],
+    }
+
+    impl<'a> EthernetAnalyzer<'a> {
+        const HEADER_LEN: usize = 14;
+
+        pub fn dest(&self) -> MacAddr {
+            MacAddr(*array_ref![self.header, 0, 6])
+        }
+
+        pub fn source(&self) -> MacAddr {
+            MacAddr(*array_ref![self.header, 6, 6])
+        }
+
+        /// Type of the payload, or the TPID of the first VLAN tag.
+        pub fn ether_type(&self) -> u16 {
+            u16::from_be_bytes(*array_ref![self.header, 12, 2])
+        }
+    }
+
+    impl<'a> Analyzer<'a, Config> for EthernetAnalyzer<'a> {
+        const ID: ClassifierId = ClassifierId::Ethernet;
+        const PREV_IDS: &'static [ClassifierId] = &[ClassifierId::Link];
+
+        type Flow = EthernetFlow;
+
+        fn build(
+            _config: &Config,
+            &Packet { data, .. }: &'a Packet,
+            _flow: &EthernetFlow,
+        ) -> AnalyzerResult<Self, ClassifierId> {
+            AnalyzerError::check_len(Self::ID, data, Self::HEADER_LEN)?;
+            let analyzer = Self {
+                header: &data[0..Self::HEADER_LEN],
+            };
+
+            Ok(AnalyzerInfo {
+                next_classifier_id: super::ether_type_classifier(analyzer.ether_type()),
+                analyzer,
+                bytes_parsed: Self::HEADER_LEN,
+            })
+        }
+    }
+}
+
+mod flow {
+    #[derive(Default)]
+    pub struct EthernetFlow {}
+}
+
+pub mod expression {
+    use super::analyzer::EthernetAnalyzer;
+    use super::flow::EthernetFlow;
+    use super::{EthernetClassifier, MacAddr};
+
+    use crate::Config;
+
+    use pmc_core::base::expression_value::ExpressionValue;
+    use pmc_core::expression::Expr;
+    use pmc_core::expression_value;
+    use pmc_core::language::{Operator, ValueCall, ValueRegistry};
+
+    #[expression_value(
+        Ethernet,
+        classifier = EthernetClassifier,
+        grant_by_flow,
+        definition = "eth"
+    )]
+    pub fn ethernet(_packet: &EthernetAnalyzer, _flow: &EthernetFlow) -> bool {
+        true
+    }
+
+    // The addresses are written as text in the definitions, which the macro does not convert.
+    #[derive(Debug)]
+    pub struct EthSource(pub MacAddr);
+    impl ExpressionValue<Config> for EthSource {
+        type Classifier = EthernetClassifier;
+
+        const SHOULD_GRANT_BY_FLOW: bool = true;
+
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("eth.source").with_comparison(Operator::Equal, self.0.to_string()))
+        }
+
+        fn check(&self, packet: &EthernetAnalyzer, _flow: &EthernetFlow) -> bool {
+            self.0 == packet.source()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct EthDest(pub MacAddr);
+    impl ExpressionValue<Config> for EthDest {
+        type Classifier = EthernetClassifier;
+
+        const SHOULD_GRANT_BY_FLOW: bool = true;
+
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("eth.dest").with_comparison(Operator::Equal, self.0.to_string()))
+        }
+
+        fn check(&self, packet: &EthernetAnalyzer, _flow: &EthernetFlow) -> bool {
+            self.0 == packet.dest()
+        }
+    }
+
+    /// EtherType of the frame. In tagged frames, it is the type of the first VLAN tag.
+    #[expression_value(
+        EtherType,
+        classifier = EthernetClassifier,
+        grant_by_flow,
+        definition = "eth.type"
+    )]
+    pub fn ether_type(ether_type: &u16, packet: &EthernetAnalyzer, _flow: &EthernetFlow) -> bool {
+        *ether_type == packet.ether_type()
+    }
+
+    fn mac_address(call: &ValueCall) -> Result<MacAddr, String> {
+        call.expect_args(0)?;
+        call.compared_by(Operator::Equal)?.as_str()?.parse()
+    }
+
+    pub(crate) fn register(registry: ValueRegistry<Config>) -> ValueRegistry<Config> {
+        registry
+            .with("eth", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(Ethernet))
+            })
+            .with("eth.source", |call| Ok(Expr::value(EthSource(mac_address(call)?))))
+            .with("eth.dest", |call| Ok(Expr::value(EthDest(mac_address(call)?))))
+            .with("eth.type", |call| {
+                call.expect_args(0)?;
+                Ok(Expr::value(EtherType(call.compared_by(Operator::Equal)?.as_u16()?)))
+            })
+    }
+}
@@ -17,7 +17,9 @@ mod analyzer {
 
     use crate::{ClassifierId, Config, FlowKind, FlowSignature};
 
-    use pmc_core::base::analyzer::{Analyzer, AnalyzerInfo, AnalyzerResult, UseFlow};
+    use pmc_core::base::analyzer::{
+        Analyzer, AnalyzerError, AnalyzerErrorKind, AnalyzerInfo, AnalyzerResult, UseFlow,
+    };
     use pmc_core::packet::{Direction, Packet};
 
     use std::convert::TryFrom;
@@ -35,6 +37,22 @@ mod analyzer {
         Patch,
     }
 
+    impl Method {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                Self::Get => "GET",
+                Self::Head => "HEAD",
+                Self::Post => "POST",
+                Self::Put => "PUT",
+                Self::Delete => "DELETE",
+                Self::Connect => "CONNECT",
+                Self::Options => "OPTIONS",
+                Self::Trace => "TRACE",
+                Self::Patch => "PATCH",
+            }
+        }
+    }
+
     impl TryFrom<&str> for Method {
         type Error = ();
         fn try_from(value: &str) -> Result<Self, ()> {
@@ -53,6 +71,12 @@ mod analyzer {
         }
     }
 
+    /// Position of the first occurrence of `pattern` in `data`.
+    fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+        data.windows(pattern.len())
+            .position(|window| window == pattern)
+    }
+
     enum StartLine<'a> {
         Request {
             version: &'a str,
@@ -72,8 +96,6 @@ mod analyzer {
     }
 
     impl<'a> HttpStartLineAnalyzer<'a> {
-        const START_LINE_MALFORMED: &'static str = "Http start line malformed";
-
         pub fn method(&self) -> Option<Method> {
             match self.start_line {
                 StartLine::Request { method, .. } => Method::try_from(method).ok(),
@@ -121,31 +143,33 @@ mod analyzer {
 
     impl<'a> Analyzer<'a, Config> for HttpStartLineAnalyzer<'a> {
         const ID: ClassifierId = ClassifierId::HttpStartLine;
-        const PREV_ID: ClassifierId = ClassifierId::Tcp;
+        const PREV_IDS: &'static [ClassifierId] = &[ClassifierId::Tcp];
 
         type Flow = HttpFlow;
 
-        fn update_flow_id(signature: &mut FlowSignature, _packet: &Packet) -> UseFlow {
+        fn update_flow_id(
+            signature: &mut FlowSignature,
+            _packet: &Packet,
+        ) -> UseFlow<ClassifierId> {
             signature.kind = FlowKind::Http;
             UseFlow::Yes
         }
 
         fn build(
             _config: &Config,
-            &Packet { data, direction }: &'a Packet,
+            &Packet {
+                data, direction, ..
+            }: &'a Packet,
             flow: &HttpFlow,
         ) -> AnalyzerResult<Self, ClassifierId> {
-            let header = unsafe {
-                //SAFETY: We only check againts first 128 ascii values
-                std::str::from_utf8_unchecked(data)
-            };
+            let parse_line: Option<(StartLine, usize)> = (|| {
+                let line_len = find(data, b"\r\n")?;
+                let line = std::str::from_utf8(&data[..line_len]).ok()?;
 
-            let parse_line: Option<(StartLine, &str)> = (|| {
-                let mut iter = header.splitn(3, ' ');
+                let mut iter = line.splitn(3, ' ');
                 let first = iter.next()?;
                 let second = iter.next()?;
-                let third_and_more = iter.next()?;
-                let (third, next_data) = third_and_more.split_once("\r\n")?;
+                let third = iter.next()?;
 
                 let start_line = match direction {
                     Direction::Uplink => StartLine::Request {
@@ -160,23 +184,23 @@ mod analyzer {
                     },
                 };
 
-                Some((start_line, next_data))
+                Some((start_line, line_len + 2)) // because of "\r\n"
             })();
 
-            let (start_line, next_data) = match parse_line {
-                Some((start_line, next_data)) => (start_line, next_data),
+            let (start_line, bytes_parsed) = match parse_line {
+                Some((start_line, line_len)) => (start_line, line_len),
                 None => {
                     if let State::Unknown = flow.state {
-                        return Err(Self::START_LINE_MALFORMED);
+                        return Err(AnalyzerError::new(Self::ID, AnalyzerErrorKind::Malformed, 0));
                     }
-                    (StartLine::Unknown, &header[header.len() - 1..])
+                    (StartLine::Unknown, data.len().saturating_sub(1))
                 }
             };
 
             Ok(AnalyzerInfo {
                 analyzer: Self { start_line },
                 next_classifier_id: ClassifierId::HttpHeader,
-                bytes_parsed: next_data.as_ptr() as usize - data.as_ptr() as usize,
+                bytes_parsed,
             })
         }
 
@@ -215,11 +239,14 @@ mod analyzer {
 
     impl<'a> Analyzer<'a, Config> for HttpHeaderAnalyzer<'a> {
         const ID: ClassifierId = ClassifierId::HttpHeader;
-        const PREV_ID: ClassifierId = ClassifierId::HttpStartLine;
+        const PREV_IDS: &'static [ClassifierId] = &[ClassifierId::HttpStartLine];
 
         type Flow = HttpFlow;
 
-        fn update_flow_id(_signature: &mut FlowSignature, _packet: &Packet) -> UseFlow {
+        fn update_flow_id(
+            _signature: &mut FlowSignature,
+            _packet: &Packet,
+        ) -> UseFlow<ClassifierId> {
             UseFlow::Yes
         }
 
@@ -228,16 +255,14 @@ mod analyzer {
             &Packet { data, .. }: &'a Packet,
             _flow: &HttpFlow,
         ) -> AnalyzerResult<Self, ClassifierId> {
-            let headers = unsafe {
-                //SAFETY: We only check againts first 128 ascii values
-                std::str::from_utf8_unchecked(data)
-            };
-
-            let header_len = headers
-                .find("\r\n\r\n")
-                .ok_or("Malformed HTTP header in headers section")?
+            let header_len = find(data, b"\r\n\r\n")
+                .ok_or_else(|| AnalyzerError::new(Self::ID, AnalyzerErrorKind::Malformed, 0))?
                 + 4; //because of "\r\n\r\n"
 
+            let headers = std::str::from_utf8(&data[..header_len]).map_err(|error| {
+                AnalyzerError::new(Self::ID, AnalyzerErrorKind::Malformed, error.valid_up_to())
+            })?;
+
             Ok(AnalyzerInfo {
                 analyzer: Self { headers },
                 next_classifier_id: ClassifierId::None,
@@ -277,90 +302,161 @@ pub mod expression {
     use crate::Config;
 
     use pmc_core::base::expression_value::ExpressionValue;
+    use pmc_core::expression::Expr;
+    use pmc_core::expression_value;
+    use pmc_core::language::{Operator, ValueCall, ValueRegistry};
 
-    #[derive(Debug)]
-    pub struct Http;
-    impl ExpressionValue<Config> for Http {
-        type Classifier = super::HttpStartLineClassifier;
-
-        const SHOULD_GRANT_BY_FLOW: bool = true;
-
-        fn check(&self, _packet: &HttpStartLineAnalyzer, _flow: &HttpFlow) -> bool {
-            true
-        }
+    use std::convert::TryFrom;
+    use std::fmt;
+
+    #[expression_value(
+        Http,
+        classifier = super::HttpStartLineClassifier,
+        grant_by_flow,
+        definition = "http"
+    )]
+    pub fn http(_packet: &HttpStartLineAnalyzer, _flow: &HttpFlow) -> bool {
+        true
     }
 
-    #[derive(Debug)]
-    pub struct HttpRequest;
-    impl ExpressionValue<Config> for HttpRequest {
-        type Classifier = super::HttpStartLineClassifier;
-
-        const SHOULD_GRANT_BY_FLOW: bool = true;
-
-        fn should_break_grant(&self, packet: &HttpStartLineAnalyzer) -> bool {
-            packet.is_response() || packet.is_request()
-        }
-
-        fn check(&self, _packet: &HttpStartLineAnalyzer, flow: &HttpFlow) -> bool {
-            flow.state == State::Request
-        }
+    /// A new start line can change the state of the flow.
+    fn has_start_line(packet: &HttpStartLineAnalyzer) -> bool {
+        packet.is_request() || packet.is_response()
     }
 
-    #[derive(Debug)]
-    pub struct HttpResponse;
-    impl ExpressionValue<Config> for HttpResponse {
-        type Classifier = super::HttpStartLineClassifier;
-
-        const SHOULD_GRANT_BY_FLOW: bool = true;
-
-        fn should_break_grant(&self, packet: &HttpStartLineAnalyzer) -> bool {
-            packet.is_request() || packet.is_response()
-        }
+    #[expression_value(
+        HttpRequest,
+        classifier = super::HttpStartLineClassifier,
+        grant_by_flow,
+        break_grant = has_start_line,
+        definition = "http.request"
+    )]
+    pub fn http_request(_packet: &HttpStartLineAnalyzer, flow: &HttpFlow) -> bool {
+        flow.state == State::Request
+    }
 
-        fn check(&self, _packet: &HttpStartLineAnalyzer, flow: &HttpFlow) -> bool {
-            flow.state == State::Response
-        }
+    #[expression_value(
+        HttpResponse,
+        classifier = super::HttpStartLineClassifier,
+        grant_by_flow,
+        break_grant = has_start_line,
+        definition = "http.response"
+    )]
+    pub fn http_response(_packet: &HttpStartLineAnalyzer, flow: &HttpFlow) -> bool {
+        flow.state == State::Response
     }
 
+    // The method enum is the value itself, it is not built from a predicate.
     pub use super::analyzer::Method as HttpMethod;
     impl ExpressionValue<Config> for HttpMethod {
         type Classifier = super::HttpStartLineClassifier;
 
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("http.method").with_comparison(Operator::Equal, self.as_str()))
+        }
+
         fn check(&self, packet: &HttpStartLineAnalyzer, _flow: &HttpFlow) -> bool {
             Some(*self) == packet.method()
         }
     }
 
+    // The text values are written by hand, the macro does not take generics.
     #[derive(Debug)]
-    pub struct HttpCode(pub &'static str);
-    impl ExpressionValue<Config> for HttpCode {
+    pub struct HttpCode<S = &'static str>(pub S);
+    impl<S> ExpressionValue<Config> for HttpCode<S>
+    where S: AsRef<str> + fmt::Debug + Send + Sync + 'static
+    {
         type Classifier = super::HttpStartLineClassifier;
 
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("http.code").with_comparison(Operator::Equal, self.0.as_ref()))
+        }
+
         fn check(&self, packet: &HttpStartLineAnalyzer, _flow: &HttpFlow) -> bool {
-            Some(self.0) == packet.code()
+            Some(self.0.as_ref()) == packet.code()
         }
     }
 
     #[derive(Debug)]
-    pub struct HttpHeaderName(pub &'static str);
-    impl ExpressionValue<Config> for HttpHeaderName {
+    pub struct HttpHeaderName<S = &'static str>(pub S);
+    impl<S> ExpressionValue<Config> for HttpHeaderName<S>
+    where S: AsRef<str> + fmt::Debug + Send + Sync + 'static
+    {
         type Classifier = super::HttpHeaderClassifier;
 
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("http.header").with_arg(self.0.as_ref()))
+        }
+
         fn check(&self, packet: &HttpHeaderAnalyzer, _flow: &HttpFlow) -> bool {
-            packet.find_header(self.0).is_some()
+            packet.find_header(self.0.as_ref()).is_some()
         }
     }
 
+    /// The header value contains the expected text.
     #[derive(Debug)]
-    pub struct HttpHeader(pub &'static str, pub &'static str);
-    impl ExpressionValue<Config> for HttpHeader {
+    pub struct HttpHeader<S = &'static str>(pub S, pub S);
+    impl<S> ExpressionValue<Config> for HttpHeader<S>
+    where S: AsRef<str> + fmt::Debug + Send + Sync + 'static
+    {
         type Classifier = super::HttpHeaderClassifier;
 
+        fn definition(&self) -> Option<ValueCall> {
+            Some(
+                ValueCall::new("http.header")
+                    .with_arg(self.0.as_ref())
+                    .with_comparison(Operator::Match, self.1.as_ref()),
+            )
+        }
+
         fn check(&self, packet: &HttpHeaderAnalyzer, _flow: &HttpFlow) -> bool {
-            match packet.find_header(self.0) {
-                Some(value) => value.contains(self.1),
+            match packet.find_header(self.0.as_ref()) {
+                Some(value) => value.contains(self.1.as_ref()),
                 None => false,
             }
         }
     }
+
+    pub(crate) fn register(registry: ValueRegistry<Config>) -> ValueRegistry<Config> {
+        registry
+            .with("http", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(Http))
+            })
+            .with("http.request", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(HttpRequest))
+            })
+            .with("http.response", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(HttpResponse))
+            })
+            .with("http.method", |call| {
+                call.expect_args(0)?;
+                let method = call.compared_by(Operator::Equal)?.as_text()?;
+                HttpMethod::try_from(method)
+                    .map(Expr::value)
+                    .map_err(|_| format!("Unknown HTTP method '{}'", method))
+            })
+            .with("http.code", |call| {
+                call.expect_args(0)?;
+                let code = call.compared_by(Operator::Equal)?;
+                let code = match code.as_int() {
+                    Ok(code) => code.to_string(),
+                    Err(_) => code.as_str()?.to_string(),
+                };
+                Ok(Expr::value(HttpCode(code)))
+            })
+            .with("http.header", |call| {
+                call.expect_args(1)?;
+                let name = call.arg(0)?.as_str()?.to_string();
+                match call.comparison {
+                    None => Ok(Expr::value(HttpHeaderName(name))),
+                    Some(_) => {
+                        let value = call.compared_by(Operator::Match)?.as_str()?.to_string();
+                        Ok(Expr::value(HttpHeader(name, value)))
+                    }
+                }
+            })
+    }
 }
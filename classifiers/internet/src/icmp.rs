@@ -0,0 +1,206 @@
+use crate::Config;
+
+use pmc_core::base::classifier::Classifier;
+
+/// ICMP and ICMPv6 messages.
+/// The datagram quoted by an error message is analyzed up to the flow it belongs to.
+pub struct IcmpClassifier;
+impl<'a> Classifier<'a, Config> for IcmpClassifier {
+    type Analyzer = analyzer::IcmpAnalyzer<'a>;
+}
+
+mod analyzer {
+    use super::flow::IcmpFlow;
+
+    use crate::ip::{IpAnalyzer, IpHeader, IpVersion};
+    use crate::{ClassifierId, Config, FlowSignature};
+
+    use pmc_core::base::analyzer::{
+        Analyzer, AnalyzerError, AnalyzerErrorKind, AnalyzerInfo, AnalyzerResult, UseFlow,
+    };
+    use pmc_core::packet::Packet;
+
+    pub struct IcmpAnalyzer<'a> {
+        pub header: &'a [u8],
+        /// The message is an error that quotes a datagram.
+        error: bool,
+        /// The quoted datagram continues with the analyzer of its protocol.
+        quotes_flow: bool,
+    }
+
+    impl<'a> IcmpAnalyzer<'a> {
+        const HEADER_LEN: usize = 8;
+
+        pub fn icmp_type(&self) -> u8 {
+            self.header[0]
+        }
+
+        pub fn code(&self) -> u8 {
+            self.header[1]
+        }
+
+        pub fn is_error(&self) -> bool {
+            self.error
+        }
+
+        /// Header of the datagram quoted by an error message.
+        /// The error types are the ones of ICMP or ICMPv6, from the IP protocol of the message,
+        /// and the quote has the same IP version as the message.
+        fn check_quoted_header(
+            &Packet { data, protocol, .. }: &Packet,
+        ) -> Result<Option<IpHeader>, AnalyzerError<ClassifierId>> {
+            AnalyzerError::check_len(Self::ID, data, Self::HEADER_LEN)?;
+            let version = match protocol {
+                Some(1) if matches!(data[0], 3 | 4 | 5 | 11 | 12) => IpVersion::V4,
+                Some(58) if matches!(data[0], 1..=4) => IpVersion::V6,
+                _ => return Ok(None),
+            };
+
+            let quote = &data[Self::HEADER_LEN..];
+            let header = IpAnalyzer::check_header(quote).map_err(|error| {
+                AnalyzerError::new(Self::ID, error.kind, Self::HEADER_LEN + error.offset)
+            })?;
+            match header.version == version {
+                true => Ok(Some(header)),
+                false => Err(AnalyzerError::new(
+                    Self::ID,
+                    AnalyzerErrorKind::Malformed,
+                    Self::HEADER_LEN,
+                )),
+            }
+        }
+
+        /// Classifier of the quoted transport header, if it is long enough to find its flow.
+        fn quoted_classifier(header: &IpHeader, quote: &[u8]) -> ClassifierId {
+            if header.fragment.is_some_and(|fragment| fragment.offset > 0) {
+                return ClassifierId::None;
+            }
+
+            let transport = &quote[header.len..];
+            match header.protocol {
+                6 if transport.len() >= 20
+                    && transport.len() >= ((transport[12] >> 4) as usize) << 2 =>
+                {
+                    ClassifierId::Tcp
+                }
+                17 if transport.len() >= 8 => ClassifierId::Udp,
+                _ => ClassifierId::None,
+            }
+        }
+    }
+
+    impl<'a> Analyzer<'a, Config> for IcmpAnalyzer<'a> {
+        const ID: ClassifierId = ClassifierId::Icmp;
+        const PREV_IDS: &'static [ClassifierId] = &[ClassifierId::Ip];
+
+        type Flow = IcmpFlow;
+
+        /// The addresses of a quoted datagram replace the ones of the message,
+        /// so its transport analyzer finds the flow of the quote.
+        fn update_flow_id(signature: &mut FlowSignature, packet: &Packet) -> UseFlow<ClassifierId> {
+            let header = match Self::check_quoted_header(packet) {
+                Ok(Some(header)) => header,
+                Ok(None) => return UseFlow::No,
+                Err(error) => return UseFlow::Abort(error),
+            };
+
+            // The quote was sent by the receiver of the error.
+            let quote = &packet.data[Self::HEADER_LEN..];
+            let (first, second) = header.flow_addresses(quote, packet.direction.reverse());
+            signature.source_ip = first;
+            signature.dest_ip = second;
+
+            UseFlow::No
+        }
+
+        fn build(
+            _config: &Config,
+            packet @ &Packet { data, .. }: &'a Packet,
+            _flow: &IcmpFlow,
+        ) -> AnalyzerResult<Self, ClassifierId> {
+            let quoted_header = Self::check_quoted_header(packet)?;
+            let (next_classifier_id, bytes_parsed) = match &quoted_header {
+                Some(header) => {
+                    let quote = &data[Self::HEADER_LEN..];
+                    match Self::quoted_classifier(header, quote) {
+                        ClassifierId::None => (ClassifierId::None, Self::HEADER_LEN),
+                        next_id => (next_id, Self::HEADER_LEN + header.len),
+                    }
+                }
+                None => (ClassifierId::None, Self::HEADER_LEN),
+            };
+
+            Ok(AnalyzerInfo {
+                analyzer: Self {
+                    header: &data[..Self::HEADER_LEN],
+                    error: quoted_header.is_some(),
+                    quotes_flow: next_classifier_id != ClassifierId::None,
+                },
+                next_classifier_id,
+                bytes_parsed,
+            })
+        }
+
+        fn quotes_packet(&self) -> bool {
+            self.quotes_flow
+        }
+    }
+}
+
+mod flow {
+    #[derive(Default)]
+    pub struct IcmpFlow {}
+}
+
+pub mod expression {
+    use super::analyzer::IcmpAnalyzer;
+    use super::flow::IcmpFlow;
+    use super::IcmpClassifier;
+
+    use crate::Config;
+
+    use pmc_core::expression::Expr;
+    use pmc_core::expression_value;
+    use pmc_core::language::{Operator, ValueCall, ValueRegistry};
+
+    #[expression_value(Icmp, classifier = IcmpClassifier, definition = "icmp")]
+    pub fn icmp(_packet: &IcmpAnalyzer, _flow: &IcmpFlow) -> bool {
+        true
+    }
+
+    /// Error messages, whose quoted datagram is attributed to its flow.
+    #[expression_value(IcmpError, classifier = IcmpClassifier, definition = "icmp.error")]
+    pub fn icmp_error(packet: &IcmpAnalyzer, _flow: &IcmpFlow) -> bool {
+        packet.is_error()
+    }
+
+    /// Type of the message. The ICMP and ICMPv6 types have different values.
+    #[expression_value(IcmpType, classifier = IcmpClassifier, definition = "icmp.type")]
+    pub fn icmp_type(icmp_type: &u8, packet: &IcmpAnalyzer, _flow: &IcmpFlow) -> bool {
+        *icmp_type == packet.icmp_type()
+    }
+
+    #[expression_value(IcmpCode, classifier = IcmpClassifier, definition = "icmp.code")]
+    pub fn icmp_code(code: &u8, packet: &IcmpAnalyzer, _flow: &IcmpFlow) -> bool {
+        *code == packet.code()
+    }
+
+    fn number(call: &ValueCall) -> Result<u8, String> {
+        call.expect_args(0)?;
+        call.compared_by(Operator::Equal)?.as_u8()
+    }
+
+    pub(crate) fn register(registry: ValueRegistry<Config>) -> ValueRegistry<Config> {
+        registry
+            .with("icmp", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(Icmp))
+            })
+            .with("icmp.error", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(IcmpError))
+            })
+            .with("icmp.type", |call| Ok(Expr::value(IcmpType(number(call)?))))
+            .with("icmp.code", |call| Ok(Expr::value(IcmpCode(number(call)?))))
+    }
+}
@@ -2,28 +2,128 @@ use crate::Config;
 
 use pmc_core::base::classifier::Classifier;
 
+pub(crate) use analyzer::{Header as IpHeader, IpAnalyzer, Version as IpVersion};
+
 pub struct IpClassifier;
 impl<'a> Classifier<'a, Config> for IpClassifier {
     type Analyzer = analyzer::IpAnalyzer<'a>;
 }
 
 mod analyzer {
-    use crate::{ClassifierId, Config, FlowSignature};
+    use super::flow::IpFlow;
+
+    use crate::{ClassifierId, Config, FlowKind, FlowSignature};
 
-    use pmc_core::base::analyzer::{Analyzer, AnalyzerInfo, AnalyzerResult, UseFlow};
+    use pmc_core::base::analyzer::{
+        Analyzer, AnalyzerError, AnalyzerErrorKind, AnalyzerInfo, AnalyzerResult, UseFlow,
+    };
     use pmc_core::packet::{Direction, Packet};
 
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
-    #[derive(Debug, Clone, Copy)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
     pub enum Version {
         V4,
         V6,
     }
 
+    /// Position of a fragment in its datagram.
+    #[derive(Debug, Clone, Copy)]
+    pub struct Fragment {
+        pub id: u32,
+        /// Offset of the fragment data in the datagram payload, in bytes.
+        pub offset: usize,
+        pub more_fragments: bool,
+    }
+
+    /// Bounds of the header, checked before parsing it.
+    pub(crate) struct Header {
+        pub version: Version,
+        pub len: usize,
+        pub protocol: u8,
+        pub fragment: Option<Fragment>,
+        extension_headers: u8,
+        /// Payload length declared in the header, if it is not 0.
+        payload_len: Option<usize>,
+    }
+
+    impl Header {
+        /// Addresses of the header in the order of the flow signature.
+        pub fn flow_addresses(&self, data: &[u8], direction: Direction) -> (Ipv6Addr, Ipv6Addr) {
+            let (source, dest) = match self.version {
+                Version::V4 => (
+                    Ipv4Addr::from(*array_ref![data, 12, 4]).to_ipv6_mapped(),
+                    Ipv4Addr::from(*array_ref![data, 16, 4]).to_ipv6_mapped(),
+                ),
+                Version::V6 => (
+                    Ipv6Addr::from(*array_ref![data, 8, 16]),
+                    Ipv6Addr::from(*array_ref![data, 24, 16]),
+                ),
+            };
+
+            match direction {
+                Direction::Uplink => (source, dest),
+                Direction::Downlink => (dest, source),
+            }
+        }
+    }
+
+    /// IPv6 extension headers that can precede the upper-layer protocol.
+    /// As expression value, it matches the packets with the header.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ExtensionHeader {
+        HopByHop,
+        Routing,
+        Fragment,
+        DestinationOptions,
+        Authentication,
+        Mobility,
+        HostIdentity,
+        Shim6,
+    }
+
+    impl ExtensionHeader {
+        fn from_code(code: u8) -> Option<Self> {
+            match code {
+                0 => Some(Self::HopByHop),
+                43 => Some(Self::Routing),
+                44 => Some(Self::Fragment),
+                60 => Some(Self::DestinationOptions),
+                51 => Some(Self::Authentication),
+                135 => Some(Self::Mobility),
+                139 => Some(Self::HostIdentity),
+                140 => Some(Self::Shim6),
+                _ => None,
+            }
+        }
+
+        /// Length of the header at the start of `data`, of at least 8 bytes.
+        fn len(self, data: &[u8]) -> usize {
+            match self {
+                Self::Fragment => 8,
+                Self::Authentication => (data[1] as usize + 2) << 2,
+                _ => (data[1] as usize + 1) << 3,
+            }
+        }
+
+        fn flag(self) -> u8 {
+            1 << self as u8
+        }
+    }
+
     pub struct IpAnalyzer<'a> {
         pub version: Version,
         pub header: &'a [u8],
+        pub fragment: Option<Fragment>,
+        protocol: u8,
+        /// Flags of the IPv6 extension headers found before the protocol.
+        extension_headers: u8,
+        /// Captured payload, without the link padding.
+        payload: &'a [u8],
+        /// The payload was cut by the capture.
+        truncated: bool,
+        /// Length of the payload in the wire, without the link padding.
+        payload_len: usize,
     }
 
     impl<'a> IpAnalyzer<'a> {
@@ -41,108 +141,383 @@ mod analyzer {
             }
         }
 
+        /// Protocol of the payload, after the IPv6 extension headers.
+        /// In fragments, it is the protocol that follows the fragment header.
         pub fn protocol_code(&self) -> u8 {
-            match self.version {
-                Version::V4 => self.header[9],
-                Version::V6 => self.header[6],
+            self.protocol
+        }
+
+        pub fn has_extension_header(&self, extension_header: ExtensionHeader) -> bool {
+            self.extension_headers & extension_header.flag() != 0
+        }
+
+        /// Checks the header bounds, returning its fields needed to parse the packet.
+        pub(crate) fn check_header(data: &[u8]) -> Result<Header, AnalyzerError<ClassifierId>> {
+            AnalyzerError::check_len(Self::ID, data, 1)?;
+            match (data[0] & 0xF0) >> 4 {
+                4 => {
+                    AnalyzerError::check_len(Self::ID, data, 20)?;
+                    let len = ((data[0] & 0x0F) as usize) << 2;
+                    if len < 20 {
+                        return Err(AnalyzerError::new(Self::ID, AnalyzerErrorKind::Malformed, 0));
+                    }
+                    AnalyzerError::check_len(Self::ID, data, len)?;
+
+                    // The total length can be 0 in packets captured before segmentation offload.
+                    let total_len = u16::from_be_bytes(*array_ref![data, 2, 2]) as usize;
+                    let payload_len = match total_len {
+                        0 => None,
+                        _ => Some(total_len.saturating_sub(len)),
+                    };
+
+                    let flags_offset = u16::from_be_bytes(*array_ref![data, 6, 2]);
+                    let more_fragments = flags_offset & 0x2000 != 0;
+                    let offset = ((flags_offset & 0x1FFF) as usize) << 3;
+                    let fragment = match more_fragments || offset > 0 {
+                        true => Some(Fragment {
+                            id: u16::from_be_bytes(*array_ref![data, 4, 2]) as u32,
+                            offset,
+                            more_fragments,
+                        }),
+                        false => None,
+                    };
+
+                    Ok(Header {
+                        version: Version::V4,
+                        len,
+                        protocol: data[9],
+                        fragment,
+                        extension_headers: 0,
+                        payload_len,
+                    })
+                }
+                6 => {
+                    AnalyzerError::check_len(Self::ID, data, 40)?;
+                    let declared_len = u16::from_be_bytes(*array_ref![data, 4, 2]) as usize;
+                    let mut len = 40;
+                    let mut protocol = data[6];
+                    let mut extension_headers = 0;
+                    let mut fragment = None;
+
+                    // The headers after a fragment header are part of the fragmented data.
+                    while fragment.is_none() {
+                        let extension_header = match ExtensionHeader::from_code(protocol) {
+                            Some(extension_header) => extension_header,
+                            None => break,
+                        };
+
+                        AnalyzerError::check_len(Self::ID, data, len + 8)?;
+                        let header = &data[len..];
+                        let header_len = extension_header.len(header);
+                        AnalyzerError::check_len(Self::ID, data, len + header_len)?;
+
+                        if extension_header == ExtensionHeader::Fragment {
+                            let offset_flags = u16::from_be_bytes(*array_ref![header, 2, 2]);
+                            fragment = Some(Fragment {
+                                id: u32::from_be_bytes(*array_ref![header, 4, 4]),
+                                offset: (offset_flags & 0xFFF8) as usize,
+                                more_fragments: offset_flags & 0x0001 != 0,
+                            });
+                        }
+
+                        extension_headers |= extension_header.flag();
+                        protocol = header[0];
+                        len += header_len;
+                    }
+
+                    Ok(Header {
+                        version: Version::V6,
+                        len,
+                        protocol,
+                        fragment,
+                        extension_headers,
+                        // The payload length is 0 in jumbograms, like in packets before offload.
+                        payload_len: match declared_len {
+                            0 => None,
+                            _ => Some(declared_len.saturating_sub(len - 40)),
+                        },
+                    })
+                }
+                _ => Err(AnalyzerError::new(Self::ID, AnalyzerErrorKind::UnsupportedVersion, 0)),
             }
         }
     }
 
     impl<'a> Analyzer<'a, Config> for IpAnalyzer<'a> {
         const ID: ClassifierId = ClassifierId::Ip;
-        const PREV_ID: ClassifierId = ClassifierId::None;
-
-        type Flow = ();
-
-        fn update_flow_id(
-            signature: &mut FlowSignature,
-            &Packet { data, direction }: &Packet,
-        ) -> UseFlow {
-            let ip_version = (data[0] & 0xF0) >> 4;
-            let (source, dest) = match ip_version {
-                4 => (
-                    Ipv4Addr::from(*array_ref![data, 12, 4]).to_ipv6_mapped(),
-                    Ipv4Addr::from(*array_ref![data, 16, 4]).to_ipv6_mapped(),
-                ),
-                6 => (
-                    Ipv6Addr::from(*array_ref![data, 8, 16]),
-                    Ipv6Addr::from(*array_ref![data, 24, 16]),
-                ),
-                _ => return UseFlow::Abort("Ip version not valid"),
-            };
-
-            let (first, second) = match direction {
-                Direction::Uplink => (source, dest),
-                Direction::Downlink => (dest, source),
+        const PREV_IDS: &'static [ClassifierId] = &[
+            ClassifierId::Link,
+            ClassifierId::Ethernet,
+            ClassifierId::Vlan,
+            ClassifierId::Mpls,
+        ];
+
+        type Flow = IpFlow;
+
+        /// Only fragments use a flow, the one of their datagram.
+        fn update_flow_id(signature: &mut FlowSignature, packet: &Packet) -> UseFlow<ClassifierId> {
+            let header = match Self::check_header(packet.data) {
+                Ok(header) => header,
+                Err(error) => return UseFlow::Abort(error),
             };
 
+            let (first, second) = header.flow_addresses(packet.data, packet.direction);
             signature.source_ip = first;
             signature.dest_ip = second;
 
-            UseFlow::No
+            match header.fragment {
+                Some(fragment) => {
+                    signature.fragment = Some((fragment.id, header.protocol));
+                    signature.kind = FlowKind::IpFragment;
+                    UseFlow::Yes
+                }
+                None => UseFlow::No,
+            }
         }
 
         fn build(
-            _config: &Config,
-            &Packet { data, .. }: &'a Packet,
-            _: &(),
+            config: &Config,
+            packet: &'a Packet,
+            flow: &IpFlow,
         ) -> AnalyzerResult<Self, ClassifierId> {
-            let ip_version = (data[0] & 0xF0) >> 4;
-
-            let (version, protocol, header_len) = match ip_version {
-                4 => (Version::V4, data[9], ((data[0] & 0x0F) as usize) << 2),
-                6 => (Version::V6, data[6], 40),
-                _ => return Err("Ip version not valid"),
+            let header = Self::check_header(packet.data)?;
+            let data = packet.data;
+            let payload = &data[header.len..];
+            // Without declared length, the payload ends with the packet.
+            let payload_len = header
+                .payload_len
+                .unwrap_or_else(|| packet.wire_len.saturating_sub(header.len));
+            let truncated = payload.len() < payload_len;
+            let payload = &payload[..payload.len().min(payload_len)];
+
+            // A fragment is only analyzed further if it completes its datagram.
+            let complete = match header.fragment {
+                Some(fragment) => {
+                    !truncated
+                        && flow.completes(
+                            fragment.offset,
+                            payload.len(),
+                            fragment.more_fragments,
+                            config.ip_reassembly_max_len,
+                        )
+                }
+                None => true,
             };
 
-            let next_classifier_id = match protocol {
-                6 => ClassifierId::Tcp,
-                17 => ClassifierId::Udp,
+            let next_classifier_id = match (header.version, header.protocol) {
+                (_, 6) if complete => ClassifierId::Tcp,
+                (_, 17) if complete => ClassifierId::Udp,
+                (Version::V4, 1) | (Version::V6, 58) if complete => ClassifierId::Icmp,
                 _ => ClassifierId::None,
             };
 
             Ok(AnalyzerInfo {
                 analyzer: Self {
-                    version,
-                    header: &data[0..header_len],
+                    version: header.version,
+                    header: &data[0..header.len],
+                    fragment: header.fragment,
+                    protocol: header.protocol,
+                    extension_headers: header.extension_headers,
+                    payload,
+                    truncated,
+                    payload_len,
                 },
                 next_classifier_id,
-                bytes_parsed: header_len,
+                bytes_parsed: header.len,
             })
         }
+
+        fn update_flow(&self, config: &Config, flow: &mut IpFlow, _direction: Direction) {
+            if let Some(fragment) = self.fragment {
+                match self.truncated {
+                    // The missing bytes can not be reassembled.
+                    true => flow.discard(),
+                    false => flow.insert(
+                        fragment.offset,
+                        self.payload,
+                        fragment.more_fragments,
+                        config.ip_reassembly_max_len,
+                    ),
+                }
+            }
+        }
+
+        fn is_flow_closed(flow: &IpFlow) -> bool {
+            flow.is_reassembled()
+        }
+
+        fn flow_heap_memory(flow: &IpFlow) -> usize {
+            flow.heap_memory()
+        }
+
+        fn take_reassembled(flow: &mut IpFlow) -> Option<Vec<u8>> {
+            flow.take_datagram()
+        }
+
+        fn declared_len(&self) -> Option<usize> {
+            Some(self.payload_len)
+        }
+
+        fn next_protocol(&self) -> Option<u32> {
+            Some(self.protocol as u32)
+        }
+    }
+}
+
+mod flow {
+    /// Fragments of a datagram, stored in place.
+    #[derive(Default)]
+    pub struct IpFlow {
+        payload: Vec<u8>,
+        /// Received ranges of `payload`, sorted and without overlaps.
+        received: Vec<(usize, usize)>,
+        /// Payload length, known once the last fragment is received.
+        total_len: Option<usize>,
+        state: State,
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, Default)]
+    enum State {
+        #[default]
+        Reassembling,
+        /// The datagram is complete and pending to be taken.
+        Complete,
+        /// The datagram was taken. Later fragments are duplicates.
+        Reassembled,
+        Discarded,
+    }
+
+    impl IpFlow {
+        /// Whether the fragment completes the datagram.
+        pub fn completes(&self, offset: usize, len: usize, more: bool, max_len: usize) -> bool {
+            if self.state != State::Reassembling || offset + len > max_len {
+                return false;
+            }
+
+            let mut received = self.received.clone();
+            add_range(&mut received, offset, offset + len);
+            let total_len = if more { self.total_len } else { Some(offset + len) };
+            matches!(total_len, Some(total_len) if received == [(0, total_len)])
+        }
+
+        /// Adds the fragment data at its offset.
+        /// Overlapping bytes take the value of the last fragment.
+        pub fn insert(&mut self, offset: usize, data: &[u8], more: bool, max_len: usize) {
+            if self.state != State::Reassembling {
+                return;
+            }
+
+            let end = offset + data.len();
+            if end > max_len || self.total_len.is_some_and(|total_len| end > total_len) {
+                self.discard();
+                return;
+            }
+
+            if !more {
+                if self
+                    .received
+                    .last()
+                    .is_some_and(|&(_, last_end)| last_end > end)
+                {
+                    self.discard();
+                    return;
+                }
+                self.total_len = Some(end);
+            }
+
+            if self.payload.len() < end {
+                self.payload.resize(end, 0);
+            }
+            self.payload[offset..end].copy_from_slice(data);
+            add_range(&mut self.received, offset, end);
+
+            if matches!(self.total_len, Some(total_len) if self.received == [(0, total_len)]) {
+                self.state = State::Complete;
+            }
+        }
+
+        pub fn discard(&mut self) {
+            self.state = State::Discarded;
+            self.payload = Vec::new();
+            self.received = Vec::new();
+        }
+
+        pub fn take_datagram(&mut self) -> Option<Vec<u8>> {
+            match self.state {
+                State::Complete => {
+                    self.state = State::Reassembled;
+                    self.received = Vec::new();
+                    Some(std::mem::take(&mut self.payload))
+                }
+                _ => None,
+            }
+        }
+
+        pub fn is_reassembled(&self) -> bool {
+            self.state == State::Reassembled
+        }
+
+        /// Bytes allocated for the fragments received.
+        pub fn heap_memory(&self) -> usize {
+            self.payload.capacity()
+                + self.received.capacity() * std::mem::size_of::<(usize, usize)>()
+        }
+    }
+
+    /// Adds the range to the sorted ranges, merging the overlapping and contiguous ones.
+    fn add_range(ranges: &mut Vec<(usize, usize)>, start: usize, end: usize) {
+        let (mut start, mut end) = (start, end);
+        ranges.retain(|&(range_start, range_end)| {
+            let merge = range_start <= end && start <= range_end;
+            if merge {
+                start = start.min(range_start);
+                end = end.max(range_end);
+            }
+            !merge
+        });
+        let index = ranges
+            .iter()
+            .position(|&(range_start, _)| range_start > start)
+            .unwrap_or(ranges.len());
+        ranges.insert(index, (start, end));
     }
 }
 
 pub mod expression {
     use super::analyzer::{IpAnalyzer, Version};
+    use super::flow::IpFlow;
     use super::IpClassifier;
 
     use crate::Config;
 
     use pmc_core::base::expression_value::ExpressionValue;
+    use pmc_core::expression::Expr;
+    use pmc_core::expression_value;
+    use pmc_core::language::{Operator, ValueCall, ValueRegistry};
 
     use std::net::IpAddr;
 
-    #[derive(Debug)]
-    pub struct Ip;
-    impl ExpressionValue<Config> for Ip {
-        type Classifier = IpClassifier;
-
-        const SHOULD_GRANT_BY_FLOW: bool = true;
-
-        fn check(&self, _packet: &IpAnalyzer, _: &()) -> bool {
-            true
-        }
+    #[expression_value(Ip, classifier = IpClassifier, grant_by_flow, definition = "ip")]
+    pub fn ip(_packet: &IpAnalyzer, _flow: &IpFlow) -> bool {
+        true
     }
 
+    // The enums of the analyzer are values themselves, they are not built from a predicate.
     pub use super::analyzer::Version as IpVersion;
     impl ExpressionValue<Config> for IpVersion {
         type Classifier = IpClassifier;
 
         const SHOULD_GRANT_BY_FLOW: bool = true;
 
-        fn check(&self, packet: &IpAnalyzer, _: &()) -> bool {
+        fn definition(&self) -> Option<ValueCall> {
+            let version: u8 = match self {
+                Self::V4 => 4,
+                Self::V6 => 6,
+            };
+            Some(ValueCall::new("ip.version").with_comparison(Operator::Equal, version))
+        }
+
+        fn check(&self, packet: &IpAnalyzer, _flow: &IpFlow) -> bool {
             match self {
                 Self::V4 => matches!(packet.version, Version::V4),
                 Self::V6 => matches!(packet.version, Version::V6),
@@ -150,6 +525,7 @@ pub mod expression {
         }
     }
 
+    // The addresses are written as text in the definitions, which the macro does not convert.
     #[derive(Debug)]
     pub struct IpSource(pub IpAddr);
     impl ExpressionValue<Config> for IpSource {
@@ -157,7 +533,11 @@ pub mod expression {
 
         const SHOULD_GRANT_BY_FLOW: bool = true;
 
-        fn check(&self, packet: &IpAnalyzer, _: &()) -> bool {
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("ip.source").with_comparison(Operator::Equal, self.0.to_string()))
+        }
+
+        fn check(&self, packet: &IpAnalyzer, _flow: &IpFlow) -> bool {
             self.0 == packet.source()
         }
     }
@@ -169,21 +549,108 @@ pub mod expression {
 
         const SHOULD_GRANT_BY_FLOW: bool = true;
 
-        fn check(&self, packet: &IpAnalyzer, _: &()) -> bool {
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("ip.dest").with_comparison(Operator::Equal, self.0.to_string()))
+        }
+
+        fn check(&self, packet: &IpAnalyzer, _flow: &IpFlow) -> bool {
             self.0 == packet.dest()
         }
     }
 
     #[derive(Debug, Clone, Copy, PartialEq)]
     pub enum IpProto {
+        Icmp = 1,
         Tcp = 6,
         Udp = 17,
+        Icmpv6 = 58,
     }
     impl ExpressionValue<Config> for IpProto {
         type Classifier = IpClassifier;
 
-        fn check(&self, packet: &IpAnalyzer, _: &()) -> bool {
+        fn definition(&self) -> Option<ValueCall> {
+            let proto = match self {
+                Self::Icmp => "icmp",
+                Self::Tcp => "tcp",
+                Self::Udp => "udp",
+                Self::Icmpv6 => "icmpv6",
+            };
+            Some(ValueCall::new("ip.proto").with_comparison(Operator::Equal, proto))
+        }
+
+        fn check(&self, packet: &IpAnalyzer, _flow: &IpFlow) -> bool {
             *self as u8 == packet.protocol_code()
         }
     }
+
+    pub use super::analyzer::ExtensionHeader as IpExtensionHeader;
+    impl IpExtensionHeader {
+        const NAMES: &'static [(&'static str, Self)] = &[
+            ("hop_by_hop", Self::HopByHop),
+            ("routing", Self::Routing),
+            ("fragment", Self::Fragment),
+            ("destination_options", Self::DestinationOptions),
+            ("authentication", Self::Authentication),
+            ("mobility", Self::Mobility),
+            ("host_identity", Self::HostIdentity),
+            ("shim6", Self::Shim6),
+        ];
+    }
+    impl ExpressionValue<Config> for IpExtensionHeader {
+        type Classifier = IpClassifier;
+
+        fn definition(&self) -> Option<ValueCall> {
+            let (name, _) = Self::NAMES.iter().find(|(_, header)| header == self)?;
+            Some(ValueCall::new("ip.extension_header").with_comparison(Operator::Equal, *name))
+        }
+
+        fn check(&self, packet: &IpAnalyzer, _flow: &IpFlow) -> bool {
+            packet.has_extension_header(*self)
+        }
+    }
+
+    fn ip_address(call: &ValueCall) -> Result<IpAddr, String> {
+        call.expect_args(0)?;
+        let address = call.compared_by(Operator::Equal)?.as_str()?;
+        address
+            .parse()
+            .map_err(|_| format!("'{}' is not a valid IP address", address))
+    }
+
+    pub(crate) fn register(registry: ValueRegistry<Config>) -> ValueRegistry<Config> {
+        registry
+            .with("ip", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(Ip))
+            })
+            .with("ip.version", |call| {
+                call.expect_args(0)?;
+                match call.compared_by(Operator::Equal)?.as_int()? {
+                    4 => Ok(Expr::value(IpVersion::V4)),
+                    6 => Ok(Expr::value(IpVersion::V6)),
+                    version => Err(format!("Unknown IP version {}", version)),
+                }
+            })
+            .with("ip.source", |call| Ok(Expr::value(IpSource(ip_address(call)?))))
+            .with("ip.dest", |call| Ok(Expr::value(IpDest(ip_address(call)?))))
+            .with("ip.proto", |call| {
+                call.expect_args(0)?;
+                match call.compared_by(Operator::Equal)?.as_text()? {
+                    "icmp" => Ok(Expr::value(IpProto::Icmp)),
+                    "tcp" => Ok(Expr::value(IpProto::Tcp)),
+                    "udp" => Ok(Expr::value(IpProto::Udp)),
+                    "icmpv6" => Ok(Expr::value(IpProto::Icmpv6)),
+                    proto => Err(format!("Unknown IP protocol '{}'", proto)),
+                }
+            })
+            .with("ip.extension_header", |call| {
+                call.expect_args(0)?;
+                let name = call.compared_by(Operator::Equal)?.as_text()?;
+                IpExtensionHeader::NAMES
+                    .iter()
+                    .find(|(header_name, _)| *header_name == name)
+                    .map(|&(_, header)| Expr::value(header))
+                    .ok_or_else(|| format!("Unknown IPv6 extension header '{}'", name))
+            })
+    }
 }
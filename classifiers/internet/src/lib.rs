@@ -1,56 +1,56 @@
 #[macro_use]
 extern crate arrayref;
 
+pub mod dns;
+pub mod ethernet;
 pub mod http;
+pub mod icmp;
 pub mod ip;
+pub mod link;
+pub mod mpls;
 pub mod tcp;
+pub mod tls;
 pub mod udp;
+pub mod vlan;
 
-use pmc_core::base::config::{
-    BaseConfig, ClassifierId as ClassifierIdTrait, Config as ConfigTrait,
-};
+use pmc_core::base::config::{BaseConfig, Config as ConfigTrait, FlowEviction};
+use pmc_core::language::ValueRegistry;
 use pmc_core::loader::ClassifierLoader;
+use pmc_core::ClassifierId;
 
-use num_derive::FromPrimitive;
-use num_traits::FromPrimitive;
-use strum::EnumCount;
-
+use std::hash::{Hash, Hasher};
 use std::net::Ipv6Addr;
+use std::time::Duration;
 
-#[derive(EnumCount, FromPrimitive, Debug, Clone, Copy, Hash, PartialEq, PartialOrd, Eq, Ord)]
+#[derive(ClassifierId, Debug, Clone, Copy, Hash, PartialEq, PartialOrd, Eq, Ord)]
 pub enum ClassifierId {
+    #[classifier_id(none)]
     None,
+    #[classifier_id(initial)]
+    Link,
+    Ethernet,
+    Vlan,
+    Mpls,
     Ip,
     Tcp,
     Udp,
+    Icmp,
     HttpStartLine,
     HttpHeader,
-}
-
-impl From<usize> for ClassifierId {
-    fn from(value: usize) -> Self {
-        Self::from_usize(value).expect("The value must be a valid ClassifierId")
-    }
-}
-
-impl From<ClassifierId> for usize {
-    fn from(id: ClassifierId) -> usize {
-        id as usize
-    }
-}
-
-impl ClassifierIdTrait for ClassifierId {
-    const NONE: ClassifierId = ClassifierId::None;
-    const INITIAL: ClassifierId = ClassifierId::Ip;
-    const TOTAL: usize = ClassifierId::COUNT;
+    DnsTcp,
+    Dns,
+    Tls,
 }
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
 pub enum FlowKind {
     None,
+    IpFragment,
     Tcp,
     Udp,
     Http,
+    Dns,
+    Tls,
 }
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
@@ -59,6 +59,8 @@ pub struct FlowSignature {
     dest_ip: Ipv6Addr,
     client_port: u16,
     server_port: u16,
+    /// IP identification and protocol of a fragmented datagram.
+    fragment: Option<(u32, u8)>,
     kind: FlowKind,
 }
 
@@ -69,22 +71,56 @@ impl Default for FlowSignature {
             dest_ip: Ipv6Addr::UNSPECIFIED,
             client_port: 0,
             server_port: 0,
+            fragment: None,
             kind: FlowKind::None,
         }
     }
 }
 
+#[derive(Clone)]
 pub struct Config {
-    pub base: BaseConfig,
+    pub base: BaseConfig<ClassifierId>,
+    /// Bytes of each direction of a TCP connection kept in order in its flow.
+    /// No reassembly is done without depth. While a segment extends the stream, the application
+    /// analyzers parse the stream from the start of its current message instead of the segment
+    /// payload. A message ends with a segment pushed by the sender. Segments in the depth that do
+    /// not extend it, as retransmissions, are not parsed by them.
+    /// The streams count in the `max_flow_memory` of the base config.
+    pub tcp_reassembly_depth: Option<usize>,
+    /// Maximum payload length of a reassembled IP datagram.
+    /// Longer datagrams are discarded. Their fragments expire with the IP flow timeout.
+    /// The fragments of all the datagrams count in the `max_flow_memory` of the base config,
+    /// which evicts the incomplete datagrams to make room.
+    pub ip_reassembly_max_len: usize,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             base: BaseConfig {
-                skip_analyzer_bytes: 1, // Skip bytes from IP analyzer
+                // Only the bytes after the IP header are payload.
+                skip_analyzer_bytes: vec![
+                    ClassifierId::Link,
+                    ClassifierId::Ethernet,
+                    ClassifierId::Vlan,
+                    ClassifierId::Mpls,
+                    ClassifierId::Ip,
+                ],
                 flow_pool_initial_size: 100,
+                flow_timeouts: vec![
+                    (ClassifierId::Ip, Duration::from_secs(30)),
+                    (ClassifierId::Tcp, Duration::from_secs(300)),
+                    (ClassifierId::Udp, Duration::from_secs(60)),
+                    (ClassifierId::HttpStartLine, Duration::from_secs(300)),
+                    (ClassifierId::Dns, Duration::from_secs(60)),
+                    (ClassifierId::Tls, Duration::from_secs(300)),
+                ],
+                max_flows: Some(1_000_000),
+                max_flow_memory: None,
+                flow_eviction: FlowEviction::Lru,
             },
+            tcp_reassembly_depth: None,
+            ip_reassembly_max_len: 65_535,
         }
     }
 }
@@ -93,16 +129,46 @@ impl ConfigTrait for Config {
     type FlowId = FlowSignature;
     type ClassifierId = ClassifierId;
 
-    fn base(&self) -> &BaseConfig {
+    fn base(&self) -> &BaseConfig<ClassifierId> {
         &self.base
     }
+
+    /// The addresses are shared by the fragments of a datagram and the flows of its payload.
+    fn hash_shard_key<H: Hasher>(signature: &FlowSignature, state: &mut H) {
+        signature.source_ip.hash(state);
+        signature.dest_ip.hash(state);
+    }
+}
+
+/// Expression values of this crate that can be written in the rule language.
+pub fn registry() -> ValueRegistry<Config> {
+    let registry = ValueRegistry::default();
+    let registry = ethernet::expression::register(registry);
+    let registry = vlan::expression::register(registry);
+    let registry = mpls::expression::register(registry);
+    let registry = ip::expression::register(registry);
+    let registry = udp::expression::register(registry);
+    let registry = tcp::expression::register(registry);
+    let registry = icmp::expression::register(registry);
+    let registry = http::expression::register(registry);
+    let registry = dns::expression::register(registry);
+    tls::expression::register(registry)
 }
 
 pub fn loader() -> ClassifierLoader<Config> {
     ClassifierLoader::default()
-        .with(ip::IpClassifier)
-        .with(udp::UdpClassifier)
-        .with(tcp::TcpClassifier)
-        .with(http::HttpStartLineClassifier)
-        .with(http::HttpHeaderClassifier)
+        .with(link::LinkClassifier)
+        .and_then(|loader| loader.with(ethernet::EthernetClassifier))
+        .and_then(|loader| loader.with(vlan::VlanClassifier))
+        .and_then(|loader| loader.with(mpls::MplsClassifier))
+        .and_then(|loader| loader.with(ip::IpClassifier))
+        .and_then(|loader| loader.with(udp::UdpClassifier))
+        .and_then(|loader| loader.with(tcp::TcpClassifier))
+        .and_then(|loader| loader.with(icmp::IcmpClassifier))
+        .and_then(|loader| loader.with(http::HttpStartLineClassifier))
+        .and_then(|loader| loader.with(http::HttpHeaderClassifier))
+        .and_then(|loader| loader.with(dns::DnsTcpClassifier))
+        .and_then(|loader| loader.with(dns::DnsClassifier))
+        .and_then(|loader| loader.with(tls::TlsClassifier))
+        .expect("The internet classifiers have different IDs")
 }
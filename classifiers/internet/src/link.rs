@@ -0,0 +1,66 @@
+use crate::Config;
+
+use pmc_core::base::classifier::Classifier;
+
+/// First classifier of every packet. It selects the classifier of the packet link type.
+pub struct LinkClassifier;
+impl<'a> Classifier<'a, Config> for LinkClassifier {
+    type Analyzer = analyzer::LinkAnalyzer;
+}
+
+mod analyzer {
+    use super::flow::LinkFlow;
+
+    use crate::ethernet::ether_type_classifier;
+    use crate::{ClassifierId, Config};
+
+    use pmc_core::base::analyzer::{
+        Analyzer, AnalyzerError, AnalyzerErrorKind, AnalyzerInfo, AnalyzerResult,
+    };
+    use pmc_core::packet::{LinkType, Packet};
+
+    pub struct LinkAnalyzer;
+
+    impl LinkAnalyzer {
+        const LINUX_SLL_HEADER_LEN: usize = 16;
+    }
+
+    impl<'a> Analyzer<'a, Config> for LinkAnalyzer {
+        const ID: ClassifierId = ClassifierId::Link;
+        const PREV_IDS: &'static [ClassifierId] = &[ClassifierId::None];
+
+        type Flow = LinkFlow;
+
+        fn build(
+            _config: &Config,
+            packet: &'a Packet,
+            _flow: &LinkFlow,
+        ) -> AnalyzerResult<Self, ClassifierId> {
+            let (next_classifier_id, bytes_parsed) = match packet.link_type {
+                LinkType::RawIp => (ClassifierId::Ip, 0),
+                LinkType::Ethernet => (ClassifierId::Ethernet, 0),
+                // The cooked header is only parsed to know its protocol.
+                LinkType::LinuxSll => {
+                    let data = packet.data;
+                    AnalyzerError::check_len(Self::ID, data, Self::LINUX_SLL_HEADER_LEN)?;
+                    let protocol = u16::from_be_bytes(*array_ref![data, 14, 2]);
+                    (ether_type_classifier(protocol), Self::LINUX_SLL_HEADER_LEN)
+                }
+                LinkType::Other(_) => {
+                    return Err(AnalyzerError::new(Self::ID, AnalyzerErrorKind::Unsupported, 0))
+                }
+            };
+
+            Ok(AnalyzerInfo {
+                analyzer: Self,
+                next_classifier_id,
+                bytes_parsed,
+            })
+        }
+    }
+}
+
+mod flow {
+    #[derive(Default)]
+    pub struct LinkFlow {}
+}
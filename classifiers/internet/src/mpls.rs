@@ -0,0 +1,119 @@
+use crate::Config;
+
+use pmc_core::base::classifier::Classifier;
+
+pub struct MplsClassifier;
+impl<'a> Classifier<'a, Config> for MplsClassifier {
+    type Analyzer = analyzer::MplsAnalyzer<'a>;
+}
+
+mod analyzer {
+    use super::flow::MplsFlow;
+
+    use crate::{ClassifierId, Config};
+
+    use pmc_core::base::analyzer::{Analyzer, AnalyzerError, AnalyzerInfo, AnalyzerResult};
+    use pmc_core::packet::Packet;
+
+    /// The whole label stack.
+    pub struct MplsAnalyzer<'a> {
+        pub entries: &'a [u8],
+    }
+
+    impl<'a> MplsAnalyzer<'a> {
+        const ENTRY_LEN: usize = 4;
+        const BOTTOM_OF_STACK: u32 = 0x100;
+
+        /// Labels from the top to the bottom of the stack.
+        pub fn labels(&self) -> impl Iterator<Item = u32> + 'a {
+            self.entries
+                .chunks_exact(Self::ENTRY_LEN)
+                .map(|entry| u32::from_be_bytes(*array_ref![entry, 0, 4]) >> 12)
+        }
+    }
+
+    impl<'a> Analyzer<'a, Config> for MplsAnalyzer<'a> {
+        const ID: ClassifierId = ClassifierId::Mpls;
+        const PREV_IDS: &'static [ClassifierId] = &[
+            ClassifierId::Link,
+            ClassifierId::Ethernet,
+            ClassifierId::Vlan,
+        ];
+
+        type Flow = MplsFlow;
+
+        fn build(
+            _config: &Config,
+            &Packet { data, .. }: &'a Packet,
+            _flow: &MplsFlow,
+        ) -> AnalyzerResult<Self, ClassifierId> {
+            let mut len = 0;
+            loop {
+                AnalyzerError::check_len(Self::ID, data, len + Self::ENTRY_LEN)?;
+                let entry = u32::from_be_bytes(*array_ref![data, len, 4]);
+                len += Self::ENTRY_LEN;
+                if entry & Self::BOTTOM_OF_STACK != 0 {
+                    break;
+                }
+            }
+
+            // The payload type is not written in the stack, only IP is recognized.
+            let next_classifier_id = match data.get(len).map(|byte| byte >> 4) {
+                Some(4) | Some(6) => ClassifierId::Ip,
+                _ => ClassifierId::None,
+            };
+
+            Ok(AnalyzerInfo {
+                analyzer: Self {
+                    entries: &data[..len],
+                },
+                next_classifier_id,
+                bytes_parsed: len,
+            })
+        }
+    }
+}
+
+mod flow {
+    #[derive(Default)]
+    pub struct MplsFlow {}
+}
+
+pub mod expression {
+    use super::analyzer::MplsAnalyzer;
+    use super::flow::MplsFlow;
+    use super::MplsClassifier;
+
+    use crate::Config;
+
+    use pmc_core::expression::Expr;
+    use pmc_core::expression_value;
+    use pmc_core::language::{Operator, ValueRegistry};
+
+    #[expression_value(Mpls, classifier = MplsClassifier, grant_by_flow, definition = "mpls")]
+    pub fn mpls(_packet: &MplsAnalyzer, _flow: &MplsFlow) -> bool {
+        true
+    }
+
+    /// Packets with this label at any level of the stack.
+    /// The labels of a flow can change along its path, so it is checked in every packet.
+    #[expression_value(MplsLabel, classifier = MplsClassifier, definition = "mpls.label")]
+    pub fn mpls_label(label: &u32, packet: &MplsAnalyzer, _flow: &MplsFlow) -> bool {
+        packet.labels().any(|entry_label| entry_label == *label)
+    }
+
+    pub(crate) fn register(registry: ValueRegistry<Config>) -> ValueRegistry<Config> {
+        registry
+            .with("mpls", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(Mpls))
+            })
+            .with("mpls.label", |call| {
+                call.expect_args(0)?;
+                match call.compared_by(Operator::Equal)?.as_int()? {
+                    label if label < 0x10_0000 => Ok(Expr::value(MplsLabel(label as u32))),
+                    label => Err(format!("The MPLS label {} does not fit in 20 bits", label)),
+                }
+            })
+    }
+}
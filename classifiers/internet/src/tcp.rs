@@ -12,9 +12,13 @@ mod analyzer {
 
     use crate::{ClassifierId, Config, FlowKind, FlowSignature};
 
-    use pmc_core::base::analyzer::{Analyzer, AnalyzerInfo, AnalyzerResult, UseFlow};
+    use pmc_core::base::analyzer::{
+        Analyzer, AnalyzerError, AnalyzerErrorKind, AnalyzerInfo, AnalyzerResult, UseFlow,
+    };
     use pmc_core::packet::{Direction, Packet};
 
+    use std::convert::TryFrom;
+
     bitflags::bitflags! {
         pub struct Flag: u8 {
             const FIN = 1 << 0;
@@ -30,6 +34,8 @@ mod analyzer {
 
     pub struct TcpAnalyzer<'a> {
         pub header: &'a [u8],
+        /// Captured payload, it can be shorter than `payload_len`.
+        pub payload: &'a [u8],
         pub payload_len: u16,
         pub direction: Direction,
     }
@@ -66,10 +72,23 @@ mod analyzer {
             Flag::from_bits(self.header[13]).unwrap()
         }
 
+        /// Checks the header bounds, returning its length.
+        fn check_header(data: &[u8]) -> Result<usize, AnalyzerError<ClassifierId>> {
+            AnalyzerError::check_len(Self::ID, data, 20)?;
+            let header_len = (((data[12] & 0xF0) as usize) >> 4) << 2;
+            if header_len < 20 {
+                return Err(AnalyzerError::new(Self::ID, AnalyzerErrorKind::Malformed, 12));
+            }
+            AnalyzerError::check_len(Self::ID, data, header_len)?;
+            Ok(header_len)
+        }
+
         fn expected_l7_classifier(server_port: u16) -> ClassifierId {
             match server_port {
                 80 => ClassifierId::HttpStartLine,
                 8080 => ClassifierId::HttpStartLine,
+                53 => ClassifierId::DnsTcp,
+                443 => ClassifierId::Tls,
                 _ => ClassifierId::None,
             }
         }
@@ -77,14 +96,20 @@ mod analyzer {
 
     impl<'a> Analyzer<'a, Config> for TcpAnalyzer<'a> {
         const ID: ClassifierId = ClassifierId::Tcp;
-        const PREV_ID: ClassifierId = ClassifierId::Ip;
+        const PREV_IDS: &'static [ClassifierId] = &[ClassifierId::Ip, ClassifierId::Icmp];
 
         type Flow = TcpFlow;
 
         fn update_flow_id(
             signature: &mut FlowSignature,
-            &Packet { data, direction }: &Packet,
-        ) -> UseFlow {
+            &Packet {
+                data, direction, ..
+            }: &Packet,
+        ) -> UseFlow<ClassifierId> {
+            if let Err(error) = Self::check_header(data) {
+                return UseFlow::Abort(error);
+            }
+
             let (source, dest) = (
                 u16::from_be_bytes(*array_ref![data, 0, 2]),
                 u16::from_be_bytes(*array_ref![data, 2, 2]),
@@ -97,21 +122,31 @@ mod analyzer {
 
             signature.client_port = first;
             signature.server_port = second;
+            signature.fragment = None;
             signature.kind = FlowKind::Tcp;
 
             UseFlow::Yes
         }
 
         fn build(
-            _config: &Config,
-            &Packet { data, direction }: &'a Packet,
-            _flow: &TcpFlow,
+            config: &Config,
+            packet @ &Packet {
+                data, direction, ..
+            }: &'a Packet,
+            flow: &TcpFlow,
         ) -> AnalyzerResult<Self, ClassifierId> {
-            let header_len = (((data[12] & 0xF0) as usize) >> 4) << 2;
+            let header_len = Self::check_header(data)?;
+            // The payload not captured also counts to follow the sequence numbers.
+            // Its length comes from the IP total length, as the wire length of the packet.
+            let wire_payload_len = data.len() + packet.truncated_len() - header_len;
+            let payload_len = u16::try_from(wire_payload_len).map_err(|_| {
+                AnalyzerError::new(Self::ID, AnalyzerErrorKind::Unsupported, header_len)
+            })?;
 
             let analyzer = Self {
                 header: &data[0..header_len],
-                payload_len: (data.len() - header_len) as u16,
+                payload: &data[header_len..],
+                payload_len,
                 direction,
             };
 
@@ -120,6 +155,21 @@ mod analyzer {
                 false => ClassifierId::None,
             };
 
+            // Segments that are parsed as part of the stream are not parsed alone. Without the
+            // stream, the TLS analyzer buffers the segments, so they can not be repeated.
+            let seq_num = analyzer.seq_num();
+            let skip_segment = match config.tcp_reassembly_depth {
+                Some(_) => flow.is_out_of_order(direction, seq_num, analyzer.payload),
+                None => {
+                    next_protocol == ClassifierId::Tls
+                        && flow.is_received(direction, seq_num, analyzer.payload_len)
+                }
+            };
+            let next_protocol = match skip_segment {
+                true => ClassifierId::None,
+                false => next_protocol,
+            };
+
             Ok(AnalyzerInfo {
                 analyzer,
                 next_classifier_id: next_protocol,
@@ -127,7 +177,19 @@ mod analyzer {
             })
         }
 
-        fn update_flow(&self, _config: &Config, flow: &mut TcpFlow, direction: Direction) {
+        fn update_flow(&self, config: &Config, flow: &mut TcpFlow, direction: Direction) {
+            if let Some(depth) = config.tcp_reassembly_depth {
+                let message_start =
+                    flow.reassemble(direction, self.seq_num(), self.flags(), self.payload, depth);
+
+                // The next analyzers parse the message instead of the payload that extends it.
+                if let Some(message_start) = message_start {
+                    if Self::expected_l7_classifier(self.server_port()) != ClassifierId::None {
+                        flow.set_extended_stream(direction, message_start);
+                    }
+                }
+            }
+
             flow.update_seq_nums(
                 direction,
                 self.seq_num(),
@@ -140,11 +202,24 @@ mod analyzer {
                 flow.update_state_transition(direction, self.flags());
             }
         }
+
+        fn is_flow_closed(flow: &TcpFlow) -> bool {
+            flow.is_closed()
+        }
+
+        fn flow_heap_memory(flow: &TcpFlow) -> usize {
+            flow.heap_memory()
+        }
+
+        fn take_reassembled(flow: &mut TcpFlow) -> Option<Vec<u8>> {
+            flow.take_extended_stream()
+        }
     }
 }
 
 mod flow {
     use super::analyzer::Flag;
+    use super::reassembly::Stream;
 
     use pmc_core::packet::Direction;
 
@@ -172,6 +247,11 @@ mod flow {
         ul_seq_num: u32,
         dl_seq_num: u32,
         last_packet_status: PacketStatus,
+        ul_stream: Option<Stream>,
+        dl_stream: Option<Stream>,
+        /// Stream extended by the last packet, with the offset of the message extended,
+        /// to be parsed by the next analyzers.
+        extended_stream: Option<(Direction, usize)>,
     }
 
     impl Default for TcpFlow {
@@ -182,6 +262,9 @@ mod flow {
                 ul_seq_num: 0,
                 dl_seq_num: 0,
                 last_packet_status: PacketStatus::Expected,
+                ul_stream: None,
+                dl_stream: None,
+                extended_stream: None,
             }
         }
     }
@@ -223,7 +306,7 @@ mod flow {
                 Direction::Uplink => {
                     if self.ul_seq_num == seq_num && self.dl_seq_num == ack_num || syn {
                         if len > 0 {
-                            self.ul_seq_num = seq_num + len;
+                            self.ul_seq_num = seq_num.wrapping_add(len);
                             self.dl_seq_num = ack_num;
                         }
                         self.last_packet_status = PacketStatus::Expected;
@@ -234,7 +317,7 @@ mod flow {
                     if self.ul_seq_num == ack_num && (self.dl_seq_num == seq_num || syn) {
                         if len > 0 {
                             self.ul_seq_num = ack_num;
-                            self.dl_seq_num = seq_num + len;
+                            self.dl_seq_num = seq_num.wrapping_add(len);
                         }
                         self.last_packet_status = PacketStatus::Expected;
                         return;
@@ -245,6 +328,85 @@ mod flow {
             self.last_packet_status = PacketStatus::Retransmission;
         }
 
+        /// Adds the payload to the stream of its direction, returning the offset of the message
+        /// extended, if the contiguous data of the stream was extended.
+        /// The stream starts after the SYN, or at the first payload if the SYN was not seen.
+        pub fn reassemble(
+            &mut self,
+            direction: Direction,
+            seq_num: u32,
+            flags: Flag,
+            payload: &[u8],
+            depth: usize,
+        ) -> Option<usize> {
+            let stream = match direction {
+                Direction::Uplink => &mut self.ul_stream,
+                Direction::Downlink => &mut self.dl_stream,
+            };
+
+            if flags.contains(Flag::SYN) {
+                *stream = Some(Stream::new(seq_num.wrapping_add(1), depth));
+                None
+            } else if !payload.is_empty() {
+                stream
+                    .get_or_insert_with(|| Stream::new(seq_num, depth))
+                    .insert(seq_num, payload, flags.contains(Flag::PSH))
+            } else {
+                None
+            }
+        }
+
+        pub fn set_extended_stream(&mut self, direction: Direction, message_start: usize) {
+            self.extended_stream = Some((direction, message_start));
+        }
+
+        /// Whether the segment is in the depth of the stream of its direction but does not extend
+        /// it, as it was already received or it comes after a gap.
+        pub fn is_out_of_order(&self, direction: Direction, seq_num: u32, payload: &[u8]) -> bool {
+            match self.stream(direction) {
+                Some(stream) => stream.is_out_of_order(seq_num, payload.len()),
+                None => false,
+            }
+        }
+
+        /// Whether the payload of the segment was already received, as far as the sequence
+        /// numbers are known from the handshake.
+        pub fn is_received(&self, direction: Direction, seq_num: u32, payload_len: u16) -> bool {
+            if matches!(self.state_transition, Listen | SynSend) {
+                return false;
+            }
+
+            let next_seq_num = match direction {
+                Direction::Uplink => self.ul_seq_num,
+                Direction::Downlink => self.dl_seq_num,
+            };
+            let end = seq_num.wrapping_add(payload_len as u32);
+            payload_len > 0 && end.wrapping_sub(next_seq_num) as i32 <= 0
+        }
+
+        /// Copy of the message extended by the last packet, if any, until the end of the
+        /// contiguous data of its stream.
+        pub fn take_extended_stream(&mut self) -> Option<Vec<u8>> {
+            let (direction, message_start) = self.extended_stream.take()?;
+            self.stream(direction)
+                .map(|stream| stream.data()[message_start..].to_vec())
+        }
+
+        /// Bytes allocated for the streams.
+        pub fn heap_memory(&self) -> usize {
+            let ul_memory = self.ul_stream.as_ref().map_or(0, Stream::heap_memory);
+            let dl_memory = self.dl_stream.as_ref().map_or(0, Stream::heap_memory);
+            ul_memory + dl_memory
+        }
+
+        /// Reassembled stream of the direction, if the reassembly is enabled and it has started.
+        pub fn stream(&self, direction: Direction) -> Option<&Stream> {
+            match direction {
+                Direction::Uplink => self.ul_stream.as_ref(),
+                Direction::Downlink => self.dl_stream.as_ref(),
+            }
+        }
+
         pub fn state_transition(&self) -> StateTransition {
             self.state_transition
         }
@@ -258,10 +420,12 @@ mod flow {
         }
 
         pub fn is_teardown(&self) -> bool {
-            match self.state_transition {
-                FinWait1 | FinWait2 | Closing | TimeWait => true,
-                _ => false,
-            }
+            matches!(self.state_transition, FinWait1 | FinWait2 | Closing | TimeWait)
+        }
+
+        /// Both sides have sent and acknowledged their FIN.
+        pub fn is_closed(&self) -> bool {
+            self.state_transition == TimeWait
         }
 
         pub fn is_last_packet_expected(&self) -> bool {
@@ -270,65 +434,177 @@ mod flow {
     }
 }
 
-pub mod expression {
-    use super::analyzer::TcpAnalyzer;
-    use super::flow::{StateTransition, TcpFlow};
-    use super::TcpClassifier;
+mod reassembly {
+    use std::collections::BTreeMap;
+    use std::convert::TryFrom;
+
+    /// Bytes of one direction of a connection, in sequence order.
+    /// Only the first `depth` bytes of the stream are kept.
+    /// The messages of the stream end with the segments pushed by the sender.
+    pub struct Stream {
+        /// Sequence number of the first byte of `data`.
+        start_seq: u32,
+        data: Vec<u8>,
+        /// Offset of the message that the next bytes extend.
+        message_start: usize,
+        /// Segments received after a gap, by their offset in the stream, and whether they
+        /// were pushed.
+        pending: BTreeMap<usize, (Vec<u8>, bool)>,
+        /// Bytes stored in `pending`, also limited by the depth.
+        pending_len: usize,
+        depth: usize,
+    }
 
-    use crate::Config;
+    impl Stream {
+        pub fn new(start_seq: u32, depth: usize) -> Self {
+            Self {
+                start_seq,
+                data: Vec::new(),
+                message_start: 0,
+                pending: BTreeMap::new(),
+                pending_len: 0,
+                depth,
+            }
+        }
 
-    use pmc_core::base::expression_value::ExpressionValue;
+        /// Contiguous bytes from the start of the stream.
+        pub fn data(&self) -> &[u8] {
+            &self.data
+        }
 
-    use std::fmt;
+        /// Bytes allocated for the data and the pending segments.
+        pub fn heap_memory(&self) -> usize {
+            let pending_memory = self.pending.values().map(|(segment, _)| segment.capacity());
+            self.data.capacity() + pending_memory.sum::<usize>()
+        }
 
-    #[derive(Debug)]
-    pub struct Tcp;
-    impl ExpressionValue<Config> for Tcp {
-        type Classifier = TcpClassifier;
+        /// Whether the segment is in the depth of the stream but does not extend the contiguous
+        /// data, as it was already received or it comes after a gap.
+        pub fn is_out_of_order(&self, seq_num: u32, len: usize) -> bool {
+            let offset = seq_num.wrapping_sub(self.start_seq) as i32 as i64;
+            let end = offset + len as i64;
+            let data_len = self.data.len() as i64;
+            let depth = self.depth as i64;
+            data_len < depth && (end <= data_len || (offset > data_len && offset < depth))
+        }
 
-        const SHOULD_GRANT_BY_FLOW: bool = true;
+        /// Adds a segment at its place in the stream, returning the offset of the message it
+        /// extends, if the contiguous data was extended.
+        /// Bytes already received are kept when segments overlap.
+        pub fn insert(&mut self, seq_num: u32, segment: &[u8], push: bool) -> Option<usize> {
+            // Sequence numbers wrap around, so segments up to 2 GiB behind the start are old.
+            let offset = seq_num.wrapping_sub(self.start_seq) as i32;
+            let (offset, segment) = match usize::try_from(offset) {
+                Ok(offset) => (offset, segment),
+                Err(_) => {
+                    let old_len = offset.unsigned_abs() as usize;
+                    (0, segment.get(old_len..).unwrap_or_default())
+                }
+            };
 
-        fn check(&self, _packet: &TcpAnalyzer, _flow: &TcpFlow) -> bool {
-            true
-        }
-    }
+            let end = (offset + segment.len()).min(self.depth);
+            if offset >= end {
+                return None;
+            }
+            // The end of the message is not kept after the depth.
+            let push = push && end == offset + segment.len();
+            let segment = &segment[..end - offset];
+
+            if offset > self.data.len() {
+                let stored_len = self
+                    .pending
+                    .get(&offset)
+                    .map_or(0, |(stored, _)| stored.len());
+                let pending_len = self.pending_len - stored_len + segment.len();
+                if stored_len < segment.len() && pending_len <= self.depth {
+                    self.pending.insert(offset, (segment.to_vec(), push));
+                    self.pending_len = pending_len;
+                }
+                return None;
+            }
 
-    #[derive(Debug)]
-    pub struct TcpSourcePort(pub u16);
-    impl ExpressionValue<Config> for TcpSourcePort {
-        type Classifier = TcpClassifier;
+            let data_len = self.data.len();
+            let message_start = self.message_start;
+            self.append(offset, segment, push);
+            while let Some(offset) = self.pending.keys().next().copied() {
+                if offset > self.data.len() {
+                    break;
+                }
+                let (segment, push) = self.pending.remove(&offset).unwrap();
+                self.pending_len -= segment.len();
+                self.append(offset, &segment, push);
+            }
 
-        const SHOULD_GRANT_BY_FLOW: bool = true;
+            match self.data.len() > data_len {
+                true => Some(message_start),
+                false => None,
+            }
+        }
 
-        fn check(&self, packet: &TcpAnalyzer, _flow: &TcpFlow) -> bool {
-            self.0 == packet.source_port()
+        /// Appends the part of a segment, starting at most at the end of the data, that is new.
+        /// A pushed segment ends the message.
+        fn append(&mut self, offset: usize, segment: &[u8], push: bool) {
+            let new_from = self.data.len() - offset;
+            if new_from < segment.len() {
+                self.data.extend_from_slice(&segment[new_from..]);
+                if push {
+                    self.message_start = self.data.len();
+                }
+            }
         }
     }
+}
 
-    #[derive(Debug)]
-    pub struct TcpDestPort(pub u16);
-    impl ExpressionValue<Config> for TcpDestPort {
-        type Classifier = TcpClassifier;
+pub mod expression {
+    use super::analyzer::TcpAnalyzer;
+    use super::flow::{StateTransition, TcpFlow};
+    use super::TcpClassifier;
 
-        const SHOULD_GRANT_BY_FLOW: bool = true;
+    use crate::Config;
 
-        fn check(&self, packet: &TcpAnalyzer, _flow: &TcpFlow) -> bool {
-            self.0 == packet.dest_port()
-        }
+    use pmc_core::base::expression_value::ExpressionValue;
+    use pmc_core::expression::Expr;
+    use pmc_core::expression_value;
+    use pmc_core::language::{Operator, ValueCall, ValueRegistry};
+
+    use std::fmt;
+
+    #[expression_value(Tcp, classifier = TcpClassifier, grant_by_flow, definition = "tcp")]
+    pub fn tcp(_packet: &TcpAnalyzer, _flow: &TcpFlow) -> bool {
+        true
     }
 
-    #[derive(Debug)]
-    pub struct TcpServerPort(pub u16);
-    impl ExpressionValue<Config> for TcpServerPort {
-        type Classifier = TcpClassifier;
+    #[expression_value(
+        TcpSourcePort,
+        classifier = TcpClassifier,
+        grant_by_flow,
+        definition = "tcp.source_port"
+    )]
+    pub fn tcp_source_port(port: &u16, packet: &TcpAnalyzer, _flow: &TcpFlow) -> bool {
+        *port == packet.source_port()
+    }
 
-        const SHOULD_GRANT_BY_FLOW: bool = true;
+    #[expression_value(
+        TcpDestPort,
+        classifier = TcpClassifier,
+        grant_by_flow,
+        definition = "tcp.dest_port"
+    )]
+    pub fn tcp_dest_port(port: &u16, packet: &TcpAnalyzer, _flow: &TcpFlow) -> bool {
+        *port == packet.dest_port()
+    }
 
-        fn check(&self, packet: &TcpAnalyzer, _flow: &TcpFlow) -> bool {
-            self.0 == packet.server_port()
-        }
+    #[expression_value(
+        TcpServerPort,
+        classifier = TcpClassifier,
+        grant_by_flow,
+        definition = "tcp.server_port"
+    )]
+    pub fn tcp_server_port(port: &u16, packet: &TcpAnalyzer, _flow: &TcpFlow) -> bool {
+        *port == packet.server_port()
     }
 
+    // The values over a user function are written by hand, the macro does not take generics.
     pub struct TcpPayloadLen<F>(pub F);
     impl<F> fmt::Debug for TcpPayloadLen<F> {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
@@ -336,7 +612,7 @@ pub mod expression {
         }
     }
     impl<F> ExpressionValue<Config> for TcpPayloadLen<F>
-    where F: Fn(u16) -> bool + 'static
+    where F: Fn(u16) -> bool + Send + Sync + 'static
     {
         type Classifier = super::TcpClassifier;
 
@@ -345,59 +621,191 @@ pub mod expression {
         }
     }
 
+    // The macro only writes definitions compared by `==`, not by the operator of the value.
+    /// Payload length compared with a fixed value.
+    /// Unlike [`TcpPayloadLen`], it can be stored as a rule definition.
     #[derive(Debug)]
-    pub struct TcpEstablished;
-    impl ExpressionValue<Config> for TcpEstablished {
-        type Classifier = TcpClassifier;
+    pub struct TcpPayloadLenCmp(pub Operator, pub u16);
+    impl ExpressionValue<Config> for TcpPayloadLenCmp {
+        type Classifier = super::TcpClassifier;
 
-        const SHOULD_GRANT_BY_FLOW: bool = true;
-        fn should_break_grant(&self, packet: &TcpAnalyzer) -> bool {
-            packet.flags().contains(TcpFlag::FIN)
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("tcp.payload_len").with_comparison(self.0, self.1))
         }
 
-        fn check(&self, _packet: &TcpAnalyzer, flow: &TcpFlow) -> bool {
-            StateTransition::Established == flow.state_transition()
+        fn check(&self, packet: &TcpAnalyzer, _flow: &TcpFlow) -> bool {
+            self.0.compare(packet.payload_len(), self.1)
         }
     }
 
-    #[derive(Debug)]
-    pub struct TcpHandshake;
-    impl ExpressionValue<Config> for TcpHandshake {
-        type Classifier = TcpClassifier;
-
-        fn check(&self, _packet: &TcpAnalyzer, flow: &TcpFlow) -> bool {
-            flow.is_handshake()
-        }
+    /// A FIN ends the established state.
+    fn is_fin(packet: &TcpAnalyzer) -> bool {
+        packet.flags().contains(TcpFlag::FIN)
     }
 
-    #[derive(Debug)]
-    pub struct TcpTeardown;
-    impl ExpressionValue<Config> for TcpTeardown {
-        type Classifier = TcpClassifier;
+    #[expression_value(
+        TcpEstablished,
+        classifier = TcpClassifier,
+        grant_by_flow,
+        break_grant = is_fin,
+        definition = "tcp.established"
+    )]
+    pub fn tcp_established(_packet: &TcpAnalyzer, flow: &TcpFlow) -> bool {
+        StateTransition::Established == flow.state_transition()
+    }
 
-        const SHOULD_GRANT_BY_FLOW: bool = true;
+    #[expression_value(TcpHandshake, classifier = TcpClassifier, definition = "tcp.handshake")]
+    pub fn tcp_handshake(_packet: &TcpAnalyzer, flow: &TcpFlow) -> bool {
+        flow.is_handshake()
+    }
 
-        fn check(&self, _packet: &TcpAnalyzer, flow: &TcpFlow) -> bool {
-            flow.is_teardown()
-        }
+    #[expression_value(
+        TcpTeardown,
+        classifier = TcpClassifier,
+        grant_by_flow,
+        definition = "tcp.teardown"
+    )]
+    pub fn tcp_teardown(_packet: &TcpAnalyzer, flow: &TcpFlow) -> bool {
+        flow.is_teardown()
     }
 
+    // The flags type is the value itself, it is not built from a predicate.
     pub use super::analyzer::Flag as TcpFlag;
     impl ExpressionValue<Config> for TcpFlag {
         type Classifier = TcpClassifier;
 
+        /// Only single flags have definition.
+        fn definition(&self) -> Option<ValueCall> {
+            let name = FLAG_NAMES
+                .iter()
+                .find(|(_, flag)| flag == self)
+                .map(|(name, _)| *name)?;
+            Some(ValueCall::new("tcp.flag").with_arg(name))
+        }
+
         fn check(&self, packet: &TcpAnalyzer, _flow: &TcpFlow) -> bool {
             packet.flags().contains(*self)
         }
     }
 
+    #[expression_value(
+        TcpRetransmission,
+        classifier = TcpClassifier,
+        definition = "tcp.retransmission"
+    )]
+    pub fn tcp_retransmission(_packet: &TcpAnalyzer, flow: &TcpFlow) -> bool {
+        !flow.is_last_packet_expected()
+    }
+
+    // Generic over the user function, as `TcpPayloadLen`.
+    /// User function over the reassembled bytes of the packet direction, see
+    /// [`Config::tcp_reassembly_depth`].
+    pub struct TcpStream<F>(pub F);
+    impl<F> fmt::Debug for TcpStream<F> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+            write!(f, "TcpStream(USER_FN)")
+        }
+    }
+    impl<F> ExpressionValue<Config> for TcpStream<F>
+    where F: Fn(&[u8]) -> bool + Send + Sync + 'static
+    {
+        type Classifier = TcpClassifier;
+
+        fn check(&self, packet: &TcpAnalyzer, flow: &TcpFlow) -> bool {
+            match flow.stream(packet.direction) {
+                Some(stream) => self.0(stream.data()),
+                None => false,
+            }
+        }
+    }
+
+    // Generic over the text, and compared by `~` in its definition.
+    /// The reassembled bytes of the packet direction contain the text.
+    /// Unlike [`TcpStream`], it can be stored as a rule definition.
     #[derive(Debug)]
-    pub struct TcpRetransmission;
-    impl ExpressionValue<Config> for TcpRetransmission {
+    pub struct TcpStreamMatch<S = &'static str>(pub S);
+    impl<S> ExpressionValue<Config> for TcpStreamMatch<S>
+    where S: AsRef<str> + fmt::Debug + Send + Sync + 'static
+    {
         type Classifier = TcpClassifier;
 
-        fn check(&self, _packet: &TcpAnalyzer, flow: &TcpFlow) -> bool {
-            !flow.is_last_packet_expected()
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("tcp.stream").with_comparison(Operator::Match, self.0.as_ref()))
         }
+
+        fn check(&self, packet: &TcpAnalyzer, flow: &TcpFlow) -> bool {
+            let text = self.0.as_ref().as_bytes();
+            match flow.stream(packet.direction) {
+                Some(stream) if !text.is_empty() => stream
+                    .data()
+                    .windows(text.len())
+                    .any(|window| window == text),
+                Some(_) => true,
+                None => false,
+            }
+        }
+    }
+
+    const FLAG_NAMES: [(&str, TcpFlag); 8] = [
+        ("FIN", TcpFlag::FIN),
+        ("SYN", TcpFlag::SYN),
+        ("RST", TcpFlag::RST),
+        ("PSH", TcpFlag::PSH),
+        ("ACK", TcpFlag::ACK),
+        ("URG", TcpFlag::URG),
+        ("ECE", TcpFlag::ECE),
+        ("CWR", TcpFlag::CWR),
+    ];
+
+    fn port(call: &ValueCall) -> Result<u16, String> {
+        call.expect_args(0)?;
+        call.compared_by(Operator::Equal)?.as_u16()
+    }
+
+    pub(crate) fn register(registry: ValueRegistry<Config>) -> ValueRegistry<Config> {
+        registry
+            .with("tcp", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(Tcp))
+            })
+            .with("tcp.source_port", |call| Ok(Expr::value(TcpSourcePort(port(call)?))))
+            .with("tcp.dest_port", |call| Ok(Expr::value(TcpDestPort(port(call)?))))
+            .with("tcp.server_port", |call| Ok(Expr::value(TcpServerPort(port(call)?))))
+            .with("tcp.payload_len", |call| {
+                call.expect_args(0)?;
+                let (operator, len) = call.ordered_comparison()?;
+                Ok(Expr::value(TcpPayloadLenCmp(operator, len.as_u16()?)))
+            })
+            .with("tcp.handshake", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(TcpHandshake))
+            })
+            .with("tcp.established", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(TcpEstablished))
+            })
+            .with("tcp.teardown", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(TcpTeardown))
+            })
+            .with("tcp.retransmission", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(TcpRetransmission))
+            })
+            .with("tcp.stream", |call| {
+                call.expect_args(0)?;
+                let text = call.compared_by(Operator::Match)?.as_str()?.to_string();
+                Ok(Expr::value(TcpStreamMatch(text)))
+            })
+            .with("tcp.flag", |call| {
+                call.expect_args(1)?;
+                call.expect_no_comparison()?;
+                let name = call.arg(0)?.as_text()?;
+                FLAG_NAMES
+                    .iter()
+                    .find(|(flag_name, _)| *flag_name == name)
+                    .map(|(_, flag)| Expr::value(*flag))
+                    .ok_or_else(|| format!("Unknown TCP flag '{}'", name))
+            })
     }
 }
@@ -0,0 +1,679 @@
+use crate::Config;
+
+use pmc_core::base::classifier::Classifier;
+
+/// TLS connections, described by the ClientHello and ServerHello of their handshake.
+pub struct TlsClassifier;
+impl<'a> Classifier<'a, Config> for TlsClassifier {
+    type Analyzer = analyzer::TlsAnalyzer<'a>;
+}
+
+mod analyzer {
+    use super::flow::TlsFlow;
+
+    use crate::{ClassifierId, Config, FlowKind, FlowSignature};
+
+    use pmc_core::base::analyzer::{Analyzer, AnalyzerInfo, AnalyzerResult, UseFlow};
+    use pmc_core::packet::{Direction, Packet};
+
+    pub struct TlsAnalyzer<'a> {
+        payload: &'a [u8],
+        /// The packet can still complete the hello of its direction.
+        hello: bool,
+    }
+
+    impl<'a> TlsAnalyzer<'a> {
+        pub fn is_hello(&self) -> bool {
+            self.hello
+        }
+    }
+
+    impl<'a> Analyzer<'a, Config> for TlsAnalyzer<'a> {
+        const ID: ClassifierId = ClassifierId::Tls;
+        const PREV_IDS: &'static [ClassifierId] = &[ClassifierId::Tcp];
+
+        type Flow = TlsFlow;
+
+        fn update_flow_id(
+            signature: &mut FlowSignature,
+            _packet: &Packet,
+        ) -> UseFlow<ClassifierId> {
+            signature.kind = FlowKind::Tls;
+            UseFlow::Yes
+        }
+
+        fn build(
+            _config: &Config,
+            &Packet {
+                data, direction, ..
+            }: &'a Packet,
+            flow: &TlsFlow,
+        ) -> AnalyzerResult<Self, ClassifierId> {
+            // The records are parsed by the flow, once the hello has all its segments.
+            // With TCP reassembly, the payload is the stream from the start of the message.
+            Ok(AnalyzerInfo {
+                analyzer: Self {
+                    payload: data,
+                    hello: flow.expects_hello(direction),
+                },
+                next_classifier_id: ClassifierId::None,
+                bytes_parsed: 0,
+            })
+        }
+
+        fn update_flow(&self, config: &Config, flow: &mut TlsFlow, direction: Direction) {
+            if self.hello {
+                match config.tcp_reassembly_depth {
+                    Some(depth) => flow.parse_stream(direction, self.payload, depth),
+                    None => flow.add_records(direction, self.payload),
+                }
+            }
+        }
+
+        fn flow_heap_memory(flow: &TlsFlow) -> usize {
+            flow.heap_memory()
+        }
+    }
+}
+
+mod handshake {
+    pub struct ClientHello {
+        /// Versions of the supported_versions extension, or the legacy version without it.
+        pub versions: Vec<u16>,
+        pub cipher_suites: Vec<u16>,
+        pub server_name: Option<String>,
+        pub alpn: Vec<String>,
+    }
+
+    pub struct ServerHello {
+        pub version: u16,
+        pub cipher_suite: u16,
+        pub alpn: Option<String>,
+    }
+
+    pub enum Hello {
+        Client(ClientHello),
+        Server(ServerHello),
+    }
+
+    pub enum Progress {
+        Incomplete,
+        Complete(Hello),
+        Invalid,
+    }
+
+    const HANDSHAKE: u8 = 22;
+    const RECORD_HEADER_LEN: usize = 5;
+    const MAX_RECORD_LEN: usize = 16384 + 2048;
+
+    const SERVER_NAME: u16 = 0;
+    const ALPN: u16 = 16;
+    const SUPPORTED_VERSIONS: u16 = 43;
+
+    /// Bytes read in order. Every read fails once the data is exhausted.
+    struct Reader<'a>(&'a [u8]);
+
+    impl<'a> Reader<'a> {
+        fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+            if self.0.len() < len {
+                return None;
+            }
+            let (bytes, rest) = self.0.split_at(len);
+            self.0 = rest;
+            Some(bytes)
+        }
+
+        fn u8(&mut self) -> Option<u8> {
+            self.bytes(1).map(|bytes| bytes[0])
+        }
+
+        fn u16(&mut self) -> Option<u16> {
+            self.bytes(2)
+                .map(|bytes| u16::from_be_bytes(*array_ref![bytes, 0, 2]))
+        }
+
+        /// Bytes after a length of `len_size` bytes.
+        fn vector(&mut self, len_size: usize) -> Option<Reader<'a>> {
+            let len = self
+                .bytes(len_size)?
+                .iter()
+                .fold(0, |len, &byte| (len << 8) | byte as usize);
+            self.bytes(len).map(Reader)
+        }
+
+        fn u16_list(&mut self, len_size: usize) -> Option<Vec<u16>> {
+            let mut list = self.vector(len_size)?;
+            let mut values = Vec::new();
+            while !list.0.is_empty() {
+                values.push(list.u16()?);
+            }
+            Some(values)
+        }
+
+        fn text(&mut self, len_size: usize) -> Option<String> {
+            let text = self.vector(len_size)?.0;
+            Some(String::from_utf8_lossy(text).into_owned())
+        }
+    }
+
+    /// Looks for the first handshake message in the records sent from the start of a direction.
+    pub fn find_hello(records: &[u8]) -> Progress {
+        let mut message = Vec::new();
+        let mut records = Reader(records);
+        while let Some(header) = records.bytes(RECORD_HEADER_LEN) {
+            let len = u16::from_be_bytes(*array_ref![header, 3, 2]) as usize;
+            if header[0] != HANDSHAKE || header[1] != 3 || len > MAX_RECORD_LEN {
+                return Progress::Invalid;
+            }
+            // The last record can be partial, its bytes are enough if they complete the message.
+            let fragment = &records.0[..len.min(records.0.len())];
+            records.0 = &records.0[fragment.len()..];
+            message.extend_from_slice(fragment);
+
+            let mut message = Reader(&message);
+            let (message_type, body) = match (message.u8(), message.vector(3)) {
+                (Some(message_type), Some(body)) => (message_type, body),
+                _ => continue,
+            };
+            return match parse_hello(message_type, body) {
+                Some(hello) => Progress::Complete(hello),
+                None => Progress::Invalid,
+            };
+        }
+        Progress::Incomplete
+    }
+
+    fn parse_hello(message_type: u8, mut body: Reader) -> Option<Hello> {
+        let legacy_version = body.u16()?;
+        body.bytes(32)?;
+        body.vector(1)?;
+        match message_type {
+            1 => {
+                let mut hello = ClientHello {
+                    versions: vec![legacy_version],
+                    cipher_suites: body.u16_list(2)?,
+                    server_name: None,
+                    alpn: Vec::new(),
+                };
+                body.vector(1)?;
+                for (extension_type, mut data) in extensions(body)? {
+                    match extension_type {
+                        SERVER_NAME => {
+                            let mut names = data.vector(2)?;
+                            while !names.0.is_empty() {
+                                let name_type = names.u8()?;
+                                let name = names.text(2)?;
+                                if name_type == 0 {
+                                    hello.server_name.get_or_insert(name);
+                                }
+                            }
+                        }
+                        ALPN => {
+                            let mut protocols = data.vector(2)?;
+                            while !protocols.0.is_empty() {
+                                hello.alpn.push(protocols.text(1)?);
+                            }
+                        }
+                        SUPPORTED_VERSIONS => hello.versions = data.u16_list(1)?,
+                        _ => (),
+                    }
+                }
+                Some(Hello::Client(hello))
+            }
+            2 => {
+                let mut hello = ServerHello {
+                    version: legacy_version,
+                    cipher_suite: body.u16()?,
+                    alpn: None,
+                };
+                body.u8()?;
+                for (extension_type, mut data) in extensions(body)? {
+                    match extension_type {
+                        ALPN => hello.alpn = Some(data.vector(2)?.text(1)?),
+                        SUPPORTED_VERSIONS => hello.version = data.u16()?,
+                        _ => (),
+                    }
+                }
+                Some(Hello::Server(hello))
+            }
+            _ => None,
+        }
+    }
+
+    /// Type and data of the extensions at the end of a hello. They are optional before TLS 1.3.
+    fn extensions(mut body: Reader) -> Option<Vec<(u16, Reader)>> {
+        let mut extensions = Vec::new();
+        if body.0.is_empty() {
+            return Some(extensions);
+        }
+        let mut list = body.vector(2)?;
+        while !list.0.is_empty() {
+            extensions.push((list.u16()?, list.vector(2)?));
+        }
+        Some(extensions)
+    }
+}
+
+mod flow {
+    use super::handshake::{self, ClientHello, Hello, Progress, ServerHello};
+
+    use pmc_core::packet::Direction;
+
+    /// Records of one direction, kept until they contain its hello.
+    /// With TCP reassembly, the records are parsed from the stream instead.
+    #[derive(Default)]
+    struct HelloBuffer {
+        records: Vec<u8>,
+        /// The hello was found, or the direction does not start with a handshake.
+        done: bool,
+    }
+
+    #[derive(Default)]
+    pub struct TlsFlow {
+        ul_buffer: HelloBuffer,
+        dl_buffer: HelloBuffer,
+        pub client_hello: Option<ClientHello>,
+        pub server_hello: Option<ServerHello>,
+    }
+
+    impl TlsFlow {
+        /// Longest hello kept, with its record headers.
+        const MAX_HELLO_LEN: usize = 16384;
+
+        pub fn expects_hello(&self, direction: Direction) -> bool {
+            match direction {
+                Direction::Uplink => !self.ul_buffer.done,
+                Direction::Downlink => !self.dl_buffer.done,
+            }
+        }
+
+        /// Adds the payload to the records of its direction, parsing the hello once complete.
+        /// The segments are expected in order, as the analyzer does not see their sequence.
+        /// Retransmissions after the handshake are not analyzed.
+        pub fn add_records(&mut self, direction: Direction, payload: &[u8]) {
+            let buffer = match direction {
+                Direction::Uplink => &mut self.ul_buffer,
+                Direction::Downlink => &mut self.dl_buffer,
+            };
+            if buffer.done {
+                return;
+            }
+
+            buffer.records.extend_from_slice(payload);
+            let progress = match buffer.records.len() > Self::MAX_HELLO_LEN {
+                true => Progress::Invalid,
+                false => handshake::find_hello(&buffer.records),
+            };
+            self.finish_hello(direction, progress);
+        }
+
+        /// Parses the hello from the first message of the stream of its direction, reassembled up
+        /// to `depth` bytes.
+        pub fn parse_stream(&mut self, direction: Direction, stream: &[u8], depth: usize) {
+            if !self.expects_hello(direction) {
+                return;
+            }
+
+            let progress = match stream.len() > Self::MAX_HELLO_LEN {
+                true => Progress::Invalid,
+                false => match handshake::find_hello(stream) {
+                    // The stream does not grow after the depth.
+                    Progress::Incomplete if stream.len() >= depth => Progress::Invalid,
+                    progress => progress,
+                },
+            };
+            self.finish_hello(direction, progress);
+        }
+
+        /// Bytes allocated for the records kept.
+        pub fn heap_memory(&self) -> usize {
+            self.ul_buffer.records.capacity() + self.dl_buffer.records.capacity()
+        }
+
+        /// Keeps the hello found, or stops looking for it if the records are not valid.
+        fn finish_hello(&mut self, direction: Direction, progress: Progress) {
+            if let Progress::Incomplete = progress {
+                return;
+            }
+
+            let buffer = match direction {
+                Direction::Uplink => &mut self.ul_buffer,
+                Direction::Downlink => &mut self.dl_buffer,
+            };
+            *buffer = HelloBuffer {
+                done: true,
+                ..Default::default()
+            };
+            match progress {
+                Progress::Complete(Hello::Client(hello)) => self.client_hello = Some(hello),
+                Progress::Complete(Hello::Server(hello)) => self.server_hello = Some(hello),
+                _ => (),
+            }
+        }
+    }
+}
+
+pub mod expression {
+    use super::analyzer::TlsAnalyzer;
+    use super::flow::TlsFlow;
+    use super::TlsClassifier;
+
+    use crate::Config;
+
+    use pmc_core::base::expression_value::ExpressionValue;
+    use pmc_core::expression::Expr;
+    use pmc_core::expression_value;
+    use pmc_core::language::{Literal, Operator, ValueCall, ValueRegistry};
+
+    use std::fmt;
+
+    // The values are checked on the hellos kept by the flow, so they are granted by flow.
+    // A grant is only reviewed while a packet can complete one of the hellos.
+    // Only `Tls` is built with the macro: the other values are generic over their text, or
+    // write their number by name in their definition.
+
+    fn is_hello(packet: &TlsAnalyzer) -> bool {
+        packet.is_hello()
+    }
+
+    #[expression_value(
+        Tls,
+        classifier = TlsClassifier,
+        grant_by_flow,
+        break_grant = is_hello,
+        definition = "tls"
+    )]
+    pub fn tls(_packet: &TlsAnalyzer, flow: &TlsFlow) -> bool {
+        flow.client_hello.is_some() || flow.server_hello.is_some()
+    }
+
+    /// Server name sent by the client, ignoring the case.
+    #[derive(Debug)]
+    pub struct TlsSni<S = &'static str>(pub S);
+    impl<S> ExpressionValue<Config> for TlsSni<S>
+    where S: AsRef<str> + fmt::Debug + Send + Sync + 'static
+    {
+        type Classifier = TlsClassifier;
+
+        const SHOULD_GRANT_BY_FLOW: bool = true;
+
+        fn should_break_grant(&self, packet: &TlsAnalyzer) -> bool {
+            packet.is_hello()
+        }
+
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("tls.sni").with_comparison(Operator::Equal, self.0.as_ref()))
+        }
+
+        fn check(&self, _packet: &TlsAnalyzer, flow: &TlsFlow) -> bool {
+            let name = flow
+                .client_hello
+                .as_ref()
+                .and_then(|hello| hello.server_name.as_ref());
+            name.is_some_and(|name| name.eq_ignore_ascii_case(self.0.as_ref()))
+        }
+    }
+
+    /// Server name sent by the client that is the domain or one of its subdomains,
+    /// ignoring the case.
+    #[derive(Debug)]
+    pub struct TlsSniSuffix<S = &'static str>(pub S);
+    impl<S> ExpressionValue<Config> for TlsSniSuffix<S>
+    where S: AsRef<str> + fmt::Debug + Send + Sync + 'static
+    {
+        type Classifier = TlsClassifier;
+
+        const SHOULD_GRANT_BY_FLOW: bool = true;
+
+        fn should_break_grant(&self, packet: &TlsAnalyzer) -> bool {
+            packet.is_hello()
+        }
+
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("tls.sni").with_comparison(Operator::Match, self.0.as_ref()))
+        }
+
+        fn check(&self, _packet: &TlsAnalyzer, flow: &TlsFlow) -> bool {
+            let domain = self.0.as_ref().as_bytes();
+            let name = flow
+                .client_hello
+                .as_ref()
+                .and_then(|hello| hello.server_name.as_ref());
+            name.map(String::as_bytes).is_some_and(|name| {
+                name.len() >= domain.len()
+                    && name[name.len() - domain.len()..].eq_ignore_ascii_case(domain)
+                    && (name.len() == domain.len() || name[name.len() - domain.len() - 1] == b'.')
+            })
+        }
+    }
+
+    /// Protocol selected by the server.
+    #[derive(Debug)]
+    pub struct TlsAlpn<S = &'static str>(pub S);
+    impl<S> ExpressionValue<Config> for TlsAlpn<S>
+    where S: AsRef<str> + fmt::Debug + Send + Sync + 'static
+    {
+        type Classifier = TlsClassifier;
+
+        const SHOULD_GRANT_BY_FLOW: bool = true;
+
+        fn should_break_grant(&self, packet: &TlsAnalyzer) -> bool {
+            packet.is_hello()
+        }
+
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("tls.alpn").with_comparison(Operator::Equal, self.0.as_ref()))
+        }
+
+        fn check(&self, _packet: &TlsAnalyzer, flow: &TlsFlow) -> bool {
+            let protocol = flow
+                .server_hello
+                .as_ref()
+                .and_then(|hello| hello.alpn.as_ref());
+            protocol.is_some_and(|protocol| protocol == self.0.as_ref())
+        }
+    }
+
+    /// Protocol offered by the client, among others.
+    #[derive(Debug)]
+    pub struct TlsOfferedAlpn<S = &'static str>(pub S);
+    impl<S> ExpressionValue<Config> for TlsOfferedAlpn<S>
+    where S: AsRef<str> + fmt::Debug + Send + Sync + 'static
+    {
+        type Classifier = TlsClassifier;
+
+        const SHOULD_GRANT_BY_FLOW: bool = true;
+
+        fn should_break_grant(&self, packet: &TlsAnalyzer) -> bool {
+            packet.is_hello()
+        }
+
+        fn definition(&self) -> Option<ValueCall> {
+            let protocol = self.0.as_ref();
+            Some(ValueCall::new("tls.offered_alpn").with_comparison(Operator::Equal, protocol))
+        }
+
+        fn check(&self, _packet: &TlsAnalyzer, flow: &TlsFlow) -> bool {
+            flow.client_hello.as_ref().is_some_and(|hello| {
+                hello
+                    .alpn
+                    .iter()
+                    .any(|protocol| protocol == self.0.as_ref())
+            })
+        }
+    }
+
+    const VERSIONS: &[(&str, u16)] = &[
+        ("ssl3", 0x0300),
+        ("1.0", 0x0301),
+        ("1.1", 0x0302),
+        ("1.2", 0x0303),
+        ("1.3", 0x0304),
+    ];
+
+    fn version_literal(version: u16) -> Literal {
+        match VERSIONS.iter().find(|(_, known)| *known == version) {
+            Some((name, _)) => Literal::Str(name.to_string()),
+            None => Literal::Int(version.into()),
+        }
+    }
+
+    /// Version selected by the server.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TlsVersion(pub u16);
+    impl TlsVersion {
+        pub const TLS_1_2: Self = Self(0x0303);
+        pub const TLS_1_3: Self = Self(0x0304);
+    }
+    impl ExpressionValue<Config> for TlsVersion {
+        type Classifier = TlsClassifier;
+
+        const SHOULD_GRANT_BY_FLOW: bool = true;
+
+        fn should_break_grant(&self, packet: &TlsAnalyzer) -> bool {
+            packet.is_hello()
+        }
+
+        fn definition(&self) -> Option<ValueCall> {
+            let version = version_literal(self.0);
+            Some(ValueCall::new("tls.version").with_comparison(Operator::Equal, version))
+        }
+
+        fn check(&self, _packet: &TlsAnalyzer, flow: &TlsFlow) -> bool {
+            flow.server_hello
+                .as_ref()
+                .is_some_and(|hello| hello.version == self.0)
+        }
+    }
+
+    /// Version supported by the client, among others.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TlsOfferedVersion(pub u16);
+    impl ExpressionValue<Config> for TlsOfferedVersion {
+        type Classifier = TlsClassifier;
+
+        const SHOULD_GRANT_BY_FLOW: bool = true;
+
+        fn should_break_grant(&self, packet: &TlsAnalyzer) -> bool {
+            packet.is_hello()
+        }
+
+        fn definition(&self) -> Option<ValueCall> {
+            let version = version_literal(self.0);
+            Some(ValueCall::new("tls.offered_version").with_comparison(Operator::Equal, version))
+        }
+
+        fn check(&self, _packet: &TlsAnalyzer, flow: &TlsFlow) -> bool {
+            flow.client_hello
+                .as_ref()
+                .is_some_and(|hello| hello.versions.contains(&self.0))
+        }
+    }
+
+    /// Cipher suite selected by the server, by its IANA number.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TlsCipherSuite(pub u16);
+    impl ExpressionValue<Config> for TlsCipherSuite {
+        type Classifier = TlsClassifier;
+
+        const SHOULD_GRANT_BY_FLOW: bool = true;
+
+        fn should_break_grant(&self, packet: &TlsAnalyzer) -> bool {
+            packet.is_hello()
+        }
+
+        fn definition(&self) -> Option<ValueCall> {
+            let suite = format!("0x{:04x}", self.0);
+            Some(ValueCall::new("tls.cipher_suite").with_comparison(Operator::Equal, suite))
+        }
+
+        fn check(&self, _packet: &TlsAnalyzer, flow: &TlsFlow) -> bool {
+            flow.server_hello
+                .as_ref()
+                .is_some_and(|hello| hello.cipher_suite == self.0)
+        }
+    }
+
+    /// Cipher suite offered by the client, among others.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TlsOfferedCipherSuite(pub u16);
+    impl ExpressionValue<Config> for TlsOfferedCipherSuite {
+        type Classifier = TlsClassifier;
+
+        const SHOULD_GRANT_BY_FLOW: bool = true;
+
+        fn should_break_grant(&self, packet: &TlsAnalyzer) -> bool {
+            packet.is_hello()
+        }
+
+        fn definition(&self) -> Option<ValueCall> {
+            let suite = format!("0x{:04x}", self.0);
+            Some(ValueCall::new("tls.offered_cipher_suite").with_comparison(Operator::Equal, suite))
+        }
+
+        fn check(&self, _packet: &TlsAnalyzer, flow: &TlsFlow) -> bool {
+            flow.client_hello
+                .as_ref()
+                .is_some_and(|hello| hello.cipher_suites.contains(&self.0))
+        }
+    }
+
+    fn text(call: &ValueCall) -> Result<String, String> {
+        call.expect_args(0)?;
+        Ok(call.compared_by(Operator::Equal)?.as_str()?.to_string())
+    }
+
+    /// A version by number or by name, like `"1.3"`.
+    fn version(call: &ValueCall) -> Result<u16, String> {
+        call.expect_args(0)?;
+        let value = call.compared_by(Operator::Equal)?;
+        if let Ok(version) = value.as_u16() {
+            return Ok(version);
+        }
+        let name = value.as_text()?;
+        VERSIONS
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .map(|&(_, version)| version)
+            .ok_or_else(|| format!("Unknown TLS version '{}'", name))
+    }
+
+    /// A cipher suite by number or by hexadecimal text, like `"0x1301"`.
+    fn cipher_suite(call: &ValueCall) -> Result<u16, String> {
+        call.expect_args(0)?;
+        let value = call.compared_by(Operator::Equal)?;
+        if let Ok(suite) = value.as_u16() {
+            return Ok(suite);
+        }
+        let text = value.as_str()?;
+        text.strip_prefix("0x")
+            .and_then(|digits| u16::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| format!("'{}' is not a cipher suite", text))
+    }
+
+    pub(crate) fn register(registry: ValueRegistry<Config>) -> ValueRegistry<Config> {
+        registry
+            .with("tls", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(Tls))
+            })
+            .with("tls.sni", |call| {
+                call.expect_args(0)?;
+                let (operator, name) = call.comparison()?;
+                let name = name.as_str()?.to_string();
+                match operator {
+                    Operator::Equal => Ok(Expr::value(TlsSni(name))),
+                    Operator::Match => Ok(Expr::value(TlsSniSuffix(name))),
+                    _ => Err("Expected the operator == or ~".to_string()),
+                }
+            })
+            .with("tls.alpn", |call| Ok(Expr::value(TlsAlpn(text(call)?))))
+            .with("tls.offered_alpn", |call| Ok(Expr::value(TlsOfferedAlpn(text(call)?))))
+            .with("tls.version", |call| Ok(Expr::value(TlsVersion(version(call)?))))
+            .with("tls.offered_version", |call| Ok(Expr::value(TlsOfferedVersion(version(call)?))))
+            .with("tls.cipher_suite", |call| Ok(Expr::value(TlsCipherSuite(cipher_suite(call)?))))
+            .with("tls.offered_cipher_suite", |call| {
+                Ok(Expr::value(TlsOfferedCipherSuite(cipher_suite(call)?)))
+            })
+    }
+}
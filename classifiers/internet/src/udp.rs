@@ -12,7 +12,9 @@ mod analyzer {
 
     use crate::{ClassifierId, Config, FlowKind, FlowSignature};
 
-    use pmc_core::base::analyzer::{Analyzer, AnalyzerInfo, AnalyzerResult, UseFlow};
+    use pmc_core::base::analyzer::{
+        Analyzer, AnalyzerError, AnalyzerErrorKind, AnalyzerInfo, AnalyzerResult, UseFlow,
+    };
     use pmc_core::packet::{Direction, Packet};
 
     pub struct UdpAnalyzer<'a> {
@@ -21,6 +23,8 @@ mod analyzer {
     }
 
     impl<'a> UdpAnalyzer<'a> {
+        const HEADER_LEN: usize = 8;
+
         pub fn source_port(&self) -> u16 {
             u16::from_be_bytes(*array_ref![self.header, 0, 2])
         }
@@ -33,21 +37,30 @@ mod analyzer {
             self.payload_len
         }
 
-        fn expected_l7_classifier(_server_port: u16) -> ClassifierId {
-            ClassifierId::None
+        fn expected_l7_classifier(server_port: u16) -> ClassifierId {
+            match server_port {
+                53 => ClassifierId::Dns,
+                _ => ClassifierId::None,
+            }
         }
     }
 
     impl<'a> Analyzer<'a, Config> for UdpAnalyzer<'a> {
         const ID: ClassifierId = ClassifierId::Udp;
-        const PREV_ID: ClassifierId = ClassifierId::Ip;
+        const PREV_IDS: &'static [ClassifierId] = &[ClassifierId::Ip, ClassifierId::Icmp];
 
         type Flow = UdpFlow;
 
         fn update_flow_id(
             signature: &mut FlowSignature,
-            &Packet { data, direction }: &Packet,
-        ) -> UseFlow {
+            &Packet {
+                data, direction, ..
+            }: &Packet,
+        ) -> UseFlow<ClassifierId> {
+            if let Err(error) = AnalyzerError::check_len(Self::ID, data, Self::HEADER_LEN) {
+                return UseFlow::Abort(error);
+            }
+
             let (source, dest) = (
                 u16::from_be_bytes(*array_ref![data, 0, 2]),
                 u16::from_be_bytes(*array_ref![data, 2, 2]),
@@ -60,6 +73,7 @@ mod analyzer {
 
             signature.client_port = first;
             signature.server_port = second;
+            signature.fragment = None;
             signature.kind = FlowKind::Udp;
 
             UseFlow::Yes
@@ -67,14 +81,18 @@ mod analyzer {
 
         fn build(
             _config: &Config,
-            &Packet { data, direction }: &'a Packet,
+            &Packet {
+                data, direction, ..
+            }: &'a Packet,
             _flow: &UdpFlow,
         ) -> AnalyzerResult<Self, ClassifierId> {
-            let header_len = 8;
-            let payload_len = u16::from_be_bytes(*array_ref![data, 4, 2]) - header_len as u16;
+            AnalyzerError::check_len(Self::ID, data, Self::HEADER_LEN)?;
+            let payload_len = u16::from_be_bytes(*array_ref![data, 4, 2])
+                .checked_sub(Self::HEADER_LEN as u16)
+                .ok_or_else(|| AnalyzerError::new(Self::ID, AnalyzerErrorKind::Malformed, 4))?;
 
             let analyzer = Self {
-                header: &data[0..header_len],
+                header: &data[0..Self::HEADER_LEN],
                 payload_len,
             };
 
@@ -92,7 +110,7 @@ mod analyzer {
             Ok(AnalyzerInfo {
                 analyzer,
                 next_classifier_id: next_protocol,
-                bytes_parsed: header_len,
+                bytes_parsed: Self::HEADER_LEN,
             })
         }
 
@@ -113,58 +131,88 @@ pub mod expression {
     use crate::Config;
 
     use pmc_core::base::expression_value::ExpressionValue;
+    use pmc_core::expression::Expr;
+    use pmc_core::expression_value;
+    use pmc_core::language::{Operator, ValueCall, ValueRegistry};
 
     use std::fmt;
 
-    #[derive(Debug)]
-    pub struct Udp;
-    impl ExpressionValue<Config> for Udp {
-        type Classifier = UdpClassifier;
+    #[expression_value(Udp, classifier = UdpClassifier, grant_by_flow, definition = "udp")]
+    pub fn udp(_packet: &UdpAnalyzer, _flow: &UdpFlow) -> bool {
+        true
+    }
 
-        const SHOULD_GRANT_BY_FLOW: bool = true;
+    #[expression_value(
+        UdpSourcePort,
+        classifier = UdpClassifier,
+        grant_by_flow,
+        definition = "udp.source_port"
+    )]
+    pub fn udp_source_port(port: &u16, packet: &UdpAnalyzer, _flow: &UdpFlow) -> bool {
+        *port == packet.source_port()
+    }
 
-        fn check(&self, _packet: &UdpAnalyzer, _flow: &UdpFlow) -> bool {
-            true
-        }
+    #[expression_value(
+        UdpDestPort,
+        classifier = UdpClassifier,
+        grant_by_flow,
+        definition = "udp.dest_port"
+    )]
+    pub fn udp_dest_port(port: &u16, packet: &UdpAnalyzer, _flow: &UdpFlow) -> bool {
+        *port == packet.dest_port()
     }
 
-    #[derive(Debug)]
-    pub struct UdpSourcePort(pub u16);
-    impl ExpressionValue<Config> for UdpSourcePort {
+    // The values over a user function are written by hand, the macro does not take generics.
+    pub struct UdpPayloadLen<F>(pub F);
+    impl<F> fmt::Debug for UdpPayloadLen<F> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+            write!(f, "UdpPayloadLen(USER_FN)")
+        }
+    }
+    impl<F> ExpressionValue<Config> for UdpPayloadLen<F>
+    where F: Fn(u16) -> bool + Send + Sync + 'static
+    {
         type Classifier = UdpClassifier;
 
-        const SHOULD_GRANT_BY_FLOW: bool = true;
-
         fn check(&self, packet: &UdpAnalyzer, _flow: &UdpFlow) -> bool {
-            self.0 == packet.source_port()
+            self.0(packet.payload_len())
         }
     }
 
+    // The macro only writes definitions compared by `==`, not by the operator of the value.
+    /// Payload length compared with a fixed value.
+    /// Unlike [`UdpPayloadLen`], it can be stored as a rule definition.
     #[derive(Debug)]
-    pub struct UdpDestPort(pub u16);
-    impl ExpressionValue<Config> for UdpDestPort {
+    pub struct UdpPayloadLenCmp(pub Operator, pub u16);
+    impl ExpressionValue<Config> for UdpPayloadLenCmp {
         type Classifier = UdpClassifier;
 
-        const SHOULD_GRANT_BY_FLOW: bool = true;
+        fn definition(&self) -> Option<ValueCall> {
+            Some(ValueCall::new("udp.payload_len").with_comparison(self.0, self.1))
+        }
 
         fn check(&self, packet: &UdpAnalyzer, _flow: &UdpFlow) -> bool {
-            self.0 == packet.dest_port()
+            self.0.compare(packet.payload_len(), self.1)
         }
     }
 
-    pub struct UdpPayloadLen<F>(pub F);
-    impl<F> fmt::Debug for UdpPayloadLen<F> {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
-            write!(f, "UdpPayloadLen(USER_FN)")
-        }
+    fn port(call: &ValueCall) -> Result<u16, String> {
+        call.expect_args(0)?;
+        call.compared_by(Operator::Equal)?.as_u16()
     }
-    impl<F> ExpressionValue<Config> for UdpPayloadLen<F>
-    where F: Fn(u16) -> bool + 'static
-    {
-        type Classifier = UdpClassifier;
 
-        fn check(&self, packet: &UdpAnalyzer, _flow: &UdpFlow) -> bool {
-            self.0(packet.payload_len())
-        }
+    pub(crate) fn register(registry: ValueRegistry<Config>) -> ValueRegistry<Config> {
+        registry
+            .with("udp", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(Udp))
+            })
+            .with("udp.source_port", |call| Ok(Expr::value(UdpSourcePort(port(call)?))))
+            .with("udp.dest_port", |call| Ok(Expr::value(UdpDestPort(port(call)?))))
+            .with("udp.payload_len", |call| {
+                call.expect_args(0)?;
+                let (operator, len) = call.ordered_comparison()?;
+                Ok(Expr::value(UdpPayloadLenCmp(operator, len.as_u16()?)))
+            })
     }
 }
@@ -0,0 +1,115 @@
+use crate::Config;
+
+use pmc_core::base::classifier::Classifier;
+
+pub struct VlanClassifier;
+impl<'a> Classifier<'a, Config> for VlanClassifier {
+    type Analyzer = analyzer::VlanAnalyzer<'a>;
+}
+
+mod analyzer {
+    use super::flow::VlanFlow;
+
+    use crate::ethernet::ether_type_classifier;
+    use crate::{ClassifierId, Config};
+
+    use pmc_core::base::analyzer::{Analyzer, AnalyzerError, AnalyzerInfo, AnalyzerResult};
+    use pmc_core::packet::Packet;
+
+    /// All the stacked tags of the frame, as in QinQ.
+    pub struct VlanAnalyzer<'a> {
+        /// Tags of 4 bytes: the TCI followed by the type of the next tag or of the payload.
+        pub tags: &'a [u8],
+    }
+
+    impl<'a> VlanAnalyzer<'a> {
+        const TAG_LEN: usize = 4;
+
+        /// VLAN IDs from the outer to the inner tag.
+        pub fn ids(&self) -> impl Iterator<Item = u16> + 'a {
+            self.tags
+                .chunks_exact(Self::TAG_LEN)
+                .map(|tag| u16::from_be_bytes(*array_ref![tag, 0, 2]) & 0x0FFF)
+        }
+
+        /// Type of the payload after the inner tag.
+        pub fn ether_type(&self) -> u16 {
+            let len = self.tags.len();
+            u16::from_be_bytes(*array_ref![self.tags, len - 2, 2])
+        }
+    }
+
+    impl<'a> Analyzer<'a, Config> for VlanAnalyzer<'a> {
+        const ID: ClassifierId = ClassifierId::Vlan;
+        const PREV_IDS: &'static [ClassifierId] = &[ClassifierId::Link, ClassifierId::Ethernet];
+
+        type Flow = VlanFlow;
+
+        fn build(
+            _config: &Config,
+            &Packet { data, .. }: &'a Packet,
+            _flow: &VlanFlow,
+        ) -> AnalyzerResult<Self, ClassifierId> {
+            // A classifier can not follow itself, so every tag is parsed here.
+            let mut len = 0;
+            let next_classifier_id = loop {
+                len += Self::TAG_LEN;
+                AnalyzerError::check_len(Self::ID, data, len)?;
+                match ether_type_classifier(u16::from_be_bytes(*array_ref![data, len - 2, 2])) {
+                    ClassifierId::Vlan => continue,
+                    next_classifier_id => break next_classifier_id,
+                }
+            };
+
+            Ok(AnalyzerInfo {
+                analyzer: Self { tags: &data[..len] },
+                next_classifier_id,
+                bytes_parsed: len,
+            })
+        }
+    }
+}
+
+mod flow {
+    #[derive(Default)]
+    pub struct VlanFlow {}
+}
+
+pub mod expression {
+    use super::analyzer::VlanAnalyzer;
+    use super::flow::VlanFlow;
+    use super::VlanClassifier;
+
+    use crate::Config;
+
+    use pmc_core::expression::Expr;
+    use pmc_core::expression_value;
+    use pmc_core::language::{Operator, ValueRegistry};
+
+    #[expression_value(Vlan, classifier = VlanClassifier, grant_by_flow, definition = "vlan")]
+    pub fn vlan(_packet: &VlanAnalyzer, _flow: &VlanFlow) -> bool {
+        true
+    }
+
+    /// Frames with a tag of this VLAN, at any level of the stack.
+    #[expression_value(VlanId, classifier = VlanClassifier, grant_by_flow, definition = "vlan.id")]
+    pub fn vlan_id(id: &u16, packet: &VlanAnalyzer, _flow: &VlanFlow) -> bool {
+        packet.ids().any(|tag_id| tag_id == *id)
+    }
+
+    pub(crate) fn register(registry: ValueRegistry<Config>) -> ValueRegistry<Config> {
+        registry
+            .with("vlan", |call| {
+                call.expect_alone()?;
+                Ok(Expr::value(Vlan))
+            })
+            .with("vlan.id", |call| {
+                call.expect_args(0)?;
+                let id = call.compared_by(Operator::Equal)?.as_u16()?;
+                match id < 0x1000 {
+                    true => Ok(Expr::value(VlanId(id))),
+                    false => Err(format!("The VLAN ID {} does not fit in 12 bits", id)),
+                }
+            })
+    }
+}
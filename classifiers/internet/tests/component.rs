@@ -3,19 +3,47 @@ use util::capture::IpCapture;
 
 use internet::{
     self,
+    dns::expression::{Dns, DnsAnswerCountCmp, DnsAnswered, DnsQueryNameSuffix, DnsQueryType},
+    ethernet::expression::{EthSource, EtherType},
     http::expression::{HttpCode, HttpHeader, HttpMethod, HttpRequest, HttpResponse},
+    icmp::expression::{Icmp, IcmpCode, IcmpError, IcmpType},
+    ip::expression::{IpExtensionHeader, IpVersion},
+    mpls::expression::MplsLabel,
     tcp::expression::{
-        Tcp, TcpDestPort, TcpEstablished, TcpHandshake, TcpPayloadLen, TcpRetransmission,
-        TcpServerPort, TcpSourcePort, TcpTeardown,
+        Tcp, TcpDestPort, TcpEstablished, TcpFlag, TcpHandshake, TcpPayloadLen, TcpPayloadLenCmp,
+        TcpRetransmission, TcpServerPort, TcpSourcePort, TcpTeardown,
+    },
+    tls::expression::{
+        Tls, TlsOfferedAlpn, TlsOfferedCipherSuite, TlsSni, TlsSniSuffix, TlsVersion,
     },
     udp::expression::{UdpDestPort, UdpPayloadLen, UdpSourcePort},
-    Config,
+    vlan::expression::VlanId,
+    ClassifierId, Config,
 };
 
-use pmc_core::engine::Rule;
+use pmc_core::base::analyzer::{AnalyzerError, AnalyzerErrorKind};
+use pmc_core::base::config::FlowEviction;
+use pmc_core::engine::{ClassifierEngine, FlowEvent, FlowEventKind, Rule, RuleValueAction};
 use pmc_core::expression::Expr;
+use pmc_core::language::{self, Operator, ParseError, RuleDefinition};
+use pmc_core::loader::{ClassifierLoader, LoadError};
+use pmc_core::metrics::{DecisionMetrics, TrafficMetrics};
+use pmc_core::packet::{Direction, LinkType, Packet};
+use pmc_core::sharded_engine::ShardedClassifierEngine;
+use pmc_core::trace::{
+    AnalyzerTrace, ClassificationEnd, ClassificationTrace, GrantTrace, RuleResult, RuleTrace,
+    ValueResult, ValueTrace,
+};
 
+use pmc_testing::capture::{Capture, CapturedPacket};
 use pmc_testing::common::{self, CaptureData, TestConfig};
+use pmc_testing::fuzz::{self, FuzzConfig};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
 #[test]
 fn udp_echo() {
@@ -119,6 +147,184 @@ fn tcp_retransmission() {
     });
 }
 
+#[test]
+fn tcp_flow_expiration() {
+    let mut config = Config::default();
+    config.base.flow_timeouts = vec![(ClassifierId::Tcp, Duration::from_millis(100))];
+
+    common::run_classification_test(TestConfig {
+        loader: internet::loader(),
+        config,
+        rules: vec![
+            Rule::new("Handshake", Expr::value(TcpHandshake)),
+            Rule::new("Established", Expr::value(TcpEstablished)),
+            Rule::new("Tcp", Expr::value(Tcp)),
+        ],
+        captures: vec![CaptureData {
+            capture: IpCapture::open("tests/captures/ipv4-http-get.pcap"),
+            sections: vec![(1, 10)],
+        }],
+        // The server takes more than 100ms to answer the request, so the flow is restarted.
+        expected_classification: vec![
+            "Handshake",
+            "Handshake",
+            "Handshake",
+            "Established",
+            "Tcp",
+            "Tcp",
+            "Tcp",
+            "Tcp",
+            "Tcp",
+            "Tcp",
+        ],
+    });
+
+    // A timestamp of zero starts the clock, so the first sweep is due 100ms after it.
+    let mut config = Config::default();
+    config.base.flow_timeouts = vec![(ClassifierId::Udp, Duration::from_millis(100))];
+    let rules = vec![Rule::new("Udp", Expr::value(UdpDestPort(1)))];
+    let mut engine = ClassifierEngine::new(internet::loader(), config, rules).unwrap();
+    let capture = IpCapture::open("tests/captures/ipv4-udp-echo.pcap");
+    let first = capture.iter().next().unwrap().data.clone();
+    let mut second = first.clone();
+    second[20..22].copy_from_slice(&40000u16.to_be_bytes());
+    for (data, millis) in [(&first, 0), (&second, 50), (&second, 120)] {
+        engine.classify_packet(Packet {
+            timestamp: Some(Duration::from_millis(millis)),
+            ..Packet::new(data, Direction::Uplink)
+        });
+    }
+    assert_eq!(engine.flow_stats().expired, 1);
+}
+
+#[test]
+fn flow_pool_eviction() {
+    let mut config = Config::default();
+    config.base.max_flows = Some(1);
+
+    common::run_classification_test(TestConfig {
+        loader: internet::loader(),
+        config,
+        rules: vec![
+            Rule::new("GET", Expr::value(HttpMethod::Get)),
+            Rule::new("Handshake", Expr::value(TcpHandshake)),
+            Rule::new("Established", Expr::value(TcpEstablished)),
+            Rule::new("Tcp", Expr::value(Tcp)),
+        ],
+        captures: vec![CaptureData {
+            capture: IpCapture::open("tests/captures/ipv4-http-get.pcap"),
+            sections: vec![(1, 10)],
+        }],
+        // The HTTP flow exceeds the limit, as it can not evict the TCP flow of its own packet.
+        expected_classification: vec![
+            "Handshake",
+            "Handshake",
+            "Handshake",
+            "GET",
+            "Established",
+            "Established",
+            "Established",
+            "Tcp",
+            "Tcp",
+            "Tcp",
+        ],
+    });
+
+    // The handshake of the connection A is interleaved with new connections B and C, which
+    // differ in the client port. The third flow evicts B if A was used since, else A.
+    let capture = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+    let packets: Vec<_> = capture.iter().take(3).collect();
+    let with_client_port = |captured: &CapturedPacket, port: u16| {
+        let mut data = captured.data.clone();
+        let offset = if captured.uplink { 20 } else { 22 };
+        data[offset..offset + 2].copy_from_slice(&port.to_be_bytes());
+        data
+    };
+    let syn_b = with_client_port(packets[0], 40001);
+    let syn_c = with_client_port(packets[0], 40002);
+    let run = |config: Config| {
+        let rules = vec![
+            Rule::new("Handshake", Expr::value(TcpHandshake)),
+            Rule::new("Tcp", Expr::value(Tcp)),
+        ];
+        let mut engine = ClassifierEngine::new(internet::loader(), config, rules).unwrap();
+        let mut classify = |data: &[u8], uplink: bool| {
+            engine
+                .classify_packet(Packet::new(data, uplink.into()))
+                .rule_tag
+        };
+        classify(&packets[0].data, true);
+        classify(&syn_b, true);
+        classify(&packets[1].data, false);
+        classify(&syn_c, true);
+        let tag = classify(&packets[2].data, true);
+        (tag, engine.flow_stats())
+    };
+
+    let mut config = Config::default();
+    config.base.max_flows = Some(2);
+    let (tag, stats) = run(config.clone());
+    assert_eq!(tag, "Handshake");
+    assert_eq!((stats.active, stats.created, stats.evicted), (2, 3, 1));
+
+    config.base.flow_eviction = FlowEviction::OldestFirst;
+    let (tag, stats) = run(config);
+    assert_eq!(tag, "Tcp");
+    assert_eq!((stats.active, stats.created, stats.evicted), (2, 4, 2));
+
+    // The same limits by memory, with room for two TCP flows.
+    let (_, stats) = run(Config::default());
+    let flow_memory = stats.memory / stats.active;
+    let mut config = Config::default();
+    config.base.max_flow_memory = Some(2 * flow_memory);
+    let (tag, stats) = run(config);
+    assert_eq!(tag, "Handshake");
+    assert_eq!((stats.memory, stats.evicted), (2 * flow_memory, 1));
+}
+
+#[test]
+fn tcp_established_granted() {
+    let results = common::run_classification_test(TestConfig {
+        loader: internet::loader(),
+        config: Config::default(),
+        rules: vec![Rule::new("Established", Expr::value(TcpEstablished))],
+        captures: vec![CaptureData {
+            capture: IpCapture::open("tests/captures/ipv4-http-get.pcap"),
+            sections: vec![(1, 10)],
+        }],
+        expected_classification: vec![
+            "",
+            "",
+            "Established",
+            "Established",
+            "Established",
+            "Established",
+            "Established",
+            "",
+            "",
+            "",
+        ],
+    });
+
+    // The grant is broken by the FIN packet.
+    use RuleValueAction::*;
+    assert_eq!(
+        results.actions(),
+        vec![
+            Computed,
+            Computed,
+            ComputedAndCached,
+            Cached,
+            Cached,
+            Cached,
+            Cached,
+            Computed,
+            Computed,
+            Computed
+        ]
+    );
+}
+
 #[test]
 fn tcp_midflow() {
     common::run_classification_test(TestConfig {
@@ -163,6 +369,44 @@ fn http_request_response() {
     });
 }
 
+#[test]
+fn http_request_response_granted() {
+    let results = common::run_classification_test(TestConfig {
+        loader: internet::loader(),
+        config: Config::default(),
+        rules: vec![
+            Rule::new("REQ", Expr::value(HttpRequest)),
+            Rule::new("RES", Expr::value(HttpResponse)),
+            Rule::new("Tcp", Expr::value(Tcp)),
+        ],
+        captures: vec![CaptureData {
+            capture: IpCapture::open("tests/captures/ipv4-http-get.pcap"),
+            sections: vec![(1, 10)],
+        }],
+        expected_classification: vec![
+            "Tcp", "Tcp", "Tcp", "REQ", "Tcp", "RES", "Tcp", "Tcp", "Tcp", "Tcp",
+        ],
+    });
+
+    // The request grant is broken by the response start line.
+    use RuleValueAction::*;
+    assert_eq!(
+        results.actions(),
+        vec![
+            Computed,
+            Computed,
+            Computed,
+            ComputedAndCached,
+            Computed,
+            ComputedAndCached,
+            Computed,
+            Computed,
+            Computed,
+            Computed
+        ]
+    );
+}
+
 #[test]
 fn http_get() {
     common::run_classification_test(TestConfig {
@@ -188,3 +432,1853 @@ fn http_get() {
         ],
     });
 }
+
+#[test]
+fn compiled_rules() {
+    static PAYLOAD_CHECKS: AtomicUsize = AtomicUsize::new(0);
+    let payload = Expr::value(UdpPayloadLen(|len| {
+        PAYLOAD_CHECKS.fetch_add(1, Ordering::Relaxed);
+        len > 10
+    }));
+
+    common::run_classification_test(TestConfig {
+        loader: internet::loader(),
+        config: Config::default(),
+        rules: vec![
+            Rule::new("None", payload.clone() & Expr::value(UdpDestPort(1))),
+            Rule::new(
+                "Echo",
+                (Expr::value(UdpDestPort(12345)) | Expr::value(UdpSourcePort(12345)))
+                    & !!payload.clone(),
+            ),
+            Rule::new("Big", payload & Expr::value(UdpDestPort(1)) | Expr::value(IpVersion::V4)),
+        ],
+        captures: vec![CaptureData {
+            capture: IpCapture::open("tests/captures/ipv4-udp-echo.pcap"),
+            sections: vec![(1, 4)],
+        }],
+        expected_classification: vec!["Big", "Big", "Echo", "Echo"],
+    });
+
+    // The same value is shared by all the rules, so it is checked once per packet.
+    assert_eq!(PAYLOAD_CHECKS.load(Ordering::Relaxed), 4);
+}
+
+#[test]
+fn unordered_loader() {
+    common::run_classification_test(TestConfig {
+        loader: ClassifierLoader::default()
+            .with(internet::http::HttpHeaderClassifier)
+            .and_then(|loader| loader.with(internet::http::HttpStartLineClassifier))
+            .and_then(|loader| loader.with(internet::tcp::TcpClassifier))
+            .and_then(|loader| loader.with(internet::udp::UdpClassifier))
+            .and_then(|loader| loader.with(internet::ip::IpClassifier))
+            .and_then(|loader| loader.with(internet::link::LinkClassifier))
+            .unwrap(),
+        config: Config::default(),
+        rules: vec![
+            Rule::new("GET", Expr::value(HttpMethod::Get)),
+            Rule::new("Udp", Expr::value(UdpSourcePort(80))),
+            Rule::new("Tcp", Expr::value(Tcp)),
+        ],
+        captures: vec![CaptureData {
+            capture: IpCapture::open("tests/captures/ipv4-http-get.pcap"),
+            sections: vec![(1, 4)],
+        }],
+        expected_classification: vec!["Tcp", "Tcp", "Tcp", "GET"],
+    });
+}
+
+#[test]
+fn invalid_configuration() {
+    let duplicated = internet::loader().with(internet::tcp::TcpClassifier).err();
+    assert_eq!(
+        duplicated.map(|error| error.to_string()),
+        Some("classifier Tcp loaded twice".into())
+    );
+
+    // Without the TCP classifier, HTTP has no path from IP.
+    let without_tcp = || {
+        ClassifierLoader::default()
+            .with(internet::link::LinkClassifier)
+            .and_then(|loader| loader.with(internet::ip::IpClassifier))
+            .and_then(|loader| loader.with(internet::http::HttpStartLineClassifier))
+            .unwrap()
+    };
+    let engine = ClassifierEngine::<_, &str>::new(without_tcp(), Config::default(), vec![]);
+    assert_eq!(engine.err(), Some(LoadError::UnreachableClassifier(ClassifierId::HttpStartLine)));
+
+    // The analysis of every packet starts at the link classifier.
+    let without_link = ClassifierLoader::default()
+        .with(internet::ip::IpClassifier)
+        .unwrap();
+    let engine = ClassifierEngine::<_, &str>::new(without_link, Config::default(), vec![]);
+    assert_eq!(engine.err(), Some(LoadError::UnreachableClassifier(ClassifierId::Ip)));
+
+    let ip_only = || {
+        ClassifierLoader::default()
+            .with(internet::link::LinkClassifier)
+            .and_then(|loader| loader.with(internet::ip::IpClassifier))
+            .unwrap()
+    };
+    let rules = vec![
+        Rule::new("Ip", Expr::value(IpVersion::V4)),
+        Rule::new("Get", Expr::value(HttpMethod::Get)),
+    ];
+    let engine = ClassifierEngine::new(ip_only(), Config::default(), rules);
+    assert_eq!(engine.err(), Some(LoadError::MissingClassifier(1, ClassifierId::HttpStartLine)));
+
+    let rules = vec![Rule::new("", Expr::value(IpVersion::V4))];
+    let engine = ClassifierEngine::new(ip_only(), Config::default(), rules);
+    assert_eq!(engine.err(), Some(LoadError::ReservedTag(0)));
+
+    let rules = vec![Rule::new("Tcp", Expr::value(Tcp))];
+    let engine = ShardedClassifierEngine::new(2, ip_only, Config::default(), rules);
+    assert_eq!(engine.err(), Some(LoadError::MissingClassifier(0, ClassifierId::Tcp)));
+
+    // Lists of expressions can not be empty, at any depth.
+    let rules = vec![
+        Rule::new("Ip", Expr::all(vec![])),
+        Rule::new("V4", Expr::value(IpVersion::V4) & !Expr::any(vec![])),
+    ];
+    let engine = ClassifierEngine::new(ip_only(), Config::default(), rules);
+    assert_eq!(engine.err(), Some(LoadError::EmptyExpressionList(0)));
+    let rules = vec![Rule::new(
+        "V4",
+        Expr::value(IpVersion::V4) & !Expr::any(vec![]),
+    )];
+    let engine = ShardedClassifierEngine::new(2, ip_only, Config::default(), rules);
+    assert_eq!(engine.err(), Some(LoadError::EmptyExpressionList(0)));
+
+    // Rejected rule updates keep the current rules.
+    let rules = vec![Rule::new("Ip", Expr::value(IpVersion::V4))];
+    let mut engine = ClassifierEngine::new(ip_only(), Config::default(), rules).unwrap();
+    let error = engine
+        .insert_rule(0, Rule::new("Tcp", Expr::value(Tcp)))
+        .err();
+    assert_eq!(error, Some(LoadError::MissingClassifier(0, ClassifierId::Tcp)));
+    let error = engine
+        .replace_rule(0, Rule::new("", Expr::value(IpVersion::V6)))
+        .err();
+    assert_eq!(error, Some(LoadError::ReservedTag(0)));
+    let error = engine
+        .insert_rule(1, Rule::new("Any", Expr::any(vec![])))
+        .err();
+    assert_eq!(error, Some(LoadError::EmptyExpressionList(1)));
+    assert_eq!(engine.rule_tags(), vec!["Ip"]);
+}
+
+#[test]
+fn sharded_engine() {
+    // The captures were taken on different days, so the timestamps are not used to avoid
+    // expiring the flows when the packets are interleaved.
+    fn as_packet(captured: &CapturedPacket) -> Packet<'_> {
+        Packet::new(&captured.data, captured.uplink.into())
+    }
+
+    let rules = vec![
+        Rule::new("Handshake", Expr::value(TcpHandshake)),
+        Rule::new("Established", Expr::value(TcpEstablished)),
+        Rule::new("Teardown", Expr::value(TcpTeardown)),
+        Rule::new("MoreThan10Bytes", Expr::value(UdpPayloadLen(|len| len > 10))),
+        Rule::new("ToServer", Expr::value(UdpDestPort(12345))),
+    ];
+
+    let captures = [
+        IpCapture::open("tests/captures/ipv4-http-get.pcap"),
+        IpCapture::open("tests/captures/ipv6-http-get.pcap"),
+        IpCapture::open("tests/captures/ipv4-udp-echo.pcap"),
+    ];
+
+    let mut expected_classification = captures
+        .iter()
+        .map(|capture| {
+            let mut engine =
+                ClassifierEngine::new(internet::loader(), Config::default(), rules.clone())
+                    .unwrap();
+            capture
+                .iter()
+                .map(|captured| engine.classify_packet(as_packet(captured)).rule_tag)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut engine =
+        ShardedClassifierEngine::new(4, internet::loader, Config::default(), rules.clone())
+            .unwrap();
+
+    // Packets of the different captures are interleaved.
+    let mut dispatched = Vec::new();
+    let mut iterators = captures
+        .iter()
+        .map(|capture| capture.iter())
+        .collect::<Vec<_>>();
+    loop {
+        let mut finished = true;
+        for (index, iterator) in iterators.iter_mut().enumerate() {
+            if let Some(captured) = iterator.next() {
+                let packet = as_packet(captured);
+                let shard = engine.shard_of(&packet);
+                engine.dispatch_packet(packet);
+                dispatched.push((index, shard));
+                finished = false;
+            }
+        }
+        if finished {
+            break;
+        }
+    }
+
+    let stats = engine.flow_stats();
+    assert_eq!(stats.created, stats.active);
+
+    let metrics = engine.metrics();
+    assert_eq!(metrics.packets.packets, dispatched.len());
+    assert_eq!(metrics.flows, stats);
+
+    let new_rule = Rule::new("Tcp", Expr::value(Tcp));
+    engine.update_rules(move |engine| engine.insert_rule(0, new_rule.clone()).unwrap());
+    let packet = as_packet(captures[0].iter().next().unwrap());
+    engine.dispatch_packet(packet);
+    dispatched.push((0, engine.shard_of(&packet)));
+    expected_classification[0].push("Tcp");
+
+    let results = engine.finish();
+    assert_eq!(results.len(), dispatched.len());
+
+    let mut classification = vec![Vec::new(); captures.len()];
+    for (result, (capture_index, shard)) in results.iter().zip(&dispatched) {
+        assert_eq!(result.shard, *shard);
+        classification[*capture_index].push(result.result.rule_tag);
+    }
+
+    assert_eq!(classification, expected_classification);
+
+    // All the packets of each HTTP capture belong to the same connection.
+    // The UDP capture is not checked: it marks both directions as downlink, so they are two flows.
+    for capture_index in 0..2 {
+        let mut shards = dispatched
+            .iter()
+            .filter(|(index, _)| *index == capture_index)
+            .map(|(_, shard)| shard);
+        let first = shards.next().unwrap();
+        assert!(shards.all(|shard| shard == first));
+    }
+
+    // The fragments of a SYN are classified with the rest of the connection, so the reassembled
+    // SYN starts the handshake of its TCP flow.
+    let captured = captures[0].iter().collect::<Vec<_>>();
+    let mut packets = vec![
+        (ipv4_fragment(&captured[0].data, 0, 24, true), true),
+        (ipv4_fragment(&captured[0].data, 24, 40, false), true),
+    ];
+    packets.extend(
+        captured[1..3]
+            .iter()
+            .map(|captured| (captured.data.clone(), captured.uplink)),
+    );
+
+    let mut engine =
+        ShardedClassifierEngine::new(4, internet::loader, Config::default(), rules.clone())
+            .unwrap();
+    let mut shards = Vec::new();
+    for (data, uplink) in &packets {
+        let packet = Packet::new(data, (*uplink).into());
+        shards.push(engine.shard_of(&packet));
+        engine.dispatch_packet(packet);
+    }
+    assert!(shards.iter().all(|shard| *shard == shards[0]));
+
+    let tags = engine
+        .finish()
+        .iter()
+        .map(|result| result.result.rule_tag)
+        .collect::<Vec<_>>();
+    assert_eq!(tags, vec!["", "Handshake", "Handshake", "Handshake"]);
+
+    // More packets than the shard and results queues hold together, without receiving results:
+    // the engine keeps the results while the shard queue is full.
+    let mut engine =
+        ShardedClassifierEngine::new(1, internet::loader, Config::default(), rules).unwrap();
+    let packet = as_packet(captures[2].iter().next().unwrap());
+    for _ in 0..10_000 {
+        engine.dispatch_packet(packet);
+    }
+    assert_eq!(engine.metrics().packets.packets, 10_000);
+    assert!(engine.try_recv_result().is_some());
+    assert_eq!(engine.finish().len(), 9_999);
+}
+
+#[test]
+fn multi_label() {
+    use RuleValueAction::*;
+
+    let rules = vec![
+        Rule::new("Established", Expr::value(TcpEstablished)),
+        Rule::new("example.com", Expr::value(HttpHeader("Host", "example.com"))),
+        Rule::new("Get", Expr::value(HttpMethod::Get)),
+        Rule::new("Tcp", Expr::value(Tcp)),
+    ];
+
+    let capture = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+    let mut engine = ClassifierEngine::new(internet::loader(), Config::default(), rules).unwrap();
+    let classification = capture
+        .iter()
+        .map(|captured| {
+            let packet = captured.as_packet();
+            engine
+                .classify_packet_all(packet)
+                .matches
+                .into_iter()
+                .map(|rule_match| (rule_match.rule_tag, rule_match.rule_value_action))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        classification,
+        vec![
+            vec![("Tcp", Computed)],
+            vec![("Tcp", Computed)],
+            vec![("Established", ComputedAndCached), ("Tcp", Computed)],
+            vec![
+                ("Established", Cached),
+                ("example.com", Computed),
+                ("Get", Computed),
+                ("Tcp", Computed),
+            ],
+            vec![("Established", Cached), ("Tcp", Computed)],
+            vec![("Established", Cached), ("Tcp", Computed)],
+            vec![("Established", Cached), ("Tcp", Computed)],
+            vec![("Tcp", Computed)],
+            vec![("Tcp", Computed)],
+            vec![("Tcp", Computed)],
+        ]
+    );
+}
+
+#[test]
+fn runtime_rule_updates() {
+    use RuleValueAction::*;
+
+    let capture = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+    let mut packets = capture.iter().map(|captured| captured.as_packet());
+
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        Config::default(),
+        vec![
+            Rule::new("S8080", Expr::value(TcpServerPort(8080))),
+            Rule::new("Established", Expr::value(TcpEstablished)),
+        ],
+    )
+    .unwrap();
+
+    let mut classify = |engine: &mut ClassifierEngine<Config, &'static str>| {
+        let result = engine.classify_packet(packets.next().unwrap());
+        (result.rule_tag, result.rule_value_action)
+    };
+
+    assert_eq!(classify(&mut engine), ("", Computed));
+    assert_eq!(classify(&mut engine), ("", Computed));
+    assert_eq!(classify(&mut engine), ("Established", ComputedAndCached));
+
+    // Removing a rule with more priority keeps the grant.
+    engine.remove_rule(0);
+    assert_eq!(classify(&mut engine), ("Established", Cached));
+
+    // A new rule with more priority was never checked, so the grant is lost.
+    engine
+        .insert_rule(0, Rule::new("Get", Expr::value(HttpMethod::Get)))
+        .unwrap();
+    assert_eq!(classify(&mut engine), ("Established", Computed));
+
+    engine.move_rule(0, 1);
+    assert_eq!(engine.rule_tags(), vec!["Established", "Get"]);
+    assert_eq!(classify(&mut engine), ("Established", ComputedAndCached));
+
+    // Replacing a rule with less priority keeps the grant.
+    engine
+        .replace_rule(1, Rule::new("Tcp", Expr::value(Tcp)))
+        .unwrap();
+    assert_eq!(classify(&mut engine), ("Established", Cached));
+
+    // Replacing the granted rule removes its grant.
+    engine
+        .replace_rule(0, Rule::new("AnyTcp", Expr::value(Tcp)))
+        .unwrap();
+    assert_eq!(classify(&mut engine), ("AnyTcp", ComputedAndCached));
+}
+
+#[test]
+fn analyzer_errors() {
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        Config::default(),
+        vec![Rule::new("Get", Expr::value(HttpMethod::Get))],
+    )
+    .unwrap();
+
+    // IP version 5 does not exist.
+    let mut data = vec![0; 40];
+    data[0] = 0x50;
+    let result = engine.classify_packet(Packet::new(&data, Direction::Uplink));
+    assert_eq!(result.rule_tag, "");
+    assert_eq!(
+        result.abort,
+        Some(AnalyzerError::new(ClassifierId::Ip, AnalyzerErrorKind::UnsupportedVersion, 0))
+    );
+
+    // The HTTP request of a new flow replaced by a payload without start line.
+    let capture = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+    let request = capture.iter().find(|captured| captured.id == 4).unwrap();
+    let ip_len = ((request.data[0] & 0x0F) as usize) << 2;
+    let headers_len = ip_len + ((request.data[ip_len + 12] >> 4) as usize) * 4;
+    let mut data = request.data[..headers_len].to_vec();
+    data.extend_from_slice(b"NOT-HTTP");
+
+    let result = engine.classify_packet(Packet::new(&data, request.uplink.into()));
+    assert_eq!(result.rule_tag, "");
+    assert_eq!(
+        result.abort,
+        Some(AnalyzerError::new(
+            ClassifierId::HttpStartLine,
+            AnalyzerErrorKind::Malformed,
+            headers_len
+        ))
+    );
+
+    // The same packet classified by all the rules.
+    let result = engine.classify_packet_all(Packet::new(&data, request.uplink.into()));
+    assert!(result.matches.is_empty());
+    assert_eq!(result.abort.map(|error| error.kind), Some(AnalyzerErrorKind::Malformed));
+
+    let result = engine.classify_packet(Packet::new(&request.data, request.uplink.into()));
+    assert_eq!(result.rule_tag, "Get");
+    assert_eq!(result.abort, None);
+}
+
+#[test]
+fn engine_metrics() {
+    let capture = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        Config::default(),
+        vec![
+            Rule::new("Established", Expr::value(TcpEstablished)),
+            Rule::new("Get", Expr::value(HttpMethod::Get)),
+        ],
+    )
+    .unwrap();
+
+    let mut packet_bytes = 0;
+    for captured in capture.iter() {
+        packet_bytes += captured.data.len();
+        engine.classify_packet(captured.as_packet());
+    }
+    engine.classify_packet(Packet::new(&[0x50; 20], Direction::Uplink));
+
+    let metrics = engine.metrics();
+    assert_eq!(metrics.packets.packets, 11);
+    assert_eq!(metrics.packets.bytes, packet_bytes + 20);
+    assert_eq!(metrics.rules[0].tag, "Established");
+    assert_eq!(metrics.rules[0].matches(), 5);
+    assert_eq!(metrics.rules[1].matches(), 0);
+    assert_eq!(
+        metrics.decisions,
+        DecisionMetrics {
+            computed: 0,
+            computed_and_cached: 1,
+            cached: 4,
+        }
+    );
+    assert_eq!(metrics.tag("Established").packets, 5);
+    assert_eq!(metrics.tag("").packets, 6);
+    assert_eq!(metrics.tag("Get"), TrafficMetrics::default());
+    assert_eq!(metrics.analyzer_builds[&ClassifierId::Ip], 10);
+    assert_eq!(metrics.analyzer_builds[&ClassifierId::Tcp], 10);
+    // The HTTP analyzers are not needed while the flow is granted.
+    assert!(!metrics
+        .analyzer_builds
+        .contains_key(&ClassifierId::HttpStartLine));
+    assert_eq!(metrics.aborts[&(ClassifierId::Ip, AnalyzerErrorKind::UnsupportedVersion)], 1);
+    assert_eq!(metrics.aborts_by_classifier(ClassifierId::Ip), 1);
+    assert_eq!(metrics.flows.created, 1);
+
+    // The rule counters follow the rules.
+    engine.move_rule(0, 1);
+    assert_eq!(engine.metrics().rules[1].matches(), 5);
+
+    engine.reset_metrics();
+    let metrics = engine.metrics();
+    assert_eq!(metrics.packets, TrafficMetrics::default());
+    assert_eq!(metrics.rules[1].matches(), 0);
+    assert!(metrics.tags.is_empty());
+    assert!(metrics.aborts.is_empty());
+    assert_eq!(metrics.flows.created, 1);
+}
+
+#[test]
+fn explained_classification() {
+    let capture = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+    let packets = capture
+        .iter()
+        .map(|captured| captured.as_packet())
+        .collect::<Vec<_>>();
+
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        Config::default(),
+        vec![
+            Rule::new("Get", Expr::value(HttpMethod::Get) & !Expr::value(TcpTeardown)),
+            Rule::new("Established", Expr::value(TcpEstablished)),
+        ],
+    )
+    .unwrap();
+
+    // SYN packet: without payload there is no HTTP analysis.
+    // The TCP value is checked first, it needs less analysis.
+    let (_, trace) = engine.classify_packet_explained(packets[0]);
+    assert_eq!(
+        trace,
+        ClassificationTrace {
+            rules: vec![
+                RuleTrace {
+                    priority: 0,
+                    tag: "Get",
+                    values: vec![
+                        ValueTrace {
+                            classifier_id: ClassifierId::Tcp,
+                            value: "TcpTeardown".into(),
+                            result: ValueResult::False,
+                        },
+                        ValueTrace {
+                            classifier_id: ClassifierId::HttpStartLine,
+                            value: "Get".into(),
+                            result: ValueResult::Unreachable,
+                        },
+                    ],
+                    result: RuleResult::NotMatched,
+                },
+                RuleTrace {
+                    priority: 1,
+                    tag: "Established",
+                    values: vec![ValueTrace {
+                        classifier_id: ClassifierId::Tcp,
+                        value: "TcpEstablished".into(),
+                        result: ValueResult::False,
+                    }],
+                    result: RuleResult::NotMatched,
+                },
+            ],
+            analyzers: vec![
+                AnalyzerTrace {
+                    classifier_id: ClassifierId::Link,
+                    bytes_parsed: 0,
+                    flow: false,
+                },
+                AnalyzerTrace {
+                    classifier_id: ClassifierId::Ip,
+                    bytes_parsed: 20,
+                    flow: false,
+                },
+                AnalyzerTrace {
+                    classifier_id: ClassifierId::Tcp,
+                    bytes_parsed: 40,
+                    flow: true,
+                },
+            ],
+            grants: vec![],
+            end: ClassificationEnd::NoMatch,
+        }
+    );
+
+    engine.classify_packet(packets[1]);
+    engine.classify_packet(packets[2]);
+
+    let (result, trace) = engine.classify_packet_explained(packets[3]);
+    assert_eq!(result.rule_tag, "Get");
+    assert_eq!(trace.rules.len(), 1);
+    assert_eq!(
+        trace
+            .rule(0)
+            .unwrap()
+            .values
+            .iter()
+            .map(|value| value.result)
+            .collect::<Vec<_>>(),
+        vec![ValueResult::False, ValueResult::True]
+    );
+    assert_eq!(trace.rule(0).unwrap().result, RuleResult::Matched(RuleValueAction::Computed));
+    assert_eq!(
+        trace.analyzers.last(),
+        Some(&AnalyzerTrace {
+            classifier_id: ClassifierId::HttpStartLine,
+            bytes_parsed: 16,
+            flow: true,
+        })
+    );
+    assert_eq!(trace.end, ClassificationEnd::Matched(0));
+
+    // A rule granted by the flow.
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        Config::default(),
+        vec![Rule::new("Established", Expr::value(TcpEstablished))],
+    )
+    .unwrap();
+    for packet in &packets[..3] {
+        engine.classify_packet(*packet);
+    }
+
+    let (_, trace) = engine.classify_packet_explained(packets[3]);
+    assert_eq!(
+        trace.grants,
+        vec![GrantTrace {
+            classifier_id: ClassifierId::Tcp,
+            rule: 0,
+            kept: true,
+        }]
+    );
+    assert_eq!(trace.rule(0).unwrap().values[0].result, ValueResult::Granted(0));
+    assert_eq!(trace.rule(0).unwrap().result, RuleResult::Stopped);
+    assert_eq!(trace.end, ClassificationEnd::Granted(0));
+
+    // An aborted analysis.
+    let (result, trace) =
+        engine.classify_packet_explained(Packet::new(&[0x50; 20], Direction::Uplink));
+    assert_eq!(trace.rule(0).unwrap().values[0].result, ValueResult::Aborted);
+    // Only the link analyzer parsed the packet, without bytes.
+    assert_eq!(trace.analyzers.len(), 1);
+    assert_eq!(trace.analyzers[0].classifier_id, ClassifierId::Link);
+    assert_eq!(trace.end, ClassificationEnd::Aborted(result.abort.unwrap()));
+}
+
+#[test]
+fn malformed_packets() {
+    let rules = r#"
+        Get: http.method == GET && http.header("Host") ~ "example.com"
+        200OK: http.code == 200 || http.response && http.header("Content-Type") ~ "text"
+        Established: tcp.established && tcp.payload_len > 0
+        Teardown: tcp.teardown || tcp.retransmission || tcp.flag(RST)
+        Udp: udp.payload_len >= 4 && udp.dest_port == 12345
+        Ip: ip.version == 6 || ip.proto == udp
+    "#;
+
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        Config::default(),
+        language::parse_rules(rules, &internet::registry()).unwrap(),
+    )
+    .unwrap();
+
+    let captures = [
+        IpCapture::open("tests/captures/ipv4-http-get.pcap"),
+        IpCapture::open("tests/captures/ipv6-http-get.pcap"),
+        IpCapture::open("tests/captures/ipv4-udp-echo.pcap"),
+    ];
+    let samples = captures
+        .iter()
+        .flat_map(|capture| capture.iter())
+        .collect::<Vec<_>>();
+
+    let result = fuzz::run_fuzz_test(&mut engine, &samples, &FuzzConfig::default());
+    assert!(result.aborted > 0);
+
+    // A SYN packet cut in the middle of the TCP header.
+    let result = engine.classify_packet(Packet::new(&samples[0].data[..30], Direction::Uplink));
+    assert_eq!(
+        result.abort,
+        Some(AnalyzerError::new(ClassifierId::Tcp, AnalyzerErrorKind::Truncated, 30))
+    );
+}
+
+#[test]
+fn packet_metadata() {
+    let classify = |snaplen: usize, use_wire_len: bool| {
+        let mut engine = ClassifierEngine::new(
+            internet::loader(),
+            Config::default(),
+            vec![
+                Rule::new("Retransmission", Expr::value(TcpRetransmission)),
+                Rule::new("Established", Expr::value(TcpEstablished)),
+                Rule::new("Tcp", Expr::value(Tcp)),
+            ],
+        )
+        .unwrap();
+
+        let capture = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+        capture
+            .iter()
+            .map(|captured| {
+                let len = captured.data.len().min(snaplen);
+                let packet = captured.as_packet();
+                let packet = Packet {
+                    data: &captured.data[..len],
+                    wire_len: if use_wire_len { packet.wire_len } else { len },
+                    ..packet
+                };
+                engine.classify_packet(packet).rule_tag
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // The payload not captured is still followed by the TCP sequence numbers,
+    // from the IP total length even if the wire length is not known.
+    let complete = classify(usize::MAX, true);
+    assert_eq!(classify(60, true), complete);
+    assert_eq!(classify(60, false), complete);
+
+    // The link type selects the first header.
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        Config::default(),
+        vec![Rule::new("Ip", Expr::value(IpVersion::V4))],
+    )
+    .unwrap();
+    let capture = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+    let captured = capture.iter().next().unwrap();
+    assert_eq!(captured.as_packet().link_type, LinkType::RawIp);
+    assert_eq!(engine.classify_packet(captured.as_packet()).rule_tag, "Ip");
+
+    let result = engine.classify_packet(
+        captured
+            .as_packet()
+            .with_link_type(LinkType::from_pcap(105)),
+    );
+    assert_eq!(
+        result.abort,
+        Some(AnalyzerError::new(ClassifierId::Link, AnalyzerErrorKind::Unsupported, 0))
+    );
+}
+
+#[test]
+fn text_rules() {
+    let rules = r#"
+        # HTTP traffic of example.com
+        GET: http.method == GET && http.header("Host") ~ "example.com"
+        200OK: http.code == 200 && http.header("Content-Type") ~ "text/html"
+        Tcp: tcp && !(tcp.server_port != 80) || udp.payload_len > 10
+    "#;
+
+    common::run_classification_test(TestConfig {
+        loader: internet::loader(),
+        config: Config::default(),
+        rules: language::parse_rules(rules, &internet::registry()).unwrap(),
+        captures: vec![CaptureData {
+            capture: IpCapture::open("tests/captures/ipv4-http-get.pcap"),
+            sections: vec![(1, 10)],
+        }],
+        expected_classification: vec![
+            "Tcp", "Tcp", "Tcp", "GET", "Tcp", "200OK", "Tcp", "Tcp", "Tcp", "Tcp",
+        ],
+    });
+}
+
+#[test]
+fn text_rules_errors() {
+    let registry = internet::registry();
+    let error = |line, column, message: &str| ParseError {
+        line,
+        column,
+        message: message.into(),
+    };
+
+    let parse_error = |source| language::parse_expr(source, &registry).err().unwrap();
+    assert_eq!(
+        parse_error("tcp.server_port == 80 &&\n  http.heder(\"Host\")"),
+        error(2, 3, "Unknown value 'http.heder'")
+    );
+    assert_eq!(
+        parse_error("tcp.server_port == 70000"),
+        error(1, 1, "The number 70000 does not fit in 16 bits")
+    );
+    assert_eq!(parse_error("(tcp || udp"), error(1, 12, "Expected ')', found the end"));
+    assert_eq!(parse_error("tcp & udp"), error(1, 5, "Expected '&&'"));
+    assert_eq!(
+        parse_error("http.header(\"Host\") == \"example.com\""),
+        error(1, 1, "'http.header' must be compared with '~', found '=='")
+    );
+
+    let rules_error = |source| language::parse_rules(source, &registry).err().unwrap();
+    assert_eq!(
+        rules_error("Tcp: tcp\n\n  Udp: udp.dest_port == \"53\""),
+        error(3, 8, "Expected a number, found \"53\"")
+    );
+    assert_eq!(rules_error("Tcp: tcp\nudp"), error(2, 1, "Expected 'tag: expression'"));
+    assert_eq!(
+        rules_error("Http: http.request http.response"),
+        error(1, 20, "Unexpected 'http', expected '&&' or '||'")
+    );
+    // Columns count characters, not bytes.
+    assert_eq!(
+        rules_error("\u{3000}Café: http.header(\"Ünïcode\") ~ \"ñ\" && htp"),
+        error(1, 40, "Unknown value 'htp'")
+    );
+}
+
+#[test]
+fn stored_rules() {
+    let rules: Vec<Rule<&str, Config>> = vec![
+        Rule::new(
+            "GET",
+            Expr::value(HttpMethod::Get) & Expr::value(HttpHeader("Host", "example.com")),
+        ),
+        Rule::new(
+            "200OK",
+            Expr::all(vec![
+                Expr::value(HttpCode("200")),
+                Expr::value(HttpHeader("Content-Type", "text/html")),
+                !Expr::value(TcpFlag::RST),
+            ]),
+        ),
+        Rule::new(
+            "Tcp",
+            Expr::value(IpVersion::V4) & Expr::value(TcpPayloadLenCmp(Operator::GreaterEqual, 0)),
+        ),
+    ];
+
+    let definitions = rules
+        .iter()
+        .map(|rule| rule.definition().unwrap())
+        .collect::<Vec<_>>();
+
+    let json = serde_json::to_string(&definitions).unwrap();
+    let from_json = serde_json::from_str::<Vec<RuleDefinition<&str>>>(&json).unwrap();
+    assert_eq!(from_json, definitions);
+
+    let toml = toml::to_string(&HashMap::from([("rules", &definitions)])).unwrap();
+    let from_toml = toml::from_str::<HashMap<String, Vec<RuleDefinition<String>>>>(&toml).unwrap();
+    assert_eq!(from_toml["rules"].len(), definitions.len());
+    for (from_toml, definition) in from_toml["rules"].iter().zip(&definitions) {
+        assert_eq!(from_toml.tag, definition.tag);
+        assert_eq!(from_toml.expr, definition.expr);
+    }
+
+    common::run_classification_test(TestConfig {
+        loader: internet::loader(),
+        config: Config::default(),
+        rules: internet::registry().build_rules(&from_json).unwrap(),
+        captures: vec![CaptureData {
+            capture: IpCapture::open("tests/captures/ipv4-http-get.pcap"),
+            sections: vec![(1, 10)],
+        }],
+        expected_classification: vec![
+            "Tcp", "Tcp", "Tcp", "GET", "Tcp", "200OK", "Tcp", "Tcp", "Tcp", "Tcp",
+        ],
+    });
+
+    // Values built from closures can not be stored.
+    let rule = Rule::new("Tcp", Expr::value(TcpPayloadLen(|len| len > 0)));
+    assert!(rule.definition().is_err());
+}
+
+#[test]
+fn flow_events() {
+    let mut config = Config::default();
+    config.base.max_flows = Some(1);
+
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        config,
+        vec![Rule::new("Established", Expr::value(TcpEstablished))],
+    )
+    .unwrap();
+
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let listener_events = events.clone();
+    engine.set_flow_listener(move |event: FlowEvent<_, _, _>| {
+        listener_events.borrow_mut().push(event)
+    });
+
+    let ipv4 = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+    let ipv6 = IpCapture::open("tests/captures/ipv6-http-get.pcap");
+    // The IPv6 packet has no timestamp, so the IPv4 flow is evicted instead of expired.
+    let packets = ipv4
+        .iter()
+        .take(10)
+        .map(|captured| captured.as_packet())
+        .chain(ipv6.iter().take(1).map(|captured| Packet {
+            timestamp: None,
+            ..captured.as_packet()
+        }));
+
+    let mut flow_ids = Vec::new();
+    let mut kinds = Vec::new();
+    for (index, packet) in packets.enumerate() {
+        engine.classify_packet(packet);
+        for event in events.borrow_mut().drain(..) {
+            assert_eq!(event.classifier_id, ClassifierId::Tcp);
+            flow_ids.push(event.flow_id);
+            kinds.push((index, event.kind, event.tag));
+        }
+    }
+
+    // The grant is broken by the FIN packet and the flow closed by the last ACK.
+    use FlowEventKind::*;
+    assert_eq!(
+        kinds,
+        vec![
+            (0, Created, ""),
+            (2, Classified, "Established"),
+            (2, GrantChanged(Some("Established")), "Established"),
+            (7, GrantChanged(None), "Established"),
+            (9, Closed, "Established"),
+            (10, Evicted, "Established"),
+            (10, Created, ""),
+        ]
+    );
+    assert!(flow_ids[..6].iter().all(|flow_id| *flow_id == flow_ids[0]));
+    assert_ne!(flow_ids[6], flow_ids[0]);
+
+    // Without listener the flows are not followed.
+    engine.remove_flow_listener();
+    engine.classify_packet(ipv4.iter().next().unwrap().as_packet());
+    assert!(events.borrow().is_empty());
+}
+
+#[test]
+fn tcp_reassembly() {
+    let capture = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+    let captured = capture.iter().collect::<Vec<_>>();
+
+    // The GET request is sent again split in segments with the payload from `start` to `end`.
+    // Only the segment that ends the request is pushed.
+    let request = &captured[3].data;
+    let headers_len = ((request[0] & 0x0F) as usize) * 4 + ((request[32] >> 4) as usize) * 4;
+    let seq_num = u32::from_be_bytes([request[24], request[25], request[26], request[27]]);
+    let segment = |start: usize, end: usize| {
+        let mut data = request[..headers_len].to_vec();
+        data[24..28].copy_from_slice(&(seq_num + start as u32).to_be_bytes());
+        if headers_len + end < request.len() {
+            data[33] &= !0x08;
+        }
+        data.extend_from_slice(&request[headers_len + start..headers_len + end]);
+        data
+    };
+
+    let classify = |depth: Option<usize>| {
+        let rules = r#"
+            Host: tcp.stream ~ "Host: example.com"
+            Tcp: tcp
+        "#;
+
+        let config = Config {
+            tcp_reassembly_depth: depth,
+            ..Config::default()
+        };
+        let mut engine = ClassifierEngine::new(
+            internet::loader(),
+            config,
+            language::parse_rules(rules, &internet::registry()).unwrap(),
+        )
+        .unwrap();
+
+        let handshake = captured[..3].iter().map(|captured| captured.as_packet());
+        // Out of order, and overlapping the received bytes.
+        let segments = [segment(20, 50), segment(0, 20), segment(10, 75)];
+        let segments = segments
+            .iter()
+            .map(|data| Packet::new(data, Direction::Uplink));
+        handshake
+            .chain(segments)
+            .map(|packet| engine.classify_packet(packet).rule_tag)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(classify(None), vec!["Tcp"; 6]);
+    assert_eq!(classify(Some(1024)), vec!["Tcp", "Tcp", "Tcp", "Tcp", "Host", "Host"]);
+    // The host is after the first 20 bytes of the stream.
+    assert_eq!(classify(Some(20)), vec!["Tcp"; 6]);
+
+    // The HTTP analyzers parse the headers split in two segments from the stream.
+    let classify_split = |depth: Option<usize>, fragmented: bool| {
+        let config = Config {
+            tcp_reassembly_depth: depth,
+            ..Config::default()
+        };
+        let rules = vec![
+            Rule::new("Host", Expr::value(HttpHeader("Host", "example.com"))),
+            Rule::new("Tcp", Expr::value(Tcp)),
+        ];
+        let mut engine = ClassifierEngine::new(internet::loader(), config, rules).unwrap();
+
+        let handshake = captured[..3].iter().map(|captured| captured.as_packet());
+        let mut segments = vec![segment(0, 25), segment(25, 75)];
+        if fragmented {
+            let last = segments.pop().unwrap();
+            segments.push(ipv4_fragment(&last, 0, 24, true));
+            segments.push(ipv4_fragment(&last, 24, last.len() - 20, false));
+        }
+        let segments = segments
+            .iter()
+            .map(|data| Packet::new(data, Direction::Uplink));
+        let tags = handshake
+            .chain(segments)
+            .map(|packet| engine.classify_packet(packet).rule_tag)
+            .collect::<Vec<_>>();
+        (tags, engine.flow_stats().memory)
+    };
+
+    // Each segment alone has incomplete headers, which abort the analysis.
+    let (tags, memory) = classify_split(None, false);
+    assert_eq!(tags, vec!["Tcp", "Tcp", "Tcp", "", ""]);
+    let (tags, stream_memory) = classify_split(Some(1024), false);
+    assert_eq!(tags, vec!["Tcp", "Tcp", "Tcp", "", "Host"]);
+    // The stream is counted in the flow memory.
+    assert!(stream_memory >= memory + 75);
+    // The segment is reassembled from its fragments before extending the stream.
+    let (tags, _) = classify_split(Some(1024), true);
+    assert_eq!(tags, vec!["Tcp", "Tcp", "Tcp", "", "", "Host"]);
+
+    // A second request on the connection is parsed from its start, not from the first request.
+    let second_request = String::from_utf8(request[headers_len..].to_vec())
+        .unwrap()
+        .replace("example.com", "example.org");
+    let mut second = request[..headers_len].to_vec();
+    let second_seq_num = seq_num + (request.len() - headers_len) as u32;
+    second[24..28].copy_from_slice(&second_seq_num.to_be_bytes());
+    second.extend_from_slice(second_request.as_bytes());
+
+    let config = Config {
+        tcp_reassembly_depth: Some(1024),
+        ..Config::default()
+    };
+    let rules = vec![
+        Rule::new("Com", Expr::value(HttpHeader("Host", "example.com"))),
+        Rule::new("Org", Expr::value(HttpHeader("Host", "example.org"))),
+    ];
+    let mut engine = ClassifierEngine::new(internet::loader(), config, rules).unwrap();
+    let tags = captured[..4]
+        .iter()
+        .map(|captured| captured.as_packet())
+        .chain([Packet::new(&second, Direction::Uplink)])
+        .map(|packet| engine.classify_packet(packet).rule_tag)
+        .collect::<Vec<_>>();
+    assert_eq!(tags, vec!["", "", "", "Com", "Org"]);
+}
+
+/// Copy of the IPv4 packet with the IP payload from `start` to `end`.
+fn ipv4_fragment(packet: &[u8], start: usize, end: usize, more: bool) -> Vec<u8> {
+    let mut data = packet[..20].to_vec();
+    data.extend_from_slice(&packet[20 + start..20 + end]);
+    let total_len = data.len() as u16;
+    data[2..4].copy_from_slice(&total_len.to_be_bytes());
+    let flags_offset = (start / 8) as u16 | if more { 0x2000 } else { 0 };
+    data[6..8].copy_from_slice(&flags_offset.to_be_bytes());
+    data
+}
+
+#[test]
+fn ip_fragments() {
+    fn ipv6_fragment(packet: &[u8], start: usize, end: usize, more: bool) -> Vec<u8> {
+        let mut data = packet[..40].to_vec();
+        data[6] = 44;
+        let offset_flags = start as u16 | more as u16;
+        data.extend_from_slice(&[packet[6], 0]);
+        data.extend_from_slice(&offset_flags.to_be_bytes());
+        data.extend_from_slice(&7u32.to_be_bytes());
+        data.extend_from_slice(&packet[40 + start..40 + end]);
+        let payload_len = (data.len() - 40) as u16;
+        data[4..6].copy_from_slice(&payload_len.to_be_bytes());
+        data
+    }
+
+    let classify = |config: Config, packets: &[Vec<u8>]| {
+        let mut engine = ClassifierEngine::new(
+            internet::loader(),
+            config,
+            vec![
+                Rule::new("ToServer", Expr::value(UdpDestPort(12345))),
+                Rule::new("Ipv4", Expr::value(IpVersion::V4)),
+            ],
+        )
+        .unwrap();
+
+        packets
+            .iter()
+            .map(|data| {
+                engine
+                    .classify_packet(Packet::new(data, Direction::Uplink))
+                    .rule_tag
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // The ports are only read from the complete datagram, received in any order.
+    let capture = IpCapture::open("tests/captures/ipv4-udp-echo.pcap");
+    let datagram = &capture.iter().nth(2).unwrap().data;
+    let fragments = vec![
+        ipv4_fragment(datagram, 16, 22, false),
+        ipv4_fragment(datagram, 8, 16, true),
+        ipv4_fragment(datagram, 0, 8, true),
+        ipv4_fragment(datagram, 8, 16, true),
+    ];
+    assert_eq!(classify(Config::default(), &fragments), vec!["Ipv4", "Ipv4", "ToServer", "Ipv4"]);
+
+    let config = Config {
+        ip_reassembly_max_len: 16,
+        ..Config::default()
+    };
+    assert_eq!(classify(config, &fragments), vec!["Ipv4"; 4]);
+
+    // The fragments of another datagram, with a different identification, do not fit in the
+    // flow memory with the first fragment, which is evicted.
+    let mut other = ipv4_fragment(datagram, 0, 16, true);
+    other[4..6].copy_from_slice(&0x4242u16.to_be_bytes());
+    let fragments = [
+        ipv4_fragment(datagram, 0, 8, true),
+        other,
+        ipv4_fragment(datagram, 8, 16, true),
+        ipv4_fragment(datagram, 16, 22, false),
+    ];
+    let run = |config: Config| {
+        let mut engine = ClassifierEngine::new(
+            internet::loader(),
+            config,
+            vec![Rule::new("ToServer", Expr::value(UdpDestPort(12345)))],
+        )
+        .unwrap();
+        fragments
+            .iter()
+            .map(|data| {
+                let tag = engine
+                    .classify_packet(Packet::new(data, Direction::Uplink))
+                    .rule_tag;
+                (tag, engine.flow_stats())
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let results = run(Config::default());
+    assert_eq!(results[3].0, "ToServer");
+    // The memory of each flow includes its fragments, so the second flow takes more memory.
+    let both_memory = results[1].1.memory;
+    assert!(both_memory > 2 * results[0].1.memory);
+
+    let mut config = Config::default();
+    config.base.max_flow_memory = Some(both_memory - 1);
+    let results = run(config);
+    assert_eq!(results[3].0, "");
+    assert_eq!(results[1].1.evicted, 1);
+    assert!(results[1].1.memory < both_memory);
+
+    // The reassembled SYN shares the TCP flow with the next packets.
+    let capture = IpCapture::open("tests/captures/ipv6-http-get.pcap");
+    let captured = capture.iter().collect::<Vec<_>>();
+    let mut packets = vec![
+        ipv6_fragment(&captured[0].data, 0, 24, true),
+        ipv6_fragment(&captured[0].data, 24, 40, false),
+    ];
+    packets.extend(captured[1..3].iter().map(|captured| captured.data.clone()));
+
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        Config::default(),
+        vec![
+            Rule::new("Established", Expr::value(TcpEstablished)),
+            Rule::new("Handshake", Expr::value(TcpHandshake)),
+            Rule::new("Ipv6", Expr::value(IpVersion::V6)),
+        ],
+    )
+    .unwrap();
+    let directions = [
+        Direction::Uplink,
+        Direction::Uplink,
+        Direction::Downlink,
+        Direction::Uplink,
+    ];
+    let tags = packets
+        .iter()
+        .zip(directions)
+        .map(|(data, direction)| {
+            engine
+                .classify_packet(Packet::new(data, direction))
+                .rule_tag
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(tags, vec!["Ipv6", "Handshake", "Handshake", "Established"]);
+    assert_eq!(engine.flow_stats().created, 2);
+}
+
+#[test]
+fn link_layers() {
+    // Whole frames are classified as the packets without link header.
+    let rules = || {
+        vec![
+            Rule::new("GET", Expr::value(HttpMethod::Get)),
+            Rule::new("Ipv6", Expr::value(IpVersion::V6)),
+            Rule::new("Tcp", Expr::value(Tcp)),
+        ]
+    };
+    for file_name in &[
+        "tests/captures/ipv4-http-get.pcap",
+        "tests/captures/ipv6-http-get.pcap",
+    ] {
+        let classify = |capture: IpCapture| {
+            let mut engine =
+                ClassifierEngine::new(internet::loader(), Config::default(), rules()).unwrap();
+            capture
+                .iter()
+                .map(|captured| {
+                    let result = engine.classify_packet(captured.as_packet());
+                    (result.rule_tag, result.payload_bytes, result.abort)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let frames = IpCapture::open_frames(file_name);
+        assert_eq!(frames.iter().next().unwrap().as_packet().link_type, LinkType::LinuxSll);
+        assert_eq!(classify(frames), classify(IpCapture::open(file_name)));
+    }
+
+    // Ethernet frames with the datagram sent to the UDP echo server.
+    let capture = IpCapture::open("tests/captures/ipv4-udp-echo.pcap");
+    let datagram = &capture.iter().next().unwrap().data;
+    let frame = |ether_type: u16, tags: &[u8]| {
+        let mut data = vec![0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01];
+        data.extend_from_slice(&ether_type.to_be_bytes());
+        data.extend_from_slice(tags);
+        data.extend_from_slice(datagram);
+        data
+    };
+    let frames = [
+        frame(0x0800, &[]),
+        // QinQ, with the VLAN 100 inside the VLAN 30.
+        frame(0x88A8, &[0x00, 0x1E, 0x81, 0x00, 0x20, 0x64, 0x08, 0x00]),
+        // Label 1000 on top of the label 16.
+        frame(0x8847, &[0x00, 0x3E, 0x80, 0x40, 0x00, 0x01, 0x01, 0x40]),
+        frame(0x0806, &[]),
+    ];
+
+    let rules = vec![
+        Rule::new("Vlan100", Expr::value(VlanId(100)) & Expr::value(UdpDestPort(12345))),
+        Rule::new("Label16", Expr::value(MplsLabel(16))),
+        Rule::new(
+            "FromHost",
+            Expr::value(EthSource("02:00:00:00:00:01".parse().unwrap()))
+                & Expr::value(EtherType(0x0800)),
+        ),
+        Rule::new("Udp", Expr::value(UdpDestPort(12345))),
+    ];
+    let text_rules = r#"
+        Vlan100: vlan.id == 100 && udp.dest_port == 12345
+        Label16: mpls.label == 16
+        FromHost: eth.source == "02-00-00-00-00-01" && eth.type == 2048
+        Udp: udp.dest_port == 12345
+    "#;
+    let text_rules = language::parse_rules(text_rules, &internet::registry()).unwrap();
+    assert_eq!(
+        text_rules
+            .iter()
+            .map(|rule| rule.definition().unwrap())
+            .collect::<Vec<_>>(),
+        rules
+            .iter()
+            .map(|rule| rule.definition().unwrap())
+            .collect::<Vec<_>>()
+    );
+
+    let mut engine = ClassifierEngine::new(internet::loader(), Config::default(), rules).unwrap();
+    let results = frames
+        .iter()
+        .map(|data| {
+            let packet = Packet::new(data, Direction::Uplink).with_link_type(LinkType::Ethernet);
+            engine.classify_packet(packet)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        results
+            .iter()
+            .map(|result| result.rule_tag)
+            .collect::<Vec<_>>(),
+        vec!["FromHost", "Vlan100", "Label16", ""]
+    );
+    // The payload bytes do not count the link and IP headers.
+    assert_eq!(results[1].payload_bytes, datagram.len() - 20);
+
+    // The padding after the datagram is not part of the TCP payload.
+    let capture = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+    let ack = &capture.iter().nth(2).unwrap().data;
+    let mut data = frame(0x0800, &[])[..14].to_vec();
+    data.extend_from_slice(ack);
+    data.extend_from_slice(&[0; 6]);
+
+    let rules = vec![
+        Rule::new("Payload", Expr::value(TcpPayloadLen(|len| len > 0))),
+        Rule::new("Tcp", Expr::value(Tcp)),
+    ];
+    let mut engine = ClassifierEngine::new(internet::loader(), Config::default(), rules).unwrap();
+    let packet = Packet::new(&data, Direction::Uplink).with_link_type(LinkType::Ethernet);
+    assert_eq!(engine.classify_packet(packet).rule_tag, "Tcp");
+}
+
+#[test]
+fn ipv6_extension_headers() {
+    // Copy of the packet with the extension headers, by code and content, before its payload.
+    fn with_extension_headers(packet: &[u8], headers: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut data = packet[..40].to_vec();
+        // Position of the next header field to fill.
+        let mut next_header = 6;
+        for &(code, content) in headers {
+            data[next_header] = code;
+            next_header = data.len();
+            data.extend_from_slice(content);
+        }
+        data[next_header] = packet[6];
+        data.extend_from_slice(&packet[40..]);
+        let payload_len = (data.len() - 40) as u16;
+        data[4..6].copy_from_slice(&payload_len.to_be_bytes());
+        data
+    }
+
+    let rules = vec![
+        Rule::new("RoutedTcp", Expr::value(IpExtensionHeader::Routing) & Expr::value(Tcp)),
+        Rule::new("HopByHop", Expr::value(IpExtensionHeader::HopByHop) & Expr::value(Tcp)),
+        Rule::new("Tcp", Expr::value(Tcp)),
+        Rule::new("Ipv6", Expr::value(IpVersion::V6)),
+    ];
+    let text_rules = r#"
+        RoutedTcp: ip.extension_header == routing && tcp
+        HopByHop: ip.extension_header == hop_by_hop && tcp
+        Tcp: tcp
+        Ipv6: ip.version == 6
+    "#;
+    let text_rules = language::parse_rules(text_rules, &internet::registry()).unwrap();
+    assert_eq!(
+        text_rules
+            .iter()
+            .map(|rule| rule.definition().unwrap())
+            .collect::<Vec<_>>(),
+        rules
+            .iter()
+            .map(|rule| rule.definition().unwrap())
+            .collect::<Vec<_>>()
+    );
+
+    let capture = IpCapture::open("tests/captures/ipv6-http-get.pcap");
+    let syn = &capture.iter().next().unwrap().data;
+    let options: &[u8] = &[0, 0, 1, 4, 0, 0, 0, 0];
+    let routing: &[u8] = &[0, 0, 4, 0, 0, 0, 0, 0];
+    let fragment: &[u8] = &[0, 0, 0, 0, 0, 0, 0, 9];
+    let mut esp = syn.clone();
+    esp[6] = 50;
+    let packets = [
+        syn.clone(),
+        with_extension_headers(syn, &[(0, options), (60, options)]),
+        with_extension_headers(syn, &[(0, options), (43, routing), (60, options)]),
+        // A datagram in a single fragment, after the hop-by-hop options.
+        with_extension_headers(syn, &[(0, options), (44, fragment)]),
+        // The encrypted payload ends the chain.
+        with_extension_headers(&esp, &[(0, options)]),
+    ];
+
+    // Each packet is classified by a new engine, without the grants of the previous ones.
+    let classify = |data: &[u8]| {
+        let mut engine =
+            ClassifierEngine::new(internet::loader(), Config::default(), rules.clone()).unwrap();
+        engine.classify_packet(Packet::new(data, Direction::Uplink))
+    };
+    assert_eq!(
+        packets
+            .iter()
+            .map(|data| classify(data).rule_tag)
+            .collect::<Vec<_>>(),
+        vec!["Tcp", "HopByHop", "RoutedTcp", "HopByHop", "Ipv6"]
+    );
+
+    // The chain is cut by the end of the packet.
+    let mut truncated = syn[..40].to_vec();
+    truncated[6] = 0;
+    assert_eq!(
+        classify(&truncated).abort,
+        Some(AnalyzerError::new(ClassifierId::Ip, AnalyzerErrorKind::Truncated, 40))
+    );
+}
+
+#[test]
+fn icmp_errors() {
+    // ICMP message sent back to the source of the IPv4 datagram, quoting its first bytes.
+    fn icmp_reply(datagram: &[u8], header: [u8; 8], quote_len: usize) -> Vec<u8> {
+        let mut data = datagram[..20].to_vec();
+        data[9] = 1;
+        data[12..16].copy_from_slice(&datagram[16..20]);
+        data[16..20].copy_from_slice(&datagram[12..16]);
+        data.extend_from_slice(&header);
+        data.extend_from_slice(&datagram[..quote_len.min(datagram.len())]);
+        let total_len = data.len() as u16;
+        data[2..4].copy_from_slice(&total_len.to_be_bytes());
+        data
+    }
+
+    let rules = vec![
+        Rule::new("Established", Expr::value(TcpEstablished)),
+        Rule::new("FragmentationNeeded", Expr::value(IcmpType(3)) & Expr::value(IcmpCode(4))),
+        Rule::new("Icmp", Expr::value(Icmp)),
+    ];
+    let mut engine = ClassifierEngine::new(internet::loader(), Config::default(), rules).unwrap();
+
+    // An error quoting the HTTP request takes the grant of its connection,
+    // without changing the state of the connection.
+    let capture = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+    let packets = capture
+        .iter()
+        .map(|captured| captured.as_packet())
+        .collect::<Vec<_>>();
+    let request = &capture.iter().nth(3).unwrap().data;
+    let error = icmp_reply(request, [3, 4, 0, 0, 0, 0, 0x05, 0xDC], 576);
+    let echo = icmp_reply(request, [8, 0, 0, 0, 0, 1, 0, 1], 0);
+    let too_short = icmp_reply(request, [3, 4, 0, 0, 0, 0, 0x05, 0xDC], 28);
+
+    let mut tags = packets[..4]
+        .iter()
+        .map(|packet| engine.classify_packet(*packet).rule_tag)
+        .collect::<Vec<_>>();
+    for data in [&error, &echo, &too_short] {
+        tags.push(
+            engine
+                .classify_packet(Packet::new(data, Direction::Downlink))
+                .rule_tag,
+        );
+    }
+    tags.extend(
+        packets[4..]
+            .iter()
+            .map(|packet| engine.classify_packet(*packet).rule_tag),
+    );
+
+    assert_eq!(
+        tags,
+        vec![
+            "",
+            "",
+            "Established",
+            "Established",
+            // The messages.
+            "Established",
+            "Icmp",
+            "FragmentationNeeded",
+            // The rest of the connection.
+            "Established",
+            "Established",
+            "Established",
+            "",
+            "",
+            "",
+        ]
+    );
+    assert_eq!(engine.flow_stats().created, 1);
+
+    // The values of the quoted transport header are available to the rules.
+    let rules = r#"
+        PortUnreachable: icmp.error && icmp.type == 3 && icmp.code == 3 && udp.dest_port == 12345
+        Echo: ip.proto == icmp && icmp.type == 8
+    "#;
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        Config::default(),
+        language::parse_rules(rules, &internet::registry()).unwrap(),
+    )
+    .unwrap();
+    let capture = IpCapture::open("tests/captures/ipv4-udp-echo.pcap");
+    let datagram = &capture.iter().next().unwrap().data;
+    let unreachable = icmp_reply(datagram, [3, 3, 0, 0, 0, 0, 0, 0], 28);
+    let echo = icmp_reply(datagram, [8, 0, 0, 0, 0, 1, 0, 1], 0);
+    let tags = [unreachable, echo]
+        .iter()
+        .map(|data| {
+            engine
+                .classify_packet(Packet::new(data, Direction::Downlink))
+                .rule_tag
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(tags, vec!["PortUnreachable", "Echo"]);
+
+    // The quoted datagram can not be shorter than its IP header.
+    let truncated = icmp_reply(datagram, [3, 3, 0, 0, 0, 0, 0, 0], 10);
+    assert_eq!(
+        engine
+            .classify_packet(Packet::new(&truncated, Direction::Downlink))
+            .abort,
+        Some(AnalyzerError::new(ClassifierId::Icmp, AnalyzerErrorKind::Truncated, 38))
+    );
+
+    // The error types are the ones of the IP version of the message, which the quote must have.
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        Config::default(),
+        vec![
+            Rule::new("Error", Expr::value(IcmpError)),
+            Rule::new("Icmp", Expr::value(Icmp)),
+        ],
+    )
+    .unwrap();
+    let capture = IpCapture::open("tests/captures/ipv6-http-get.pcap");
+    let ipv6_datagram = &capture.iter().next().unwrap().data;
+    let quoting_ipv6 = |header: [u8; 8]| {
+        let mut data = icmp_reply(datagram, header, 0);
+        data.extend_from_slice(ipv6_datagram);
+        let total_len = data.len() as u16;
+        data[2..4].copy_from_slice(&total_len.to_be_bytes());
+        data
+    };
+    // The type 2 is the ICMPv6 packet too big, but not an ICMP error.
+    let results = [
+        quoting_ipv6([3, 3, 0, 0, 0, 0, 0, 0]),
+        quoting_ipv6([2, 0, 0, 0, 0, 0, 0, 0]),
+    ]
+    .iter()
+    .map(|data| engine.classify_packet(Packet::new(data, Direction::Downlink)))
+    .collect::<Vec<_>>();
+    assert_eq!(
+        results[0].abort,
+        Some(AnalyzerError::new(ClassifierId::Icmp, AnalyzerErrorKind::Malformed, 28))
+    );
+    assert_eq!(results[1].rule_tag, "Icmp");
+
+    // An ICMPv6 destination unreachable, with the type 1.
+    let mut unreachable = ipv6_datagram[..40].to_vec();
+    unreachable[6] = 58;
+    unreachable[8..24].copy_from_slice(&ipv6_datagram[24..40]);
+    unreachable[24..40].copy_from_slice(&ipv6_datagram[8..24]);
+    unreachable.extend_from_slice(&[1, 4, 0, 0, 0, 0, 0, 0]);
+    unreachable.extend_from_slice(ipv6_datagram);
+    let payload_len = (unreachable.len() - 40) as u16;
+    unreachable[4..6].copy_from_slice(&payload_len.to_be_bytes());
+    assert_eq!(
+        engine
+            .classify_packet(Packet::new(&unreachable, Direction::Downlink))
+            .rule_tag,
+        "Error"
+    );
+}
+
+#[test]
+fn dns() {
+    fn message(id: u16, flags: u16, name: &[&str], qtype: u16, answers: u16) -> Vec<u8> {
+        let mut data = id.to_be_bytes().to_vec();
+        data.extend_from_slice(&flags.to_be_bytes());
+        data.extend_from_slice(&[0, 1]);
+        data.extend_from_slice(&answers.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        for label in name {
+            data.push(label.len() as u8);
+            data.extend_from_slice(label.as_bytes());
+        }
+        data.extend_from_slice(&[0]);
+        data.extend_from_slice(&qtype.to_be_bytes());
+        data.extend_from_slice(&[0, 1]);
+        data
+    }
+
+    // The IPv4 datagram sent between the port 40000 of the client and the port 53 of the server.
+    fn datagram(
+        template: &[u8],
+        header_len: usize,
+        direction: Direction,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut data = template[..20 + header_len].to_vec();
+        let ports = match direction {
+            Direction::Uplink => [40000u16, 53],
+            Direction::Downlink => {
+                let (source, dest) = data.split_at_mut(16);
+                source[12..16].swap_with_slice(&mut dest[..4]);
+                [53, 40000]
+            }
+        };
+        data[20..22].copy_from_slice(&ports[0].to_be_bytes());
+        data[22..24].copy_from_slice(&ports[1].to_be_bytes());
+        data.extend_from_slice(payload);
+        let total_len = data.len() as u16;
+        data[2..4].copy_from_slice(&total_len.to_be_bytes());
+        if data[9] == 17 {
+            data[24..26].copy_from_slice(&(total_len - 20).to_be_bytes());
+        }
+        data
+    }
+
+    let rules = r#"
+        Answered: dns.answered && dns.answers > 0
+        NxDomain: dns.rcode == NXDOMAIN
+        Example: dns.qname ~ "example.com" && dns.qtype == A
+        Aaaa: dns.qname == "example.org." && dns.qtype == 28
+        Dns: dns
+    "#;
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        Config::default(),
+        language::parse_rules(rules, &internet::registry()).unwrap(),
+    )
+    .unwrap();
+
+    let capture = IpCapture::open("tests/captures/ipv4-udp-echo.pcap");
+    let udp = capture.iter().next().unwrap().data.clone();
+    let udp_messages = [
+        (Direction::Uplink, message(0x1234, 0x0100, &["www", "Example", "COM"], 1, 0)),
+        (Direction::Downlink, message(0x1234, 0x8180, &["www", "example", "com"], 1, 2)),
+        // Not a response to the query.
+        (Direction::Downlink, message(0x1234, 0x8183, &["www", "example", "com"], 1, 0)),
+        (Direction::Uplink, message(0x5678, 0x0100, &["badexample", "com"], 1, 0)),
+        (Direction::Uplink, message(0x9ABC, 0x0100, &["example", "org"], 28, 0)),
+    ];
+    let tags = udp_messages
+        .iter()
+        .map(|(direction, message)| {
+            let data = datagram(&udp, 8, *direction, message);
+            engine
+                .classify_packet(Packet::new(&data, *direction))
+                .rule_tag
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(tags, vec!["Example", "Answered", "NxDomain", "Dns", "Aaaa"]);
+
+    // Over TCP, each message follows its length.
+    let capture = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+    let tcp = capture.iter().nth(3).unwrap().data.clone();
+    let tcp_header_len = ((tcp[32] >> 4) as usize) << 2;
+    let rules = vec![
+        Rule::new(
+            "Answered",
+            Expr::value(DnsAnswered) & Expr::value(DnsAnswerCountCmp(Operator::Greater, 0)),
+        ),
+        Rule::new(
+            "Example",
+            Expr::value(DnsQueryNameSuffix("example.com")) & Expr::value(DnsQueryType::AAAA),
+        ),
+        Rule::new("Dns", Expr::value(Dns)),
+    ];
+    let mut engine = ClassifierEngine::new(internet::loader(), Config::default(), rules).unwrap();
+    let tcp_messages = [
+        (Direction::Uplink, message(7, 0x0100, &["example", "com"], 28, 0)),
+        (Direction::Downlink, message(7, 0x8180, &["example", "com"], 28, 1)),
+    ];
+    let tags = tcp_messages
+        .iter()
+        .map(|(direction, message)| {
+            let mut payload = (message.len() as u16).to_be_bytes().to_vec();
+            payload.extend_from_slice(message);
+            let data = datagram(&tcp, tcp_header_len, *direction, &payload);
+            engine
+                .classify_packet(Packet::new(&data, *direction))
+                .rule_tag
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(tags, vec!["Example", "Answered"]);
+
+    // A compression pointer can only refer to a previous name.
+    let mut looping = message(1, 0x0100, &[], 1, 0);
+    looping[12..14].copy_from_slice(&[0xC0, 12]);
+    let data = datagram(&udp, 8, Direction::Uplink, &looping);
+    assert_eq!(
+        engine
+            .classify_packet(Packet::new(&data, Direction::Uplink))
+            .abort,
+        Some(AnalyzerError::new(ClassifierId::Dns, AnalyzerErrorKind::Malformed, 40))
+    );
+}
+
+#[test]
+fn tls() {
+    fn vector(len_size: usize, data: &[u8]) -> Vec<u8> {
+        let mut vector = data.len().to_be_bytes()[8 - len_size..].to_vec();
+        vector.extend_from_slice(data);
+        vector
+    }
+
+    fn extension(extension_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut extension = extension_type.to_be_bytes().to_vec();
+        extension.extend(vector(2, data));
+        extension
+    }
+
+    // Handshake record with the hello, after its version, random and empty session ID.
+    fn hello(message_type: u8, fields: &[u8], extensions: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = vec![3, 3];
+        body.extend_from_slice(&[0; 33]);
+        body.extend_from_slice(fields);
+        body.extend(vector(2, &extensions.concat()));
+        let mut message = vec![message_type];
+        message.extend(vector(3, &body));
+        let mut record = vec![22, 3, 1];
+        record.extend(vector(2, &message));
+        record
+    }
+
+    // The IPv4 segment sent between the port 40000 of the client and the port 443 of the server.
+    fn segment(template: &[u8], direction: Direction, payload: &[u8]) -> Vec<u8> {
+        let header_len = 20 + (((template[32] >> 4) as usize) << 2);
+        let mut data = template[..header_len].to_vec();
+        let ports = match direction {
+            Direction::Uplink => [40000u16, 443],
+            Direction::Downlink => {
+                let (source, dest) = data.split_at_mut(16);
+                source[12..16].swap_with_slice(&mut dest[..4]);
+                [443, 40000]
+            }
+        };
+        data[20..22].copy_from_slice(&ports[0].to_be_bytes());
+        data[22..24].copy_from_slice(&ports[1].to_be_bytes());
+        data.extend_from_slice(payload);
+        let total_len = data.len() as u16;
+        data[2..4].copy_from_slice(&total_len.to_be_bytes());
+        data
+    }
+
+    let client_hello = hello(
+        1,
+        &[vector(2, &[0x13, 0x01, 0x13, 0x02]), vec![1, 0]].concat(),
+        &[
+            extension(0, &vector(2, &[vec![0], vector(2, b"www.Example.com")].concat())),
+            extension(16, &vector(2, &[vector(1, b"h2"), vector(1, b"http/1.1")].concat())),
+            extension(43, &vector(1, &[0x03, 0x04, 0x03, 0x03])),
+        ],
+    );
+    let server_hello = hello(
+        2,
+        &[0x13, 0x01, 0],
+        &[
+            extension(16, &vector(2, &vector(1, b"h2"))),
+            extension(43, &[0x03, 0x04]),
+        ],
+    );
+
+    let rules = r#"
+        Http2: tls.alpn == "h2" && tls.version == "1.3" && tls.cipher_suite == "0x1301"
+        Example: tls.sni ~ "example.com" && tls.offered_version == "1.3"
+        Tls: tls
+    "#;
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        Config::default(),
+        language::parse_rules(rules, &internet::registry()).unwrap(),
+    )
+    .unwrap();
+
+    let capture = IpCapture::open("tests/captures/ipv4-http-get.pcap");
+    let template = capture.iter().nth(3).unwrap().data.clone();
+    let application_data = [23, 3, 3, 0, 4, 1, 2, 3, 4];
+    let packets = [
+        // The ClientHello is split in two segments.
+        segment(&template, Direction::Uplink, &client_hello[..50]),
+        segment(&template, Direction::Uplink, &client_hello[50..]),
+        segment(&template, Direction::Downlink, &server_hello),
+        segment(&template, Direction::Uplink, &application_data),
+        segment(&template, Direction::Downlink, &application_data),
+    ];
+    let directions = [
+        Direction::Uplink,
+        Direction::Uplink,
+        Direction::Downlink,
+        Direction::Uplink,
+        Direction::Downlink,
+    ];
+    let results = packets
+        .iter()
+        .zip(directions)
+        .map(|(data, direction)| engine.classify_packet(Packet::new(data, direction)))
+        .collect::<Vec<_>>();
+
+    use RuleValueAction::*;
+    assert_eq!(
+        results
+            .iter()
+            .map(|result| result.rule_tag)
+            .collect::<Vec<_>>(),
+        vec!["", "Example", "Http2", "Http2", "Http2"]
+    );
+    assert_eq!(
+        results
+            .iter()
+            .map(|result| result.rule_value_action)
+            .collect::<Vec<_>>(),
+        vec![
+            Computed,
+            ComputedAndCached,
+            ComputedAndCached,
+            Cached,
+            Cached
+        ]
+    );
+
+    // The values can be built without the rule language.
+    let rules = vec![
+        Rule::new(
+            "Offered",
+            Expr::value(TlsSniSuffix("www.example.com"))
+                & Expr::value(TlsOfferedAlpn("http/1.1"))
+                & Expr::value(TlsOfferedCipherSuite(0x1302)),
+        ),
+        Rule::new("Other", Expr::value(TlsSni("example.com")) | Expr::value(TlsVersion::TLS_1_2)),
+    ];
+    let mut engine = ClassifierEngine::new(internet::loader(), Config::default(), rules).unwrap();
+    let hello = segment(&template, Direction::Uplink, &client_hello);
+    assert_eq!(
+        engine
+            .classify_packet(Packet::new(&hello, Direction::Uplink))
+            .rule_tag,
+        "Offered"
+    );
+
+    // A connection that does not start with a handshake is not TLS.
+    let mut engine = ClassifierEngine::new(
+        internet::loader(),
+        Config::default(),
+        vec![Rule::new("Tls", Expr::value(Tls))],
+    )
+    .unwrap();
+    let data = segment(&template, Direction::Uplink, &application_data);
+    assert_eq!(
+        engine
+            .classify_packet(Packet::new(&data, Direction::Uplink))
+            .rule_tag,
+        ""
+    );
+
+    // After a handshake, the retransmission of the first segment of the ClientHello is not added
+    // to the hello, with or without TCP reassembly.
+    let captured = capture.iter().take(3).collect::<Vec<_>>();
+    let seq_num = u32::from_be_bytes([template[24], template[25], template[26], template[27]]);
+    let with_seq = |mut data: Vec<u8>, offset: u32| {
+        data[24..28].copy_from_slice(&(seq_num + offset).to_be_bytes());
+        data
+    };
+    let mut packets = captured
+        .iter()
+        .map(|captured| {
+            let mut data = captured.data.clone();
+            let ports = match captured.uplink {
+                true => [40000u16, 443],
+                false => [443, 40000],
+            };
+            data[20..22].copy_from_slice(&ports[0].to_be_bytes());
+            data[22..24].copy_from_slice(&ports[1].to_be_bytes());
+            (data, captured.uplink.into())
+        })
+        .collect::<Vec<_>>();
+    // Only the segment that ends the ClientHello is pushed.
+    let mut first = segment(&template, Direction::Uplink, &client_hello[..50]);
+    first[33] &= !0x08;
+    let second = segment(&template, Direction::Uplink, &client_hello[50..]);
+    packets.push((with_seq(first.clone(), 0), Direction::Uplink));
+    packets.push((with_seq(first, 0), Direction::Uplink));
+    packets.push((with_seq(second, 50), Direction::Uplink));
+
+    for depth in [None, Some(1024)] {
+        let config = Config {
+            tcp_reassembly_depth: depth,
+            ..Config::default()
+        };
+        let rules = vec![Rule::new("Example", Expr::value(TlsSni("www.Example.com")))];
+        let mut engine = ClassifierEngine::new(internet::loader(), config, rules).unwrap();
+        let tags = packets
+            .iter()
+            .map(|(data, direction)| {
+                engine
+                    .classify_packet(Packet::new(data, *direction))
+                    .rule_tag
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(tags, vec!["", "", "", "", "", "Example"]);
+    }
+}
@@ -1,6 +1,7 @@
 use arrayref::array_ref;
 
 use pcap_file::{pcap::PcapReader, DataLink};
+use pmc_core::packet::LinkType;
 use pmc_testing::capture::{Capture, CaptureIterator, CapturedPacket};
 
 use std::fs::File;
@@ -10,11 +11,21 @@ pub struct IpCapture {
 }
 
 impl IpCapture {
+    /// Packets without their link header, as raw IP.
     pub fn open(file_name: &str) -> Self {
+        Self::load(file_name, true)
+    }
+
+    /// Whole frames, with the link type of the capture.
+    pub fn open_frames(file_name: &str) -> Self {
+        Self::load(file_name, false)
+    }
+
+    fn load(file_name: &str, strip_link_header: bool) -> Self {
         let pcap_file = File::open(file_name).expect("Error opening file");
         let pcap_reader = PcapReader::new(pcap_file).unwrap();
         let datalink = pcap_reader.header.datalink;
-        let start = match datalink {
+        let link_header_len = match datalink {
             DataLink::ETHERNET => 14,
             DataLink::LINUX_SLL => 16,
             _ => unimplemented!(
@@ -22,6 +33,10 @@ impl IpCapture {
                 pcap_reader.header.datalink
             ),
         };
+        let (start, link_type) = match strip_link_header {
+            true => (link_header_len, LinkType::RawIp),
+            false => (0, LinkType::from_pcap(u32::from(datalink))),
+        };
 
         let mut next_id = 0;
         let ip_packets = pcap_reader
@@ -30,6 +45,7 @@ impl IpCapture {
                 next_id += 1;
                 CapturedPacket {
                     id: next_id,
+                    timestamp: pcap.header.timestamp(),
                     uplink: match datalink {
                         DataLink::ETHERNET => todo!(),
                         DataLink::LINUX_SLL => {
@@ -43,6 +59,9 @@ impl IpCapture {
                         _ => unimplemented!(),
                     },
                     data: Vec::from(&pcap.data[start..]),
+                    wire_len: pcap.header.orig_len as usize - start,
+                    interface: 0,
+                    link_type,
                 }
             })
             .collect();
@@ -56,7 +75,7 @@ impl Capture for IpCapture {
         self.ip_packets.len()
     }
 
-    fn section(&self, from_id: usize, to_id: usize) -> CaptureIterator {
+    fn section(&self, from_id: usize, to_id: usize) -> CaptureIterator<'_> {
         assert!(from_id > 0, "The first packet must be higher than one");
         assert!(
             to_id <= self.ip_packets.len(),
@@ -18,7 +18,7 @@ impl<C: Config> AnalyzerCache<C> {
         }
     }
 
-    pub fn prepare_for_packet(&mut self) -> CacheFrame<C> {
+    pub fn prepare_for_packet(&mut self) -> CacheFrame<'_, C> {
         CacheFrame { cache: self }
     }
 }
@@ -33,7 +33,7 @@ impl<'a, C: Config> CacheFrame<'a, C> {
         id: C::ClassifierId,
         flow_id: &mut C::FlowId,
         packet: &Packet,
-    ) -> UseFlow {
+    ) -> UseFlow<C::ClassifierId> {
         self.cache.classifiers[id.inner()]
             .as_ref()
             .unwrap_or_else(|| panic!("The ID {:?} must have an associated builder", id))
@@ -80,8 +80,16 @@ impl<'a, C: Config> CacheFrame<'a, C> {
         }
     }
 
-    pub fn analyzers_cached(&self) -> usize {
-        self.cache.current_ids.len()
+    pub fn get_built(&self, id: C::ClassifierId) -> Option<&dyn AnalyzerController<'a, C>> {
+        match self.cache.current_ids.contains(&id) {
+            true => Some(self.get(id)),
+            false => None,
+        }
+    }
+
+    /// Classifiers whose analyzer is built for the current packet, in build order.
+    pub fn built_ids(&self) -> &[C::ClassifierId] {
+        &self.cache.current_ids
     }
 }
 
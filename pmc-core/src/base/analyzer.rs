@@ -2,19 +2,22 @@ use crate::base::config::{ClassifierId, Config};
 
 use crate::packet::{Direction, Packet};
 
-pub enum UseFlow {
+use std::fmt;
+
+pub enum UseFlow<I: ClassifierId> {
     Yes,
     No,
-    Abort(&'static str),
+    Abort(AnalyzerError<I>),
 }
 
 pub trait Analyzer<'a, C: Config>: Sized {
     const ID: C::ClassifierId;
-    const PREV_ID: C::ClassifierId;
+    /// Classifiers that can precede this one in the analysis of a packet.
+    const PREV_IDS: &'static [C::ClassifierId];
 
     type Flow: Default + 'static;
 
-    fn update_flow_id(_flow_id: &mut C::FlowId, _packet: &Packet) -> UseFlow {
+    fn update_flow_id(_flow_id: &mut C::FlowId, _packet: &Packet) -> UseFlow<C::ClassifierId> {
         UseFlow::No
     }
 
@@ -27,12 +30,112 @@ pub trait Analyzer<'a, C: Config>: Sized {
     fn update_flow(&self, _config: &C, _flow: &mut Self::Flow, _direction: Direction) {
         unimplemented!("Analyzer {:?} do not update the flow instance", Self::ID)
     }
+
+    /// Whether the flow has finished, e.g. by the teardown of the protocol.
+    /// Checked after each update of the flow. A closed flow is kept until it expires.
+    fn is_flow_closed(_flow: &Self::Flow) -> bool {
+        false
+    }
+
+    /// Heap memory used by the flow, in bytes, such as its reassembly buffers.
+    /// Checked after each update of the flow and counted in the flow memory limit.
+    fn flow_heap_memory(_flow: &Self::Flow) -> usize {
+        0
+    }
+
+    /// Data completed by the last update of the flow, such as a reassembled datagram.
+    /// The next analyzers parse it instead of the rest of the packet.
+    fn take_reassembled(_flow: &mut Self::Flow) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Whether the rest of the packet is a copy of a packet sent in the opposite direction,
+    /// as the datagram quoted by an ICMP error.
+    /// The copy is analyzed until the first analyzer with a flow, which is used but not updated.
+    fn quotes_packet(&self) -> bool {
+        false
+    }
+
+    /// Length in the wire of the rest of the packet, when the protocol declares it,
+    /// as the total length of an IP datagram.
+    /// The next analyzers do not see the bytes after it, such as the padding of the link layer,
+    /// and the bytes missing before it are counted as not captured.
+    fn declared_len(&self) -> Option<usize> {
+        None
+    }
+
+    /// Number of the protocol of the rest of the packet, when the header declares it,
+    /// as the IP protocol. The next analyzer finds it in the packet.
+    fn next_protocol(&self) -> Option<u32> {
+        None
+    }
 }
 
-pub type AnalyzerResult<A, I> = Result<AnalyzerInfo<A, I>, &'static str>;
+pub type AnalyzerResult<A, I> = Result<AnalyzerInfo<A, I>, AnalyzerError<I>>;
 
 pub struct AnalyzerInfo<A, I: ClassifierId> {
     pub analyzer: A,
     pub next_classifier_id: I,
     pub bytes_parsed: usize,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AnalyzerErrorKind {
+    /// The data ends before the expected end of the section.
+    Truncated,
+    /// The data does not follow the protocol format.
+    Malformed,
+    /// The protocol version is not known by the analyzer.
+    UnsupportedVersion,
+    /// The data is valid but uses a feature not supported by the analyzer.
+    Unsupported,
+}
+
+impl fmt::Display for AnalyzerErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "truncated"),
+            Self::Malformed => write!(f, "malformed"),
+            Self::UnsupportedVersion => write!(f, "unsupported version"),
+            Self::Unsupported => write!(f, "unsupported"),
+        }
+    }
+}
+
+/// Reason why an analyzer can not process a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AnalyzerError<I: ClassifierId> {
+    pub classifier_id: I,
+    pub kind: AnalyzerErrorKind,
+    /// Byte where the error was found.
+    /// Analyzers set it from the start of their data,
+    /// the engine reports it from the start of the packet.
+    pub offset: usize,
+}
+
+impl<I: ClassifierId> AnalyzerError<I> {
+    pub fn new(classifier_id: I, kind: AnalyzerErrorKind, offset: usize) -> Self {
+        Self {
+            classifier_id,
+            kind,
+            offset,
+        }
+    }
+
+    /// Checks that `data` has at least `len` bytes.
+    /// If not, the error is [`AnalyzerErrorKind::Truncated`] at the end of `data`.
+    pub fn check_len(classifier_id: I, data: &[u8], len: usize) -> Result<(), Self> {
+        match data.len() >= len {
+            true => Ok(()),
+            false => Err(Self::new(classifier_id, AnalyzerErrorKind::Truncated, data.len())),
+        }
+    }
+}
+
+impl<I: ClassifierId> fmt::Display for AnalyzerError<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} {} at byte {}", self.classifier_id, self.kind, self.offset)
+    }
+}
+
+impl<I: ClassifierId> std::error::Error for AnalyzerError<I> {}
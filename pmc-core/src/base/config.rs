@@ -1,8 +1,9 @@
 use std::fmt::Debug;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
 
 pub trait ClassifierId:
-    From<usize> + Into<usize> + Hash + Debug + Copy + Eq + Ord + 'static
+    From<usize> + Into<usize> + Hash + Debug + Copy + Eq + Ord + Send + Sync + 'static
 {
     const NONE: Self;
     const INITIAL: Self;
@@ -13,14 +14,44 @@ pub trait ClassifierId:
     }
 }
 
-pub struct BaseConfig {
-    pub skip_analyzer_bytes: usize,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowEviction {
+    /// Evicts the least recently used flow.
+    Lru,
+    /// Evicts the first created flow, regardless of its activity.
+    OldestFirst,
+}
+
+#[derive(Clone)]
+pub struct BaseConfig<I: ClassifierId> {
+    /// Classifiers whose parsed bytes are not counted in the payload bytes of a packet.
+    pub skip_analyzer_bytes: Vec<I>,
     pub flow_pool_initial_size: usize,
+    /// Idle time after which a flow created by the classifier `I` is removed.
+    /// Flows of classifiers without timeout are never removed.
+    pub flow_timeouts: Vec<(I, Duration)>,
+    /// Maximum number of flows stored at the same time.
+    /// The flows used by the packet being classified are never evicted, so a packet that creates
+    /// several flows can exceed it until the next flows are created.
+    pub max_flows: Option<usize>,
+    /// Maximum memory used by the stored flows, in bytes: the size of the flows and the heap
+    /// memory reported by their analyzers, such as reassembly buffers. It can be exceeded as `max_flows`.
+    pub max_flow_memory: Option<usize>,
+    /// Flow to remove when a new one does not fit in the limits.
+    pub flow_eviction: FlowEviction,
 }
 
 pub trait Config: Sized + 'static {
     type FlowId: Default + Clone + Hash + Eq + Debug;
     type ClassifierId: ClassifierId;
 
-    fn base(&self) -> &BaseConfig;
+    fn base(&self) -> &BaseConfig<Self::ClassifierId>;
+
+    /// Hashes the part of the flow id shared by the flows that must be classified by the same
+    /// engine, such as the flows of a datagram and of its fragments.
+    /// [`ShardedClassifierEngine`](crate::sharded_engine::ShardedClassifierEngine) dispatches the
+    /// packets by the shard key of their first flow id.
+    fn hash_shard_key<H: Hasher>(flow_id: &Self::FlowId, state: &mut H) {
+        flow_id.hash(state);
+    }
 }
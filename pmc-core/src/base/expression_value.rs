@@ -1,8 +1,9 @@
 use crate::base::analyzer::Analyzer;
 use crate::base::classifier::Classifier;
 use crate::base::config::Config;
+use crate::language::ValueCall;
 
-pub trait ExpressionValue<C: Config>: Sized + std::fmt::Debug + 'static {
+pub trait ExpressionValue<C: Config>: Sized + std::fmt::Debug + Send + Sync + 'static {
     type Classifier: for<'a> Classifier<'a, C>;
 
     const SHOULD_GRANT_BY_FLOW: bool = false;
@@ -14,6 +15,12 @@ pub trait ExpressionValue<C: Config>: Sized + std::fmt::Debug + 'static {
         false
     }
 
+    /// How the value is written in the rule language, used to store it.
+    /// Values without definition can not be stored.
+    fn definition(&self) -> Option<ValueCall> {
+        None
+    }
+
     fn check<'a>(
         &self,
         analyzer: &<Self::Classifier as Classifier<C>>::Analyzer,
@@ -0,0 +1,267 @@
+use crate::base::config::Config;
+use crate::controller::expression_value::ExpressionValueController;
+use crate::engine::Rule;
+use crate::expression::{Expr, ValidatedExpr};
+use crate::language::ValueCall;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Node of the compiled expressions. Children are indexes of other nodes.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Node {
+    Value(usize),
+    Not(usize),
+    All(Vec<usize>),
+    Any(Vec<usize>),
+}
+
+/// The rules compiled into a graph of unique sub-expressions.
+///
+/// - Nested `And`/`All` and `Or`/`Any` are flattened and double negations removed.
+/// - Equal values, by their definition or by being the same instance, are only stored once.
+/// - Equal sub-expressions are stored once and shared among the rules, so their result is
+///   computed once per packet.
+/// - The operands of `All` and `Any` are checked by increasing classifier ID, so values that need
+///   less analysis are checked first. Repeated operands are removed.
+pub(crate) struct CompiledRules<C: Config> {
+    values: Vec<Arc<dyn ExpressionValueController<C>>>,
+    nodes: Vec<Node>,
+    /// Highest classifier ID used by each node.
+    max_ids: Vec<C::ClassifierId>,
+    /// Root node of each rule, by priority.
+    roots: Vec<usize>,
+    /// Node results of the current packet.
+    results: Vec<Option<ValidatedExpr<usize>>>,
+}
+
+impl<C: Config> CompiledRules<C> {
+    pub fn new<T: Copy>(rules: &[Rule<T, C>]) -> Self {
+        let mut compiler = Compiler {
+            rules: CompiledRules {
+                values: Vec::new(),
+                nodes: Vec::new(),
+                max_ids: Vec::new(),
+                roots: Vec::new(),
+                results: Vec::new(),
+            },
+            value_definitions: HashMap::new(),
+            value_instances: HashMap::new(),
+            nodes: HashMap::new(),
+        };
+
+        for rule in rules {
+            let root = compiler.compile(rule.expr());
+            compiler.rules.roots.push(root);
+        }
+
+        let mut compiled = compiler.rules;
+        compiled.results = vec![None; compiled.nodes.len()];
+
+        log::trace!(
+            "Compiled {} rules into {} expressions with {} values",
+            rules.len(),
+            compiled.nodes.len(),
+            compiled.values.len()
+        );
+
+        compiled
+    }
+
+    /// Forgets the results of the previous packet.
+    pub fn reset(&mut self) {
+        self.results.iter_mut().for_each(|result| *result = None);
+    }
+
+    /// Checks the rule with this priority, reusing the results of the sub-expressions already
+    /// checked since the last [`CompiledRules::reset()`].
+    pub fn check(
+        &mut self,
+        priority: usize,
+        value_validator: &mut dyn FnMut(&dyn ExpressionValueController<C>) -> ValidatedExpr<usize>,
+    ) -> ValidatedExpr<usize> {
+        let mut evaluation = Evaluation {
+            values: &self.values,
+            nodes: &self.nodes,
+            results: &mut self.results,
+            value_validator,
+        };
+        evaluation.check(self.roots[priority])
+    }
+}
+
+struct Evaluation<'a, C: Config> {
+    values: &'a [Arc<dyn ExpressionValueController<C>>],
+    nodes: &'a [Node],
+    results: &'a mut [Option<ValidatedExpr<usize>>],
+    value_validator: &'a mut dyn FnMut(&dyn ExpressionValueController<C>) -> ValidatedExpr<usize>,
+}
+
+impl<'a, C: Config> Evaluation<'a, C> {
+    fn check(&mut self, node: usize) -> ValidatedExpr<usize> {
+        if let Some(result) = self.results[node] {
+            return result;
+        }
+
+        let nodes = self.nodes;
+        let result = match &nodes[node] {
+            Node::Value(value) => (self.value_validator)(self.values[*value].as_ref()),
+            Node::Not(child) => match self.check(*child) {
+                ValidatedExpr::Classified(should_grant) => {
+                    ValidatedExpr::NotClassified(should_grant)
+                }
+                ValidatedExpr::NotClassified(should_grant) => {
+                    ValidatedExpr::Classified(should_grant)
+                }
+                abort @ ValidatedExpr::Abort(_) => abort,
+            },
+            Node::All(children) => self.check_all(children),
+            Node::Any(children) => self.check_any(children),
+        };
+
+        // An aborted check depends on the rule being checked, so it is not reused.
+        if !matches!(result, ValidatedExpr::Abort(_)) {
+            self.results[node] = Some(result);
+        }
+
+        result
+    }
+
+    fn check_all(&mut self, children: &[usize]) -> ValidatedExpr<usize> {
+        let mut cache_result = true;
+        for &child in children {
+            match self.check(child) {
+                ValidatedExpr::Classified(should_grant) => cache_result &= should_grant,
+                ValidatedExpr::NotClassified(should_grant) => {
+                    return ValidatedExpr::NotClassified(should_grant & cache_result)
+                }
+                abort @ ValidatedExpr::Abort(_) => return abort,
+            }
+        }
+        ValidatedExpr::Classified(cache_result)
+    }
+
+    fn check_any(&mut self, children: &[usize]) -> ValidatedExpr<usize> {
+        let mut cache_result = true;
+        for &child in children {
+            match self.check(child) {
+                ValidatedExpr::Classified(should_grant) => {
+                    return ValidatedExpr::Classified(should_grant)
+                }
+                ValidatedExpr::NotClassified(should_grant) => cache_result &= should_grant,
+                abort @ ValidatedExpr::Abort(_) => return abort,
+            }
+        }
+        ValidatedExpr::NotClassified(cache_result)
+    }
+}
+
+struct Compiler<C: Config> {
+    rules: CompiledRules<C>,
+    value_definitions: HashMap<(C::ClassifierId, ValueCall), usize>,
+    value_instances: HashMap<*const (), usize>,
+    nodes: HashMap<Node, usize>,
+}
+
+impl<C: Config> Compiler<C> {
+    fn compile(&mut self, expr: &Expr<C>) -> usize {
+        match expr {
+            Expr::Value(value) => {
+                let value = self.value(value);
+                self.node(Node::Value(value))
+            }
+            Expr::Not(inner) => {
+                let inner = self.compile(inner);
+                match self.rules.nodes[inner] {
+                    Node::Not(double_negated) => double_negated,
+                    _ => self.node(Node::Not(inner)),
+                }
+            }
+            Expr::All(_) | Expr::And(_) => self.operands(expr, true, Node::All),
+            Expr::Any(_) | Expr::Or(_) => self.operands(expr, false, Node::Any),
+        }
+    }
+
+    fn operands(&mut self, expr: &Expr<C>, all: bool, node: fn(Vec<usize>) -> Node) -> usize {
+        let mut operands = Vec::new();
+        self.flatten(expr, all, &mut operands);
+
+        let max_ids = &self.rules.max_ids;
+        operands.sort_by_key(|&operand| max_ids[operand]);
+        let mut seen = HashSet::new();
+        operands.retain(|&operand| seen.insert(operand));
+
+        match operands.len() {
+            1 => operands[0],
+            _ => self.node(node(operands)),
+        }
+    }
+
+    fn flatten(&mut self, expr: &Expr<C>, all: bool, operands: &mut Vec<usize>) {
+        match expr {
+            Expr::All(exprs) if all => exprs
+                .iter()
+                .for_each(|expr| self.flatten(expr, all, operands)),
+            Expr::Any(exprs) if !all => exprs
+                .iter()
+                .for_each(|expr| self.flatten(expr, all, operands)),
+            Expr::And(pair) if all => {
+                self.flatten(&pair.0, all, operands);
+                self.flatten(&pair.1, all, operands);
+            }
+            Expr::Or(pair) if !all => {
+                self.flatten(&pair.0, all, operands);
+                self.flatten(&pair.1, all, operands);
+            }
+            _ => operands.push(self.compile(expr)),
+        }
+    }
+
+    fn value(&mut self, value: &Arc<dyn ExpressionValueController<C>>) -> usize {
+        let instance = Arc::as_ptr(value) as *const ();
+        if let Some(&index) = self.value_instances.get(&instance) {
+            return index;
+        }
+
+        // Values created from the same definition are the same value.
+        let definition = value
+            .definition()
+            .map(|definition| (value.classifier_id(), definition));
+        let found = definition
+            .as_ref()
+            .and_then(|definition| self.value_definitions.get(definition).copied());
+
+        let index = found.unwrap_or_else(|| {
+            self.rules.values.push(value.clone());
+            self.rules.values.len() - 1
+        });
+
+        self.value_instances.insert(instance, index);
+        if let Some(definition) = definition {
+            self.value_definitions.insert(definition, index);
+        }
+        index
+    }
+
+    fn node(&mut self, node: Node) -> usize {
+        if let Some(&index) = self.nodes.get(&node) {
+            return index;
+        }
+
+        let rules = &mut self.rules;
+        let max_id = match &node {
+            Node::Value(value) => rules.values[*value].classifier_id(),
+            Node::Not(child) => rules.max_ids[*child],
+            Node::All(children) | Node::Any(children) => children
+                .iter()
+                .map(|child| rules.max_ids[*child])
+                .max()
+                .expect("Empty lists are rejected when the rules are validated"),
+        };
+
+        rules.nodes.push(node.clone());
+        rules.max_ids.push(max_id);
+        self.nodes.insert(node, rules.nodes.len() - 1);
+        rules.nodes.len() - 1
+    }
+}
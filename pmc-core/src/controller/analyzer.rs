@@ -5,8 +5,14 @@ use crate::packet::Direction;
 
 pub trait AnalyzerController<'a, C: Config> {
     fn id(&self) -> C::ClassifierId;
-    fn prev_id(&self) -> C::ClassifierId;
+    fn prev_ids(&self) -> &'static [C::ClassifierId];
     fn update_flow(&self, config: &C, flow: &mut dyn FlowController, direction: Direction);
+    fn is_flow_closed(&self, flow: &dyn FlowController) -> bool;
+    fn flow_heap_memory(&self, flow: &dyn FlowController) -> usize;
+    fn take_reassembled(&self, flow: &mut dyn FlowController) -> Option<Vec<u8>>;
+    fn quotes_packet(&self) -> bool;
+    fn declared_len(&self) -> Option<usize>;
+    fn next_protocol(&self) -> Option<u32>;
 }
 
 impl<'a, C: Config> dyn AnalyzerController<'a, C> + '_ {
@@ -43,12 +49,36 @@ where
         A::ID
     }
 
-    fn prev_id(&self) -> C::ClassifierId {
-        A::PREV_ID
+    fn prev_ids(&self) -> &'static [C::ClassifierId] {
+        A::PREV_IDS
     }
 
     fn update_flow(&self, config: &C, flow: &mut dyn FlowController, direction: Direction) {
         let flow = &mut flow.inner_mut::<A::Flow>();
         self.0.update_flow(config, flow, direction);
     }
+
+    fn is_flow_closed(&self, flow: &dyn FlowController) -> bool {
+        A::is_flow_closed(flow.inner_ref::<A::Flow>())
+    }
+
+    fn flow_heap_memory(&self, flow: &dyn FlowController) -> usize {
+        A::flow_heap_memory(flow.inner_ref::<A::Flow>())
+    }
+
+    fn take_reassembled(&self, flow: &mut dyn FlowController) -> Option<Vec<u8>> {
+        A::take_reassembled(flow.inner_mut::<A::Flow>())
+    }
+
+    fn quotes_packet(&self) -> bool {
+        self.0.quotes_packet()
+    }
+
+    fn declared_len(&self) -> Option<usize> {
+        self.0.declared_len()
+    }
+
+    fn next_protocol(&self) -> Option<u32> {
+        self.0.next_protocol()
+    }
 }
@@ -6,7 +6,7 @@ use crate::controller::flow::{FlowController, SharedFlowController};
 use crate::packet::Packet;
 
 pub trait ClassifierController<C: Config> {
-    fn update_flow_id(&self, flow_id: &mut C::FlowId, packet: &Packet) -> UseFlow;
+    fn update_flow_id(&self, flow_id: &mut C::FlowId, packet: &Packet) -> UseFlow<C::ClassifierId>;
 
     fn build_flow(&self) -> SharedFlowController;
 
@@ -52,7 +52,7 @@ where
     B: for<'b> Classifier<'b, C> + 'static,
     C: Config,
 {
-    fn update_flow_id(&self, flow_id: &mut C::FlowId, packet: &Packet) -> UseFlow {
+    fn update_flow_id(&self, flow_id: &mut C::FlowId, packet: &Packet) -> UseFlow<C::ClassifierId> {
         B::Analyzer::update_flow_id(flow_id, packet)
     }
 
@@ -4,10 +4,12 @@ use crate::base::config::Config;
 use crate::base::expression_value::ExpressionValue;
 use crate::controller::analyzer::AnalyzerController;
 use crate::controller::flow::FlowController;
+use crate::language::ValueCall;
 
 use std::fmt;
+use std::sync::Arc;
 
-pub trait ExpressionValueController<C: Config>: fmt::Debug {
+pub trait ExpressionValueController<C: Config>: fmt::Debug + Send + Sync {
     fn check(
         &self,
         analyzer: &dyn AnalyzerController<C>,
@@ -19,15 +21,17 @@ pub trait ExpressionValueController<C: Config>: fmt::Debug {
     fn should_break_grant(&self, analyzer: &dyn AnalyzerController<C>) -> bool;
 
     fn classifier_id(&self) -> C::ClassifierId;
+
+    fn definition(&self) -> Option<ValueCall>;
 }
 
 impl<C: Config> dyn ExpressionValueController<C> {
-    pub fn new<V, B>(expression_value: V) -> Box<dyn ExpressionValueController<C>>
+    pub fn new<V, B>(expression_value: V) -> Arc<dyn ExpressionValueController<C>>
     where
         V: ExpressionValue<C, Classifier = B> + 'static,
         B: for<'a> Classifier<'a, C>,
     {
-        Box::new(ControllerImpl(expression_value))
+        Arc::new(ControllerImpl(expression_value))
     }
 }
 
@@ -76,4 +80,8 @@ where
     fn classifier_id(&self) -> C::ClassifierId {
         B::Analyzer::ID
     }
+
+    fn definition(&self) -> Option<ValueCall> {
+        self.0.definition()
+    }
 }
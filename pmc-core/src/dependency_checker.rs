@@ -1,4 +1,5 @@
 use crate::base::config::ClassifierId;
+use crate::loader::LoadError;
 
 pub enum DependencyStatus {
     Predecessor,
@@ -12,25 +13,52 @@ pub struct DependencyChecker<I: ClassifierId> {
 }
 
 impl<I: ClassifierId> DependencyChecker<I> {
-    pub fn new(dependency_list: Vec<(I, I)>) -> Self {
+    /// Creates the checker from the parents of each classifier.
+    /// A classifier can have several parents, as long as the dependencies have no cycles.
+    pub fn new(dependency_list: &[(I, &[I])]) -> Result<Self, LoadError<I>> {
         let mut checker = Self {
             dependency_matrix: vec![false; I::TOTAL * I::TOTAL],
-            _index_type: std::marker::PhantomData::default(),
+            _index_type: std::marker::PhantomData,
         };
 
-        for (id, prev_id) in dependency_list {
-            assert!(!checker.get(id, id), "Analyzer {:?} already registered", id);
-
+        for &(id, prev_ids) in dependency_list {
             checker.set(id, id, true);
-            checker.set(prev_id, id, true);
-            for i in 0..prev_id.inner() {
-                if checker.get(i.into(), prev_id) {
-                    checker.set(i.into(), id, true);
+            for &prev_id in prev_ids {
+                checker.set(prev_id, id, true);
+            }
+        }
+
+        // Transitive closure, the registration order does not matter.
+        for k in 0..I::TOTAL {
+            for i in 0..I::TOTAL {
+                if i != k && checker.get(i.into(), k.into()) {
+                    for j in 0..I::TOTAL {
+                        if checker.get(k.into(), j.into()) {
+                            checker.set(i.into(), j.into(), true);
+                        }
+                    }
+                }
+            }
+        }
+
+        for &(id, prev_ids) in dependency_list {
+            for &prev_id in prev_ids {
+                if prev_id == id || checker.get(id, prev_id) {
+                    return Err(LoadError::DependencyCycle(id, prev_id));
                 }
             }
         }
 
-        checker
+        Ok(checker)
+    }
+
+    /// The classifier is loaded and has a path from [`ClassifierId::INITIAL`].
+    pub fn is_reachable(&self, id: I) -> bool {
+        self.is_loaded(I::INITIAL) && self.get(I::INITIAL, id)
+    }
+
+    pub fn is_loaded(&self, id: I) -> bool {
+        self.get(id, id)
     }
 
     fn get(&self, x: I, y: I) -> bool {
@@ -41,6 +69,8 @@ impl<I: ClassifierId> DependencyChecker<I> {
         self.dependency_matrix[y.inner() * I::TOTAL + x.inner()] = value;
     }
 
+    /// Position of `to` in relation to `next`.
+    /// With several parents, a predecessor is not always part of the analysis of a packet.
     pub fn check(&self, next: I, to: I) -> DependencyStatus {
         if self.get(next, to) {
             DependencyStatus::Descendant
@@ -1,21 +1,43 @@
 use crate::analyzer_cache::{AnalyzerCache, CacheFrame};
-use crate::base::analyzer::UseFlow;
+use crate::base::analyzer::{AnalyzerError, AnalyzerErrorKind, UseFlow};
 use crate::base::config::{ClassifierId, Config};
+use crate::compiler::CompiledRules;
 use crate::controller::expression_value::ExpressionValueController;
 use crate::dependency_checker::{DependencyChecker, DependencyStatus};
 use crate::expression::{Expr, ValidatedExpr};
 use crate::flow_pool::FlowPool;
-use crate::loader::ClassifierLoader;
+use crate::loader::{ClassifierLoader, LoadError};
+use crate::metrics::{EngineMetrics, MetricsCounters};
 use crate::packet::Packet;
+use crate::trace::{
+    AnalysisRecorder, AnalyzerTrace, ClassificationEnd, ClassificationTrace, GrantTrace,
+    RuleResult, RuleTrace, ValueResult, ValueTrace,
+};
 
 use std::fmt;
 
+pub use crate::flow_pool::{FlowEvent, FlowEventKind, FlowListener, FlowStats};
+
+/// Reassemblies supported in the analysis of a packet, such as a datagram and then the stream it
+/// belongs to. Further reassemblies abort the analysis.
+const MAX_REASSEMBLIES: usize = 2;
+
 pub struct Rule<T, C: Config> {
     tag: T,
     expr: Expr<C>,
     max_classifier_id: C::ClassifierId,
 }
 
+impl<T: Clone, C: Config> Clone for Rule<T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            tag: self.tag.clone(),
+            expr: self.expr.clone(),
+            max_classifier_id: self.max_classifier_id,
+        }
+    }
+}
+
 impl<T: Copy, C: Config> Rule<T, C> {
     pub fn new(tag: T, expr: Expr<C>) -> Self {
         Self {
@@ -38,7 +60,36 @@ impl<T: Copy, C: Config> Rule<T, C> {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl<T: Default + Eq, C: Config> Rule<T, C> {
+    /// Checks that the rule with this priority can be used with the loaded classifiers.
+    pub(crate) fn validate(
+        &self,
+        priority: usize,
+        dependency_checker: &DependencyChecker<C::ClassifierId>,
+    ) -> Result<(), LoadError<C::ClassifierId>> {
+        if self.tag == T::default() {
+            return Err(LoadError::ReservedTag(priority));
+        }
+
+        if self.expr.has_empty_list() {
+            return Err(LoadError::EmptyExpressionList(priority));
+        }
+
+        let mut missing = None;
+        self.expr.visit_classifier_ids(&mut |id| {
+            if missing.is_none() && !dependency_checker.is_loaded(id) {
+                missing = Some(id);
+            }
+        });
+
+        match missing {
+            Some(id) => Err(LoadError::MissingClassifier(priority, id)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RuleValueAction {
     Computed,
     ComputedAndCached,
@@ -46,18 +97,50 @@ pub enum RuleValueAction {
 }
 
 #[derive(Debug, Clone)]
-pub struct ClassificationResult<T> {
+pub struct ClassificationResult<T, I: ClassifierId> {
     pub rule_tag: T,
     pub payload_bytes: usize,
     pub rule_value_action: RuleValueAction,
+    /// Analyzer error that stopped the classification, if any.
+    /// The offset is counted from the start of the packet.
+    pub abort: Option<AnalyzerError<I>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleMatch<T> {
+    pub rule_tag: T,
+    pub rule_value_action: RuleValueAction,
+}
+
+#[derive(Debug, Clone)]
+pub struct MultiClassificationResult<T, I: ClassifierId> {
+    /// Matching rules, sorted by priority.
+    pub matches: Vec<RuleMatch<T>>,
+    pub payload_bytes: usize,
+    /// Analyzer error that stopped the classification, if any.
+    /// The offset is counted from the start of the packet.
+    pub abort: Option<AnalyzerError<I>>,
+}
+
+impl<T: Copy, I: ClassifierId> MultiClassificationResult<T, I> {
+    pub fn rule_tags(&self) -> Vec<T> {
+        self.matches
+            .iter()
+            .map(|rule_match| rule_match.rule_tag)
+            .collect()
+    }
 }
 
 pub struct ClassifierEngine<C: Config, T> {
     config: C,
     rules: Vec<Rule<T, C>>,
+    compiled_rules: CompiledRules<C>,
     analyzer_cache: AnalyzerCache<C>,
     dependency_checker: DependencyChecker<C::ClassifierId>,
-    flow_pool: FlowPool<C>,
+    flow_pool: FlowPool<C, T>,
+    metrics: MetricsCounters<T, C>,
+    /// Data reassembled by the analysis of the last packet, one buffer per reassembly.
+    reassembly_buffers: [Vec<u8>; MAX_REASSEMBLIES],
 }
 
 impl<C, T> ClassifierEngine<C, T>
@@ -65,195 +148,623 @@ where
     T: fmt::Display + Default + Eq + Copy,
     C: Config,
 {
-    pub fn new(factory: ClassifierLoader<C>, config: C, rules: Vec<Rule<T, C>>) -> Self {
-        rules.iter().for_each(|rule| {
-            assert!(
-                rule.tag != T::default(),
-                "The default tag value is reserved for not maching packets"
-            );
-        });
-
-        let (analyzer_cache, dependency_checker) = factory.split();
+    /// Fails if the classifiers can not be used together or the rules do not fit them.
+    pub fn new(
+        factory: ClassifierLoader<C>,
+        config: C,
+        rules: Vec<Rule<T, C>>,
+    ) -> Result<Self, LoadError<C::ClassifierId>> {
+        let (analyzer_cache, dependency_checker) = factory.split()?;
+        for (priority, rule) in rules.iter().enumerate() {
+            rule.validate(priority, &dependency_checker)?;
+        }
 
-        ClassifierEngine {
+        Ok(ClassifierEngine {
+            metrics: MetricsCounters::new(&rules),
+            compiled_rules: CompiledRules::new(&rules),
             rules,
             analyzer_cache,
             dependency_checker,
-            flow_pool: FlowPool::new(config.base().flow_pool_initial_size),
+            flow_pool: FlowPool::new(config.base()),
+            reassembly_buffers: Default::default(),
             config,
-        }
+        })
     }
 
     pub fn rule_tags(&self) -> Vec<T> {
         self.rules.iter().map(|rule| rule.tag).collect()
     }
 
-    pub fn classify_packet(&mut self, packet: Packet) -> ClassificationResult<T> {
+    pub fn rules(&self) -> &[Rule<T, C>] {
+        &self.rules
+    }
+
+    /// Adds a rule with the priority `index`, shifting the following rules.
+    pub fn insert_rule(
+        &mut self,
+        index: usize,
+        rule: Rule<T, C>,
+    ) -> Result<(), LoadError<C::ClassifierId>> {
+        rule.validate(index, &self.dependency_checker)?;
+        self.update_rules(|rules, origins| {
+            rules.insert(index, rule);
+            origins.insert(index, None);
+        });
+        Ok(())
+    }
+
+    pub fn remove_rule(&mut self, index: usize) -> Rule<T, C> {
+        self.update_rules(|rules, origins| {
+            origins.remove(index);
+            rules.remove(index)
+        })
+    }
+
+    pub fn replace_rule(
+        &mut self,
+        index: usize,
+        rule: Rule<T, C>,
+    ) -> Result<Rule<T, C>, LoadError<C::ClassifierId>> {
+        rule.validate(index, &self.dependency_checker)?;
+        Ok(self.update_rules(|rules, origins| {
+            origins[index] = None;
+            std::mem::replace(&mut rules[index], rule)
+        }))
+    }
+
+    /// Moves the rule at `from` to have the priority `to`.
+    pub fn move_rule(&mut self, from: usize, to: usize) {
+        self.update_rules(|rules, origins| {
+            let rule = rules.remove(from);
+            rules.insert(to, rule);
+            let origin = origins.remove(from);
+            origins.insert(to, origin);
+        });
+    }
+
+    pub fn flow_stats(&self) -> FlowStats {
+        self.flow_pool.stats()
+    }
+
+    /// Reports the lifecycle of the flows to `listener`, replacing the previous one.
+    /// See [`FlowEventKind`] for the reported events.
+    pub fn set_flow_listener(&mut self, listener: impl FlowListener<T, C> + 'static) {
+        self.flow_pool.set_listener(Some(Box::new(listener)));
+    }
+
+    pub fn remove_flow_listener(&mut self) {
+        self.flow_pool.set_listener(None);
+    }
+
+    /// Counters of the classified packets. See [`EngineMetrics`].
+    pub fn metrics(&self) -> EngineMetrics<T, C::ClassifierId> {
+        self.metrics.snapshot(self.flow_pool.stats())
+    }
+
+    /// Restarts the packet counters. The flow statistics are kept.
+    pub fn reset_metrics(&mut self) {
+        self.metrics.reset();
+    }
+
+    /// Modifies the rules keeping the flows granted by them.
+    /// The update must apply the same changes to `origins`, the previous index of each rule or
+    /// `None` for new rules.
+    fn update_rules<R>(
+        &mut self,
+        update: impl FnOnce(&mut Vec<Rule<T, C>>, &mut Vec<Option<usize>>) -> R,
+    ) -> R {
+        let previous_len = self.rules.len();
+        let mut origins = (0..previous_len).map(Some).collect::<Vec<_>>();
+        let output = update(&mut self.rules, &mut origins);
+
+        // A granted rule keeps its grant only if the rules with more priority were also rules
+        // with more priority when it was granted, because only those rules did not match.
+        let mut remap = vec![None; previous_len];
+        let mut all_previous_known = true;
+        let mut max_previous_origin = None;
+        for (index, origin) in origins.iter().enumerate() {
+            if let Some(origin) = *origin {
+                if all_previous_known && max_previous_origin < Some(origin) {
+                    remap[origin] = Some(index);
+                }
+                max_previous_origin = max_previous_origin.max(Some(origin));
+            } else {
+                all_previous_known = false;
+            }
+        }
+
+        self.flow_pool
+            .remap_associated_indices(|index| remap.get(index).copied().flatten());
+        self.metrics.update_rules(&self.rules, &origins);
+        self.compiled_rules = CompiledRules::new(&self.rules);
+
+        output
+    }
+
+    pub fn classify_packet(&mut self, packet: Packet) -> ClassificationResult<T, C::ClassifierId> {
+        self.classify(packet, None).0
+    }
+
+    /// Classifies the packet as [`ClassifierEngine::classify_packet()`] and also returns the
+    /// decision path: the checked rules with their values, the built analyzers and the reason
+    /// of the end of the classification.
+    /// Values shared by several rules are checked again for each rule, to list them in all of them.
+    pub fn classify_packet_explained(
+        &mut self,
+        packet: Packet,
+    ) -> (ClassificationResult<T, C::ClassifierId>, ClassificationTrace<T, C::ClassifierId>) {
+        let (result, trace) = self.classify(packet, Some(AnalysisRecorder::default()));
+        (result, trace.expect("The classification was recorded"))
+    }
+
+    fn classify(
+        &mut self,
+        packet: Packet,
+        recorder: Option<AnalysisRecorder<C::ClassifierId>>,
+    ) -> RecordedClassification<T, C::ClassifierId> {
         let Self {
             config,
             rules,
+            compiled_rules,
             analyzer_cache,
             dependency_checker,
             flow_pool,
+            metrics,
+            reassembly_buffers,
         } = self;
 
         log::trace!("Classify {} packet with {} bytes...", packet.direction, packet.data.len(),);
 
+        flow_pool.start_packet(packet.timestamp);
+
         let packet_len = packet.data.len();
-        let mut state = ClassificationState {
+        let mut state = ClassificationState::new(
+            ClassificationMode::FirstMatch,
             config,
             packet,
-            skipped_bytes: 0,
-            cache: analyzer_cache.prepare_for_packet(),
+            analyzer_cache,
             flow_pool,
-            current_flow_id: C::FlowId::default(),
             dependency_checker,
-            last_id: C::ClassifierId::NONE,
-            next_id: C::ClassifierId::INITIAL,
-            last_flow_id: C::ClassifierId::NONE,
-        };
-
-        let previous_rule_max_classifier_id = C::ClassifierId::NONE;
+            reassembly_buffers,
+        );
+        let explained = recorder.is_some();
+        state.recorder = recorder;
+        compiled_rules.reset();
+
+        // A rule can be granted only if all the previous rules are not classified at flow level.
+        let mut should_grant_previous = true;
+        let mut matched = None;
+        let mut rule_traces = Vec::new();
         for (priority, rule) in rules.iter().enumerate() {
             log::trace!("Check rule {}: {}", priority, rule.tag);
-            let validated_expression = rule.expr.check(&mut |expr_value| {
-                log::trace!(
-                    "Check expresion value of {:?}: [{:?}]",
-                    expr_value.classifier_id(),
-                    expr_value
-                );
+            if explained {
+                // Each rule lists all its values instead of reusing the previous results.
+                compiled_rules.reset();
+            }
+            let validated_expression = compiled_rules
+                .check(priority, &mut |expr_value| state.check_value(rules, priority, expr_value));
 
-                loop {
-                    match state.analyze_classification_for(expr_value.classifier_id()) {
-                        ClassificationStatus::CanClassify => {
-                            break state.check_expr_value(expr_value)
-                        }
-                        ClassificationStatus::NotClassify => {
-                            break ValidatedExpr::NotClassified(false)
-                        }
-                        ClassificationStatus::NeedMoreAnalysis => continue,
-                        ClassificationStatus::Abort(reason) => {
-                            log::trace!("Analysis aborted. Reason: {}", reason);
-                            break ValidatedExpr::Abort(None);
-                        }
-                        ClassificationStatus::FlowCached(associated_rule, should_classify) => {
-                            let granted_rule = &rules[associated_rule];
-                            let should_break = granted_rule.expr.should_break(&mut |value| {
-                                let analyzer = state.cache.get(expr_value.classifier_id());
-                                !value.should_grant_by_flow() || value.should_break_grant(analyzer)
-                            });
+            let rule_result = match validated_expression {
+                ValidatedExpr::Classified(should_grant) => {
+                    let action = match should_grant && should_grant_previous {
+                        true => state.grant(priority),
+                        false => RuleValueAction::Computed,
+                    };
 
-                            if !should_break {
-                                log::trace!("Use grant value at flow level");
-                                break ValidatedExpr::Abort(Some(associated_rule));
-                            }
+                    log::trace!("Classified: rule {}, action: {:?}", rule.tag, action);
+                    matched = Some((priority, action));
+                    RuleResult::Matched(action)
+                }
+                ValidatedExpr::NotClassified(should_grant) => {
+                    should_grant_previous &= should_grant;
+                    RuleResult::NotMatched
+                }
+                ValidatedExpr::Abort(granted) => {
+                    if let Some(granted_rule) = granted {
+                        let action = RuleValueAction::Cached;
+                        log::trace!("Classified: rule {}, action: {:?}", granted_rule, action);
+                        matched = Some((granted_rule, action));
+                    }
+                    RuleResult::Stopped
+                }
+            };
+
+            if let Some(recorder) = &mut state.recorder {
+                rule_traces.push(RuleTrace {
+                    priority,
+                    tag: rule.tag,
+                    values: std::mem::take(&mut recorder.values),
+                    result: rule_result,
+                });
+            }
 
-                            log::trace!("Break grant value at flow level");
+            if rule_result != RuleResult::NotMatched {
+                break;
+            }
+        }
 
-                            state
-                                .flow_pool
-                                .get_cached_mut(state.last_flow_id)
-                                .unwrap()
-                                .delete_associated_index();
+        // The skipped bytes can include reassembled data, longer than the packet.
+        let payload_bytes = packet_len.saturating_sub(state.skipped_bytes);
+        metrics.record_packet(packet_len);
+        metrics.record_analyzers(state.cache.built_ids());
 
-                            let analyzer = state.cache.get(state.last_id);
+        let (rule_tag, rule_value_action) = match matched {
+            Some((priority, action)) => {
+                metrics.record_match(priority, action);
+                (rules[priority].tag, action)
+            }
+            None => {
+                match &state.abort {
+                    Some(error) => {
+                        log::trace!("Not classified: analysis aborted by {}", error);
+                        metrics.record_abort(error);
+                    }
+                    None => log::trace!("Not classified: not rule matched"),
+                }
+                (T::default(), RuleValueAction::Computed)
+            }
+        };
+        metrics.record_tag(rule_tag, payload_bytes);
+        state.finish_flows(rules, rule_tag);
+
+        let trace = state.recorder.take().map(|recorder| ClassificationTrace {
+            rules: rule_traces,
+            analyzers: recorder.analyzers,
+            grants: recorder.grants,
+            end: match (matched, state.abort) {
+                (Some((priority, RuleValueAction::Cached)), _) => {
+                    ClassificationEnd::Granted(priority)
+                }
+                (Some((priority, _)), _) => ClassificationEnd::Matched(priority),
+                (None, Some(error)) => ClassificationEnd::Aborted(error),
+                (None, None) => ClassificationEnd::NoMatch,
+            },
+        });
 
-                            log::trace!(
-                                "Update {:?} flow. Sig: {:?}",
-                                state.last_id,
-                                state.current_flow_id
-                            );
+        let result = ClassificationResult {
+            rule_tag,
+            rule_value_action,
+            payload_bytes,
+            abort: state.abort,
+        };
 
-                            analyzer.update_flow(
-                                state.config,
-                                &mut *state.flow_pool.get_cached_mut(state.last_flow_id).unwrap(),
-                                state.packet.direction,
-                            );
+        (result, trace)
+    }
 
-                            match should_classify {
-                                ShouldClassify::Yes => break state.check_expr_value(expr_value),
-                                ShouldClassify::No => break ValidatedExpr::NotClassified(false),
-                                ShouldClassify::Continue => continue,
-                            }
-                        }
-                    }
+    /// Classifies the packet by all the rules instead of stopping at the first matching rule.
+    /// The analyzers are built only once per packet and shared among all the rules.
+    /// Only the first matching rule can be granted at flow level, as in
+    /// [`ClassifierEngine::classify_packet()`].
+    pub fn classify_packet_all(
+        &mut self,
+        packet: Packet,
+    ) -> MultiClassificationResult<T, C::ClassifierId> {
+        let Self {
+            config,
+            rules,
+            compiled_rules,
+            analyzer_cache,
+            dependency_checker,
+            flow_pool,
+            metrics,
+            reassembly_buffers,
+        } = self;
+
+        log::trace!(
+            "Classify {} packet with {} bytes by all rules...",
+            packet.direction,
+            packet.data.len()
+        );
+
+        flow_pool.start_packet(packet.timestamp);
+
+        let packet_len = packet.data.len();
+        let mut state = ClassificationState::new(
+            ClassificationMode::AllMatches,
+            config,
+            packet,
+            analyzer_cache,
+            flow_pool,
+            dependency_checker,
+            reassembly_buffers,
+        );
+        compiled_rules.reset();
+
+        let mut matches = Vec::new();
+        let mut should_grant_previous = true;
+        for (priority, rule) in rules.iter().enumerate() {
+            let validated_expression = match state.granted_rule == Some(priority) {
+                true => ValidatedExpr::Abort(Some(priority)),
+                false => {
+                    log::trace!("Check rule {}: {}", priority, rule.tag);
+                    compiled_rules.check(priority, &mut |expr_value| {
+                        state.check_value(rules, priority, expr_value)
+                    })
                 }
-            });
+            };
 
             match validated_expression {
                 ValidatedExpr::Classified(should_grant) => {
-                    let action = match should_grant {
-                        true if state.last_flow_id != C::ClassifierId::NONE => {
-                            state
-                                .flow_pool
-                                .get_cached_mut(state.last_flow_id)
-                                .unwrap()
-                                .associate_index(priority);
-                            RuleValueAction::ComputedAndCached
-                        }
+                    let action = match should_grant && should_grant_previous {
+                        true if state.granted_rule.is_none() => state.grant(priority),
                         _ => RuleValueAction::Computed,
                     };
 
-                    log::trace!("Classified: rule {}, action: {:?}", rule.tag, action);
-                    return ClassificationResult {
+                    log::trace!("Matched: rule {}, action: {:?}", rule.tag, action);
+                    metrics.record_match(priority, action);
+                    matches.push(RuleMatch {
                         rule_tag: rule.tag,
                         rule_value_action: action,
-                        payload_bytes: packet_len - state.skipped_bytes,
-                    };
+                    });
+                    should_grant_previous = false;
+                }
+                ValidatedExpr::NotClassified(should_grant) => {
+                    should_grant_previous &= should_grant;
+                }
+                ValidatedExpr::Abort(Some(_)) => {
+                    let action = RuleValueAction::Cached;
+                    log::trace!("Matched: rule {}, action: {:?}", rule.tag, action);
+                    metrics.record_match(priority, action);
+                    matches.push(RuleMatch {
+                        rule_tag: rule.tag,
+                        rule_value_action: action,
+                    });
+                    should_grant_previous = false;
+                }
+                ValidatedExpr::Abort(None) => break,
+            }
+        }
+
+        // The skipped bytes can include reassembled data, longer than the packet.
+        let payload_bytes = packet_len.saturating_sub(state.skipped_bytes);
+        metrics.record_packet(packet_len);
+        metrics.record_analyzers(state.cache.built_ids());
+        if let Some(error) = &state.abort {
+            metrics.record_abort(error);
+        }
+        match matches.is_empty() {
+            true => metrics.record_tag(T::default(), payload_bytes),
+            false => {
+                for rule_match in &matches {
+                    metrics.record_tag(rule_match.rule_tag, payload_bytes);
                 }
-                ValidatedExpr::NotClassified(_) => continue,
-                ValidatedExpr::Abort(granted) => match granted {
-                    Some(granted_rule) => {
-                        let action = RuleValueAction::Cached;
-                        log::trace!("Classified: rule {}, action: {:?}", granted_rule, action);
-                        return ClassificationResult {
-                            rule_tag: rules[granted_rule].tag,
-                            rule_value_action: action,
-                            payload_bytes: packet_len - state.skipped_bytes,
-                        };
-                    }
-                    None => break,
-                },
             }
         }
 
-        log::trace!("Not classified: not rule matched");
-        ClassificationResult {
-            rule_tag: T::default(),
-            rule_value_action: RuleValueAction::Computed,
-            payload_bytes: packet_len - state.skipped_bytes,
+        let rule_tag = matches
+            .first()
+            .map_or_else(T::default, |rule_match| rule_match.rule_tag);
+        state.finish_flows(rules, rule_tag);
+
+        MultiClassificationResult {
+            matches,
+            payload_bytes,
+            abort: state.abort,
         }
     }
 }
 
+/// Classification result with its trace, if it was recorded.
+type RecordedClassification<T, I> = (ClassificationResult<T, I>, Option<ClassificationTrace<T, I>>);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ClassificationMode {
+    /// Stops at the first matching rule. A granted rule finishes the classification.
+    FirstMatch,
+    /// Checks all the rules. Only the granted rule is skipped.
+    AllMatches,
+}
+
 enum ShouldClassify {
     Yes,
     No,
     Continue,
 }
 
-enum ClassificationStatus {
+enum ClassificationStatus<I: ClassifierId> {
     CanClassify,
     NotClassify,
     NeedMoreAnalysis,
     FlowCached(usize, ShouldClassify),
-    Abort(&'static str),
+    Abort(AnalyzerError<I>),
 }
 
-struct ClassificationState<'a, C: Config> {
+struct ClassificationState<'a, C: Config, T> {
+    mode: ClassificationMode,
+    /// Rule granted by the flow of this packet, once the grant is found still valid.
+    granted_rule: Option<usize>,
     config: &'a C,
     packet: Packet<'a>,
+    parsed_bytes: usize,
     skipped_bytes: usize,
+    /// Error of the analyzer that stopped the analysis, with the offset from the packet start.
+    abort: Option<AnalyzerError<C::ClassifierId>>,
+    /// Only used to explain the classification.
+    recorder: Option<AnalysisRecorder<C::ClassifierId>>,
     cache: CacheFrame<'a, C>,
-    flow_pool: &'a mut FlowPool<C>,
+    flow_pool: &'a mut FlowPool<C, T>,
     current_flow_id: C::FlowId,
     dependency_checker: &'a DependencyChecker<C::ClassifierId>,
     last_id: C::ClassifierId,
     next_id: C::ClassifierId,
     last_flow_id: C::ClassifierId,
+    /// Flow id of the last flow of the packet, the one that takes the grants.
+    last_flow_key: Option<C::FlowId>,
+    /// Flows found closed by this packet.
+    closed_flows: Vec<C::FlowId>,
+    /// Flows updated by this packet, with the heap memory they use after the update.
+    updated_flows: Vec<(C::FlowId, usize)>,
+    /// Storage of the reassembled data, until it is used by this packet.
+    /// Each reassembly takes the next buffer.
+    reassembly_buffers: std::slice::IterMut<'a, Vec<u8>>,
+    /// The rest of the packet is a copy quoted by an analyzer.
+    quoted: bool,
 }
 
-impl<'a, C: Config> ClassificationState<'a, C> {
+impl<'a, C: Config, T: Default + Eq + Copy> ClassificationState<'a, C, T> {
+    fn new(
+        mode: ClassificationMode,
+        config: &'a C,
+        packet: Packet<'a>,
+        analyzer_cache: &'a mut AnalyzerCache<C>,
+        flow_pool: &'a mut FlowPool<C, T>,
+        dependency_checker: &'a DependencyChecker<C::ClassifierId>,
+        reassembly_buffers: &'a mut [Vec<u8>],
+    ) -> Self {
+        Self {
+            mode,
+            granted_rule: None,
+            config,
+            packet,
+            parsed_bytes: 0,
+            skipped_bytes: 0,
+            abort: None,
+            recorder: None,
+            cache: analyzer_cache.prepare_for_packet(),
+            flow_pool,
+            current_flow_id: C::FlowId::default(),
+            dependency_checker,
+            last_id: C::ClassifierId::NONE,
+            next_id: C::ClassifierId::INITIAL,
+            last_flow_id: C::ClassifierId::NONE,
+            last_flow_key: None,
+            closed_flows: Vec::new(),
+            updated_flows: Vec::new(),
+            reassembly_buffers: reassembly_buffers.iter_mut(),
+            quoted: false,
+        }
+    }
+
+    /// Reports the classification of the packet, the memory of the updated flows and the closed
+    /// flows to the flow pool.
+    fn finish_flows(&mut self, rules: &[Rule<T, C>], rule_tag: T) {
+        if let Some(flow_key) = &self.last_flow_key {
+            let granted = self
+                .flow_pool
+                .get_cached(self.last_flow_id)
+                .and_then(|flow| flow.associated_index())
+                .map(|index| rules[index].tag);
+            self.flow_pool
+                .update_classification(flow_key, rule_tag, granted);
+        }
+
+        for (flow_key, heap_memory) in &self.updated_flows {
+            self.flow_pool.update_heap_memory(flow_key, *heap_memory);
+        }
+
+        for flow_key in &self.closed_flows {
+            self.flow_pool.close(flow_key);
+        }
+    }
+
+    /// Analyzes the packet as far as the value of the rule `priority` needs and checks it.
+    fn check_value(
+        &mut self,
+        rules: &[Rule<T, C>],
+        priority: usize,
+        expr_value: &dyn ExpressionValueController<C>,
+    ) -> ValidatedExpr<usize> {
+        log::trace!(
+            "Check expresion value of {:?}: [{:?}]",
+            expr_value.classifier_id(),
+            expr_value
+        );
+
+        let mut reached = false;
+        let validated = loop {
+            match self.analyze_classification_for(expr_value.classifier_id()) {
+                ClassificationStatus::CanClassify => {
+                    reached = true;
+                    break self.check_expr_value(expr_value);
+                }
+                ClassificationStatus::NotClassify => break ValidatedExpr::NotClassified(false),
+                ClassificationStatus::NeedMoreAnalysis => continue,
+                ClassificationStatus::Abort(mut error) => {
+                    error.offset += self.parsed_bytes;
+                    log::trace!("Analysis aborted. Reason: {}", error);
+                    self.abort = Some(error);
+                    break ValidatedExpr::Abort(None);
+                }
+                ClassificationStatus::FlowCached(associated_rule, should_classify) => {
+                    // The granted rule is only valid if the rules with more priority
+                    // keep the result they had when the rule was granted.
+                    let should_break = rules[..=associated_rule].iter().any(|rule| {
+                        rule.expr
+                            .should_break(&mut |value| self.should_break_grant(value))
+                    });
+
+                    if let Some(recorder) = &mut self.recorder {
+                        recorder.grants.push(GrantTrace {
+                            classifier_id: self.last_flow_id,
+                            rule: associated_rule,
+                            kept: !should_break,
+                        });
+                    }
+
+                    if !should_break {
+                        log::trace!("Use grant value at flow level");
+                        self.granted_rule = Some(associated_rule);
+                        match self.mode {
+                            ClassificationMode::FirstMatch => {
+                                break ValidatedExpr::Abort(Some(associated_rule))
+                            }
+                            ClassificationMode::AllMatches if associated_rule == priority => {
+                                break ValidatedExpr::Abort(Some(associated_rule))
+                            }
+                            ClassificationMode::AllMatches => (),
+                        }
+                    } else {
+                        log::trace!("Break grant value at flow level");
+
+                        self.flow_pool
+                            .get_cached_mut(self.last_flow_id)
+                            .unwrap()
+                            .delete_associated_index();
+                    }
+
+                    match should_classify {
+                        ShouldClassify::Yes => {
+                            reached = true;
+                            break self.check_expr_value(expr_value);
+                        }
+                        ShouldClassify::No => break ValidatedExpr::NotClassified(false),
+                        ShouldClassify::Continue => continue,
+                    }
+                }
+            }
+        };
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.values.push(ValueTrace {
+                classifier_id: expr_value.classifier_id(),
+                value: format!("{:?}", expr_value),
+                result: match validated {
+                    ValidatedExpr::Classified(_) => ValueResult::True,
+                    ValidatedExpr::NotClassified(_) if reached => ValueResult::False,
+                    ValidatedExpr::NotClassified(_) => ValueResult::Unreachable,
+                    ValidatedExpr::Abort(Some(granted_rule)) => ValueResult::Granted(granted_rule),
+                    ValidatedExpr::Abort(None) => ValueResult::Aborted,
+                },
+            });
+        }
+
+        validated
+    }
+
+    /// Associates the rule to the last flow of the packet, if any.
+    fn grant(&mut self, priority: usize) -> RuleValueAction {
+        if self.last_flow_id == C::ClassifierId::NONE {
+            return RuleValueAction::Computed;
+        }
+
+        self.flow_pool
+            .get_cached_mut(self.last_flow_id)
+            .unwrap()
+            .associate_index(priority);
+
+        RuleValueAction::ComputedAndCached
+    }
+
     fn check_expr_value(
         &self,
         expr_value: &dyn ExpressionValueController<C>,
@@ -269,7 +780,22 @@ impl<'a, C: Config> ClassificationState<'a, C> {
         }
     }
 
-    fn analyze_classification_for(&mut self, id: C::ClassifierId) -> ClassificationStatus {
+    fn should_break_grant(&self, expr_value: &dyn ExpressionValueController<C>) -> bool {
+        if !expr_value.should_grant_by_flow() {
+            // Not granted values were not taken into account when the rule was granted.
+            return false;
+        }
+
+        match self.cache.get_built(expr_value.classifier_id()) {
+            Some(analyzer) => expr_value.should_break_grant(analyzer),
+            None => false,
+        }
+    }
+
+    fn analyze_classification_for(
+        &mut self,
+        id: C::ClassifierId,
+    ) -> ClassificationStatus<C::ClassifierId> {
         match self.dependency_checker.check(self.next_id, id) {
             DependencyStatus::Descendant => {
                 if self.next_id == self.last_id {
@@ -291,6 +817,7 @@ impl<'a, C: Config> ClassificationState<'a, C> {
                         let cache = &self.cache;
                         let next_id = self.next_id;
                         self.last_flow_id = self.next_id;
+                        self.last_flow_key = Some(self.current_flow_id.clone());
                         Some(self.flow_pool.get_or_create(
                             self.next_id,
                             &self.current_flow_id,
@@ -298,10 +825,9 @@ impl<'a, C: Config> ClassificationState<'a, C> {
                         ))
                     }
                     UseFlow::No => None,
-                    UseFlow::Abort(reason) => return ClassificationStatus::Abort(reason),
+                    UseFlow::Abort(error) => return ClassificationStatus::Abort(error),
                 };
 
-                let analyzers_cached = self.cache.analyzers_cached();
                 let analyzer_result = self.cache.build_analyzer(
                     self.next_id,
                     self.config,
@@ -311,45 +837,103 @@ impl<'a, C: Config> ClassificationState<'a, C> {
 
                 match analyzer_result {
                     Ok(info) => {
-                        self.packet.data = &self.packet.data[info.bytes_parsed..];
+                        if let Some(recorder) = &mut self.recorder {
+                            recorder.analyzers.push(AnalyzerTrace {
+                                classifier_id: self.next_id,
+                                bytes_parsed: info.bytes_parsed,
+                                flow: flow.is_some(),
+                            });
+                        }
+
+                        self.packet.advance(info.bytes_parsed);
+                        if let Some(len) = info.analyzer.declared_len() {
+                            self.packet.truncate(len);
+                        }
+                        self.packet.protocol = info.analyzer.next_protocol();
+                        self.parsed_bytes += info.bytes_parsed;
                         self.last_id = self.next_id;
 
-                        if analyzers_cached < self.config.base().skip_analyzer_bytes {
+                        if self
+                            .config
+                            .base()
+                            .skip_analyzer_bytes
+                            .contains(&self.last_id)
+                        {
                             self.skipped_bytes += info.bytes_parsed;
                         }
 
-                        let should_classify = if info.next_classifier_id == ClassifierId::NONE {
+                        // The analysis of a quoted packet ends once its flow is found.
+                        let next_classifier_id = match self.quoted && flow.is_some() {
+                            true => C::ClassifierId::NONE,
+                            false => info.next_classifier_id,
+                        };
+
+                        if info.analyzer.quotes_packet() {
+                            log::trace!("Continue with a quoted packet");
+                            self.quoted = true;
+                            self.packet.direction = self.packet.direction.reverse();
+                        }
+
+                        let should_classify = if next_classifier_id == ClassifierId::NONE {
                             log::trace!("Analysis finished");
                             match self.next_id == id {
                                 true => ShouldClassify::Yes,
                                 false => ShouldClassify::No,
                             }
                         } else {
-                            self.next_id = info.next_classifier_id;
+                            self.next_id = next_classifier_id;
                             ShouldClassify::Continue
                         };
 
                         if let Some(mut flow) = flow {
-                            /*
+                            // The flow of a quoted packet only provides its grant.
+                            if !self.quoted {
+                                log::trace!(
+                                    "Update {:?} flow. Sig: {:?}",
+                                    self.last_id,
+                                    self.current_flow_id
+                                );
+
+                                info.analyzer.update_flow(
+                                    self.config,
+                                    &mut *flow,
+                                    self.packet.direction,
+                                );
+
+                                if info.analyzer.is_flow_closed(&*flow) {
+                                    self.closed_flows.push(self.current_flow_id.clone());
+                                }
+
+                                let heap_memory = info.analyzer.flow_heap_memory(&*flow);
+                                self.updated_flows
+                                    .push((self.current_flow_id.clone(), heap_memory));
+
+                                if let Some(data) = info.analyzer.take_reassembled(&mut *flow) {
+                                    let buffer = match self.reassembly_buffers.next() {
+                                        Some(buffer) => buffer,
+                                        None => {
+                                            return ClassificationStatus::Abort(AnalyzerError::new(
+                                                self.last_id,
+                                                AnalyzerErrorKind::Unsupported,
+                                                0,
+                                            ))
+                                        }
+                                    };
+
+                                    log::trace!("Continue with {} reassembled bytes", data.len());
+                                    *buffer = data;
+                                    self.packet.wire_len = buffer.len();
+                                    self.packet.data = buffer;
+                                }
+                            }
+
                             if let Some(associated_rule) = flow.associated_index() {
                                 log::trace!("Flow with cached rule: {}", associated_rule);
                                 return ClassificationStatus::FlowCached(
                                     associated_rule,
                                     should_classify,
                                 );
-                            }*/
-
-                            log::trace!(
-                                "Update {:?} flow. Sig: {:?}",
-                                self.last_id,
-                                self.current_flow_id
-                            );
-
-                            info.analyzer.update_flow(
-                                self.config,
-                                &mut *flow,
-                                self.packet.direction,
-                            );
+                            }
                         }
 
                         match should_classify {
@@ -358,10 +942,14 @@ impl<'a, C: Config> ClassificationState<'a, C> {
                             ShouldClassify::Continue => ClassificationStatus::NeedMoreAnalysis,
                         }
                     }
-                    Err(reason) => ClassificationStatus::Abort(reason),
+                    Err(error) => ClassificationStatus::Abort(error),
                 }
             }
-            DependencyStatus::Predecessor => ClassificationStatus::CanClassify,
+            DependencyStatus::Predecessor => match self.cache.get_built(id) {
+                Some(_) => ClassificationStatus::CanClassify,
+                // The packet was analyzed through another parent.
+                None => ClassificationStatus::NotClassify,
+            },
             DependencyStatus::NoPath => ClassificationStatus::NotClassify,
         }
     }
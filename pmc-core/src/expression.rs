@@ -1,11 +1,13 @@
 use crate::base::classifier::Classifier;
-use crate::base::config::Config;
+use crate::base::config::{ClassifierId, Config};
 use crate::base::expression_value::ExpressionValue;
 use crate::controller::expression_value::ExpressionValueController;
 
 use std::cmp;
 use std::ops::{BitAnd, BitOr, Not};
+use std::sync::Arc;
 
+#[derive(Clone, Copy)]
 pub(crate) enum ValidatedExpr<T> {
     Classified(bool),
     NotClassified(bool),
@@ -14,7 +16,7 @@ pub(crate) enum ValidatedExpr<T> {
 
 pub enum Expr<C: Config> {
     #[non_exhaustive]
-    Value(Box<dyn ExpressionValueController<C>>),
+    Value(Arc<dyn ExpressionValueController<C>>),
     #[non_exhaustive]
     Not(Box<Expr<C>>),
     #[non_exhaustive]
@@ -44,105 +46,79 @@ impl<C: Config> Expr<C> {
         Expr::Any(expressions)
     }
 
-    pub(crate) fn check<T>(
+    /// The expression should be computed again if any of its values breaks the grant.
+    pub(crate) fn should_break(
         &self,
-        value_validator: &mut dyn FnMut(&dyn ExpressionValueController<C>) -> ValidatedExpr<T>,
-    ) -> ValidatedExpr<T> {
+        value_validator: &mut dyn FnMut(&dyn ExpressionValueController<C>) -> bool,
+    ) -> bool {
         match self {
             Expr::Value(value) => value_validator(value.as_ref()),
-            Expr::Not(rule) => match rule.check(value_validator) {
-                ValidatedExpr::Classified(should_grant) => {
-                    ValidatedExpr::NotClassified(should_grant)
-                }
-                ValidatedExpr::NotClassified(should_grant) => {
-                    ValidatedExpr::Classified(should_grant)
-                }
-                ValidatedExpr::Abort(granted) => ValidatedExpr::Abort(granted),
-            },
-            Expr::All(rules) => {
-                let mut cache_result = true;
-                for rule in rules.iter() {
-                    match rule.check(value_validator) {
-                        ValidatedExpr::Classified(should_grant) => cache_result &= should_grant,
-                        ValidatedExpr::NotClassified(should_grant) => {
-                            return ValidatedExpr::NotClassified(should_grant & cache_result)
-                        }
-                        ValidatedExpr::Abort(granted) => return ValidatedExpr::Abort(granted),
-                    }
-                }
-                ValidatedExpr::Classified(false)
+            Expr::Not(rule) => rule.should_break(value_validator),
+            Expr::All(rules) | Expr::Any(rules) => {
+                rules.iter().any(|rule| rule.should_break(value_validator))
             }
-            Expr::Any(rules) => {
-                for rule in rules.iter() {
-                    match rule.check(value_validator) {
-                        ValidatedExpr::Classified(should_grant) => {
-                            return ValidatedExpr::Classified(should_grant)
-                        }
-                        ValidatedExpr::NotClassified(_) => continue,
-                        ValidatedExpr::Abort(granted) => return ValidatedExpr::Abort(granted),
-                    }
-                }
-                ValidatedExpr::NotClassified(false)
+            Expr::And(pair) | Expr::Or(pair) => {
+                pair.0.should_break(value_validator) || pair.1.should_break(value_validator)
             }
-            Expr::And(pair) => match pair.0.check(value_validator) {
-                ValidatedExpr::Classified(should_grant_0) => match pair.1.check(value_validator) {
-                    ValidatedExpr::Classified(should_grant_1) => {
-                        ValidatedExpr::Classified(should_grant_0 & should_grant_1)
-                    }
-                    ValidatedExpr::NotClassified(should_grant_1) => {
-                        ValidatedExpr::NotClassified(should_grant_0 & should_grant_1)
-                    }
-                    val @ ValidatedExpr::Abort(_) => val,
-                },
-                val @ ValidatedExpr::NotClassified(_) => val,
-                val @ ValidatedExpr::Abort(_) => val,
-            },
-            Expr::Or(pair) => match pair.0.check(value_validator) {
-                val @ ValidatedExpr::Classified(_) => val,
-                ValidatedExpr::NotClassified(_) => pair.1.check(value_validator),
-                val @ ValidatedExpr::Abort(_) => val,
-            },
         }
     }
 
-    pub(crate) fn should_break(
-        &self,
-        value_validator: &mut dyn FnMut(&dyn ExpressionValueController<C>) -> bool,
-    ) -> bool {
+    /// Calls `visit` with the classifier of each value.
+    pub(crate) fn visit_classifier_ids(&self, visit: &mut dyn FnMut(C::ClassifierId)) {
         match self {
-            Expr::Value(value) => value_validator(value.as_ref()),
-            Expr::Not(rule) => !rule.should_break(value_validator),
-            Expr::All(rules) => rules.iter().all(|rule| rule.should_break(value_validator)),
-            Expr::Any(rules) => rules.iter().any(|rule| rule.should_break(value_validator)),
-            Expr::And(pair) => {
-                pair.0.should_break(value_validator) && pair.1.should_break(value_validator)
+            Expr::Value(value) => visit(value.classifier_id()),
+            Expr::Not(rule) => rule.visit_classifier_ids(visit),
+            Expr::All(rules) | Expr::Any(rules) => rules
+                .iter()
+                .for_each(|rule| rule.visit_classifier_ids(visit)),
+            Expr::And(pair) | Expr::Or(pair) => {
+                pair.0.visit_classifier_ids(visit);
+                pair.1.visit_classifier_ids(visit);
             }
-            Expr::Or(pair) => {
-                pair.0.should_break(value_validator) || pair.1.should_break(value_validator)
+        }
+    }
+
+    /// Some list of the expression has no expressions, so it can not be compiled.
+    pub(crate) fn has_empty_list(&self) -> bool {
+        match self {
+            Expr::Value(_) => false,
+            Expr::Not(rule) => rule.has_empty_list(),
+            Expr::All(rules) | Expr::Any(rules) => {
+                rules.is_empty() || rules.iter().any(|rule| rule.has_empty_list())
             }
+            Expr::And(pair) | Expr::Or(pair) => pair.0.has_empty_list() || pair.1.has_empty_list(),
         }
     }
 
+    /// Empty lists, rejected when the rule is validated, have no classifier.
     pub(crate) fn max_classifier_id(&self) -> C::ClassifierId {
         match self {
             Expr::Value(value) => value.classifier_id(),
             Expr::Not(rule) => rule.max_classifier_id(),
-            Expr::All(rules) => rules
-                .iter()
-                .map(|rule| rule.max_classifier_id())
-                .max()
-                .unwrap(),
-            Expr::Any(rules) => rules
+            Expr::All(rules) | Expr::Any(rules) => rules
                 .iter()
                 .map(|rule| rule.max_classifier_id())
                 .max()
-                .unwrap(),
+                .unwrap_or(C::ClassifierId::NONE),
             Expr::And(pair) => cmp::max(pair.0.max_classifier_id(), pair.1.max_classifier_id()),
             Expr::Or(pair) => cmp::max(pair.0.max_classifier_id(), pair.1.max_classifier_id()),
         }
     }
 }
 
+impl<C: Config> Clone for Expr<C> {
+    fn clone(&self) -> Self {
+        match self {
+            Expr::Value(value) => Expr::Value(value.clone()),
+            Expr::Not(rule) => Expr::Not(rule.clone()),
+            Expr::All(rules) => Expr::All(rules.clone()),
+            Expr::Any(rules) => Expr::Any(rules.clone()),
+            Expr::And(pair) => Expr::And(pair.clone()),
+            Expr::Or(pair) => Expr::Or(pair.clone()),
+        }
+    }
+}
+
 impl<C: Config> Not for Expr<C> {
     type Output = Expr<C>;
     fn not(self) -> Expr<C> {
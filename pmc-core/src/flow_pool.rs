@@ -1,19 +1,156 @@
-use crate::base::config::{ClassifierId, Config};
+use crate::base::config::{BaseConfig, ClassifierId, Config, FlowEviction};
 use crate::controller::flow::{FlowController, SharedFlowController};
 
 use std::cell::{Ref, RefMut};
-use std::collections::{hash_map::Entry, HashMap};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
 
-pub struct FlowPool<C: Config> {
-    flows: HashMap<C::FlowId, SharedFlowController>,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlowStats {
+    /// Flows currently stored in the pool.
+    pub active: usize,
+    /// Memory used by the stored flows, in bytes: the size of the entries and the heap memory
+    /// reported by the analyzers of each flow.
+    pub memory: usize,
+    pub created: usize,
+    pub expired: usize,
+    pub evicted: usize,
+}
+
+impl std::ops::AddAssign for FlowStats {
+    fn add_assign(&mut self, other: Self) {
+        self.active += other.active;
+        self.memory += other.memory;
+        self.created += other.created;
+        self.expired += other.expired;
+        self.evicted += other.evicted;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowEventKind<T> {
+    Created,
+    /// A packet of the flow was matched by a rule for the first time.
+    Classified,
+    /// The flow was granted the rule with this tag, or lost its grant.
+    GrantChanged(Option<T>),
+    /// The flow finished, e.g. by the TCP teardown. It is kept until it expires.
+    Closed,
+    Expired,
+    /// Removed to make room for a new flow.
+    Evicted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowEvent<T, I, F> {
+    pub kind: FlowEventKind<T>,
+    /// Classifier that owns the flow.
+    pub classifier_id: I,
+    pub flow_id: F,
+    /// Tag of the last rule that matched a packet of the flow, or the default tag if none.
+    pub tag: T,
+}
+
+/// Observer of the flow lifecycle, registered with
+/// [`ClassifierEngine::set_flow_listener()`](crate::engine::ClassifierEngine::set_flow_listener).
+/// Only the flows whose packets are tagged by the engine, the last flow of each packet, are
+/// classified and granted.
+pub trait FlowListener<T, C: Config> {
+    fn on_flow_event(&mut self, event: FlowEvent<T, C::ClassifierId, C::FlowId>);
+}
+
+impl<T, C, F> FlowListener<T, C> for F
+where
+    C: Config,
+    F: FnMut(FlowEvent<T, C::ClassifierId, C::FlowId>),
+{
+    fn on_flow_event(&mut self, event: FlowEvent<T, C::ClassifierId, C::FlowId>) {
+        self(event)
+    }
+}
+
+struct FlowEntry<C: Config, T> {
+    flow: SharedFlowController,
+    id: C::ClassifierId,
+    /// Time of the last packet, unknown if the clock had not started.
+    last_activity: Option<Duration>,
+    order: u64,
+    /// Inline size of the entry plus the heap memory reported for the flow.
+    memory: usize,
+    heap_memory: usize,
+    tag: T,
+    granted: Option<T>,
+    closed: bool,
+}
+
+pub struct FlowPool<C: Config, T> {
+    flows: HashMap<C::FlowId, FlowEntry<C, T>>,
+    order: BTreeMap<u64, C::FlowId>,
+    next_order: u64,
     cached: Vec<Option<SharedFlowController>>,
+    timeouts: Vec<Option<Duration>>,
+    sweep_interval: Option<Duration>,
+    last_sweep: Option<Duration>,
+    /// Timestamp of the latest packet. The clock starts with the first packet that has one.
+    now: Option<Duration>,
+    /// Flows used by the current packet, which can not be evicted.
+    in_use: Vec<C::FlowId>,
+    max_flows: Option<usize>,
+    max_memory: Option<usize>,
+    eviction: FlowEviction,
+    stats: FlowStats,
+    listener: Option<Box<dyn FlowListener<T, C>>>,
 }
 
-impl<C: Config> FlowPool<C> {
-    pub fn new(capacity: usize) -> Self {
+impl<C: Config, T: Default + Eq + Copy> FlowPool<C, T> {
+    pub fn new(config: &BaseConfig<C::ClassifierId>) -> Self {
+        let mut timeouts = vec![None; C::ClassifierId::TOTAL];
+        for (id, timeout) in &config.flow_timeouts {
+            timeouts[id.inner()] = Some(*timeout);
+        }
+
         Self {
-            flows: HashMap::with_capacity(capacity),
+            flows: HashMap::with_capacity(config.flow_pool_initial_size),
+            order: BTreeMap::new(),
+            next_order: 0,
             cached: (0..C::ClassifierId::TOTAL).map(|_| None).collect(),
+            sweep_interval: timeouts.iter().flatten().min().copied(),
+            timeouts,
+            last_sweep: None,
+            now: None,
+            in_use: Vec::new(),
+            max_flows: config.max_flows,
+            max_memory: config.max_flow_memory,
+            eviction: config.flow_eviction,
+            stats: FlowStats::default(),
+            listener: None,
+        }
+    }
+
+    pub fn set_listener(&mut self, listener: Option<Box<dyn FlowListener<T, C>>>) {
+        self.listener = listener;
+    }
+
+    /// Prepares the pool for a new packet and advances the pool clock. Packets without timestamp
+    /// do not move the clock. Expired flows are removed at most once per the lowest configured
+    /// timeout.
+    pub fn start_packet(&mut self, timestamp: Option<Duration>) {
+        self.in_use.clear();
+
+        let timestamp = match (timestamp, self.now) {
+            (Some(timestamp), Some(now)) if timestamp <= now => return,
+            (Some(timestamp), _) => timestamp,
+            (None, _) => return,
+        };
+
+        let last_sweep = *self.last_sweep.get_or_insert(timestamp);
+        self.now = Some(timestamp);
+
+        if let Some(sweep_interval) = self.sweep_interval {
+            if timestamp - last_sweep >= sweep_interval {
+                self.remove_expired();
+                self.last_sweep = Some(timestamp);
+            }
         }
     }
 
@@ -22,32 +159,256 @@ impl<C: Config> FlowPool<C> {
         id: C::ClassifierId,
         flow_id: &C::FlowId,
         builder: impl Fn() -> SharedFlowController,
-    ) -> RefMut<dyn FlowController> {
-        match self.flows.entry(flow_id.clone()) {
-            Entry::Vacant(entry) => {
-                let shared_flow = builder();
-                log::trace!("Create {:?} flow. Sig: {:?}", id, flow_id);
-                entry.insert(shared_flow.clone());
-                self.cached[id.inner()] = Some(shared_flow.clone());
-                self.cached[id.inner()].as_ref().unwrap().borrow_mut()
-            }
-            Entry::Occupied(entry) => {
+    ) -> RefMut<'_, dyn FlowController> {
+        if !self.in_use.contains(flow_id) {
+            self.in_use.push(flow_id.clone());
+        }
+
+        let shared_flow = match self.flows.get_mut(flow_id) {
+            Some(entry) if !Self::is_expired(&self.timeouts, entry, self.now) => {
                 log::trace!("Use {:?} flow. Sig: {:?}", id, flow_id);
-                self.cached[id.inner()] = Some(entry.get().clone());
-                self.cached[id.inner()].as_ref().unwrap().borrow_mut()
+                entry.last_activity = self.now;
+                if let FlowEviction::Lru = self.eviction {
+                    let flow_id = self.order.remove(&entry.order).unwrap();
+                    entry.order = self.next_order;
+                    self.order.insert(self.next_order, flow_id);
+                    self.next_order += 1;
+                }
+                entry.flow.clone()
             }
-        }
+            entry => {
+                if entry.is_some() {
+                    log::trace!("Recreate expired {:?} flow. Sig: {:?}", id, flow_id);
+                    let entry = self.remove(flow_id).unwrap();
+                    Self::notify(&mut self.listener, FlowEventKind::Expired, &entry, flow_id);
+                    self.stats.expired += 1;
+                } else {
+                    log::trace!("Create {:?} flow. Sig: {:?}", id, flow_id);
+                }
+
+                let shared_flow = builder();
+                self.insert(id, flow_id, shared_flow.clone());
+                shared_flow
+            }
+        };
+
+        self.cached[id.inner()].insert(shared_flow).borrow_mut()
     }
 
-    pub fn get_cached(&self, id: C::ClassifierId) -> Option<Ref<dyn FlowController>> {
+    pub fn get_cached(&self, id: C::ClassifierId) -> Option<Ref<'_, dyn FlowController>> {
         self.cached[id.inner()]
             .as_ref()
             .map(|shared_flow| shared_flow.borrow())
     }
 
-    pub fn get_cached_mut(&self, id: C::ClassifierId) -> Option<RefMut<dyn FlowController>> {
+    pub fn get_cached_mut(&self, id: C::ClassifierId) -> Option<RefMut<'_, dyn FlowController>> {
         self.cached[id.inner()]
             .as_ref()
             .map(|shared_flow| shared_flow.borrow_mut())
     }
+
+    /// Updates the heap memory used by the flow after an update, evicting other flows if the
+    /// pool exceeds its memory limit.
+    pub fn update_heap_memory(&mut self, flow_id: &C::FlowId, heap_memory: usize) {
+        let entry = match self.flows.get_mut(flow_id) {
+            Some(entry) if entry.heap_memory != heap_memory => entry,
+            _ => return,
+        };
+
+        self.stats.memory = self.stats.memory - entry.heap_memory + heap_memory;
+        entry.memory = entry.memory - entry.heap_memory + heap_memory;
+        entry.heap_memory = heap_memory;
+
+        self.make_room(0, 0);
+    }
+
+    /// Changes the rule index associated to each flow.
+    /// Flows whose index has not a new value lose their association.
+    pub fn remap_associated_indices(&mut self, remap: impl Fn(usize) -> Option<usize>) {
+        for (flow_id, entry) in self.flows.iter_mut() {
+            let mut flow = entry.flow.borrow_mut();
+            if let Some(index) = flow.associated_index() {
+                match remap(index) {
+                    Some(new_index) => flow.associate_index(new_index),
+                    None => {
+                        flow.delete_associated_index();
+                        if entry.granted.take().is_some() {
+                            let kind = FlowEventKind::GrantChanged(None);
+                            Self::notify(&mut self.listener, kind, entry, flow_id);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records the classification of a packet of the flow: the tag of the packet and the tag of
+    /// the rule granted to the flow, if any.
+    pub fn update_classification(&mut self, flow_id: &C::FlowId, tag: T, granted: Option<T>) {
+        if self.listener.is_none() {
+            return;
+        }
+
+        if let Some(entry) = self.flows.get_mut(flow_id) {
+            if tag != T::default() {
+                let first = entry.tag == T::default();
+                entry.tag = tag;
+                if first {
+                    Self::notify(&mut self.listener, FlowEventKind::Classified, entry, flow_id);
+                }
+            }
+
+            if entry.granted != granted {
+                entry.granted = granted;
+                let kind = FlowEventKind::GrantChanged(granted);
+                Self::notify(&mut self.listener, kind, entry, flow_id);
+            }
+        }
+    }
+
+    /// Reports the flow as closed the first time it is found closed.
+    pub fn close(&mut self, flow_id: &C::FlowId) {
+        if self.listener.is_none() {
+            return;
+        }
+
+        if let Some(entry) = self.flows.get_mut(flow_id) {
+            if !entry.closed {
+                log::trace!("Close {:?} flow. Sig: {:?}", entry.id, flow_id);
+                entry.closed = true;
+                Self::notify(&mut self.listener, FlowEventKind::Closed, entry, flow_id);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> FlowStats {
+        FlowStats {
+            active: self.flows.len(),
+            ..self.stats
+        }
+    }
+
+    fn insert(&mut self, id: C::ClassifierId, flow_id: &C::FlowId, flow: SharedFlowController) {
+        // The heap memory is reported once the flow is updated.
+        let memory = std::mem::size_of::<C::FlowId>()
+            + std::mem::size_of::<FlowEntry<C, T>>()
+            + std::mem::size_of_val(&*flow.borrow());
+
+        self.make_room(1, memory);
+
+        let order = self.next_order;
+        self.next_order += 1;
+        self.order.insert(order, flow_id.clone());
+        let entry = FlowEntry {
+            flow,
+            id,
+            last_activity: self.now,
+            order,
+            memory,
+            heap_memory: 0,
+            tag: T::default(),
+            granted: None,
+            closed: false,
+        };
+        Self::notify(&mut self.listener, FlowEventKind::Created, &entry, flow_id);
+        self.flows.insert(flow_id.clone(), entry);
+
+        self.stats.memory += memory;
+        self.stats.created += 1;
+    }
+
+    /// Evicts flows until `flows` more flows and `memory` more bytes fit in the limits. Flows used by the current packet
+    /// are kept, so the limits can be exceeded while it is classified.
+    fn make_room(&mut self, flows: usize, memory: usize) {
+        loop {
+            let full_by_count = match self.max_flows {
+                Some(max_flows) => self.flows.len() + flows > max_flows,
+                None => false,
+            };
+
+            let full_by_memory = match self.max_memory {
+                Some(max_memory) => self.stats.memory + memory > max_memory,
+                None => false,
+            };
+
+            if !(full_by_count || full_by_memory) {
+                break;
+            }
+
+            let in_use = &self.in_use;
+            match self
+                .order
+                .values()
+                .find(|flow_id| !in_use.contains(flow_id))
+            {
+                Some(flow_id) => {
+                    let flow_id = flow_id.clone();
+                    let entry = self.remove(&flow_id).unwrap();
+                    log::trace!("Evict {:?} flow. Sig: {:?}", entry.id, flow_id);
+                    Self::notify(&mut self.listener, FlowEventKind::Evicted, &entry, &flow_id);
+                    self.stats.evicted += 1;
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&mut self, flow_id: &C::FlowId) -> Option<FlowEntry<C, T>> {
+        let entry = self.flows.remove(flow_id)?;
+        self.order.remove(&entry.order);
+        self.stats.memory -= entry.memory;
+        Some(entry)
+    }
+
+    fn remove_expired(&mut self) {
+        let Self {
+            flows,
+            order,
+            timeouts,
+            now,
+            stats,
+            listener,
+            ..
+        } = self;
+
+        flows.retain(|flow_id, entry| {
+            let expired = Self::is_expired(timeouts, entry, *now);
+            if expired {
+                log::trace!("Remove expired {:?} flow. Sig: {:?}", entry.id, flow_id);
+                order.remove(&entry.order);
+                stats.memory -= entry.memory;
+                stats.expired += 1;
+                Self::notify(listener, FlowEventKind::Expired, entry, flow_id);
+            }
+            !expired
+        });
+    }
+
+    /// Flows without activity time can not expire until a packet with timestamp uses them.
+    fn is_expired(
+        timeouts: &[Option<Duration>],
+        entry: &FlowEntry<C, T>,
+        now: Option<Duration>,
+    ) -> bool {
+        match (timeouts[entry.id.inner()], now, entry.last_activity) {
+            (Some(timeout), Some(now), Some(last_activity)) => now - last_activity > timeout,
+            _ => false,
+        }
+    }
+
+    fn notify(
+        listener: &mut Option<Box<dyn FlowListener<T, C>>>,
+        kind: FlowEventKind<T>,
+        entry: &FlowEntry<C, T>,
+        flow_id: &C::FlowId,
+    ) {
+        if let Some(listener) = listener {
+            listener.on_flow_event(FlowEvent {
+                kind,
+                classifier_id: entry.id,
+                flow_id: flow_id.clone(),
+                tag: entry.tag,
+            });
+        }
+    }
 }
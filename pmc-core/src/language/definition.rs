@@ -0,0 +1,116 @@
+use super::registry::{ValueCall, ValueRegistry};
+
+use crate::base::config::Config;
+use crate::engine::Rule;
+use crate::expression::Expr;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// Owned description of an [`Expr`], where each value is described by its name in the rule
+/// language. It can be stored and built again into an [`Expr`] by a [`ValueRegistry`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "snake_case")
+)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprDefinition {
+    Value(ValueCall),
+    Not(Box<ExprDefinition>),
+    All(Vec<ExprDefinition>),
+    Any(Vec<ExprDefinition>),
+    And(Box<(ExprDefinition, ExprDefinition)>),
+    Or(Box<(ExprDefinition, ExprDefinition)>),
+}
+
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleDefinition<T> {
+    pub tag: T,
+    pub expr: ExprDefinition,
+}
+
+impl<C: Config> Expr<C> {
+    /// Fails if some value has no definition, as the values built from closures.
+    pub fn definition(&self) -> Result<ExprDefinition, String> {
+        Ok(match self {
+            Expr::Value(value) => match value.definition() {
+                Some(call) => ExprDefinition::Value(call),
+                None => return Err(format!("The value {:?} has no definition", value)),
+            },
+            Expr::Not(expr) => ExprDefinition::Not(Box::new(expr.definition()?)),
+            Expr::All(exprs) => ExprDefinition::All(
+                exprs
+                    .iter()
+                    .map(|expr| expr.definition())
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::Any(exprs) => ExprDefinition::Any(
+                exprs
+                    .iter()
+                    .map(|expr| expr.definition())
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::And(pair) => {
+                ExprDefinition::And(Box::new((pair.0.definition()?, pair.1.definition()?)))
+            }
+            Expr::Or(pair) => {
+                ExprDefinition::Or(Box::new((pair.0.definition()?, pair.1.definition()?)))
+            }
+        })
+    }
+}
+
+impl<T: Copy, C: Config> Rule<T, C> {
+    pub fn definition(&self) -> Result<RuleDefinition<T>, String> {
+        Ok(RuleDefinition {
+            tag: self.tag(),
+            expr: self.expr().definition()?,
+        })
+    }
+}
+
+impl<C: Config> ValueRegistry<C> {
+    pub fn build_expr(&self, definition: &ExprDefinition) -> Result<Expr<C>, String> {
+        Ok(match definition {
+            ExprDefinition::Value(call) => self.build(call)?,
+            ExprDefinition::Not(definition) => !self.build_expr(definition)?,
+            ExprDefinition::All(definitions) => Expr::all(
+                definitions
+                    .iter()
+                    .map(|definition| self.build_expr(definition))
+                    .collect::<Result<_, _>>()?,
+            ),
+            ExprDefinition::Any(definitions) => Expr::any(
+                definitions
+                    .iter()
+                    .map(|definition| self.build_expr(definition))
+                    .collect::<Result<_, _>>()?,
+            ),
+            ExprDefinition::And(pair) => self.build_expr(&pair.0)? & self.build_expr(&pair.1)?,
+            ExprDefinition::Or(pair) => self.build_expr(&pair.0)? | self.build_expr(&pair.1)?,
+        })
+    }
+
+    /// The error message contains the position of the failing rule.
+    pub fn build_rules<T: Copy>(
+        &self,
+        definitions: &[RuleDefinition<T>],
+    ) -> Result<Vec<Rule<T, C>>, String> {
+        definitions
+            .iter()
+            .enumerate()
+            .map(|(index, definition)| {
+                let expr = self
+                    .build_expr(&definition.expr)
+                    .map_err(|message| format!("Rule {}: {}", index, message))?;
+                Ok(Rule::new(definition.tag, expr))
+            })
+            .collect()
+    }
+}
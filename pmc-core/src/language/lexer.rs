@@ -0,0 +1,189 @@
+use super::ParseError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    Str(String),
+    Int(u64),
+    Dot,
+    Comma,
+    LeftParen,
+    RightParen,
+    And,
+    Or,
+    Not,
+    Equal,
+    NotEqual,
+    Match,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Token {
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Ident(ident) => format!("'{}'", ident),
+            Token::Str(value) => format!("\"{}\"", value),
+            Token::Int(value) => format!("'{}'", value),
+            Token::Dot => "'.'".into(),
+            Token::Comma => "','".into(),
+            Token::LeftParen => "'('".into(),
+            Token::RightParen => "')'".into(),
+            Token::And => "'&&'".into(),
+            Token::Or => "'||'".into(),
+            Token::Not => "'!'".into(),
+            Token::Equal => "'=='".into(),
+            Token::NotEqual => "'!='".into(),
+            Token::Match => "'~'".into(),
+            Token::Less => "'<'".into(),
+            Token::LessEqual => "'<='".into(),
+            Token::Greater => "'>'".into(),
+            Token::GreaterEqual => "'>='".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn error(self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+/// Splits the text into tokens. `start` is the position of the first character of `text`.
+/// A `#` outside a string starts a comment until the end of the line.
+pub fn tokenize(text: &str, start: Position) -> Result<Vec<(Token, Position)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut position = start;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let token_position = position;
+        position.column += 1;
+
+        let token = match c {
+            '\n' => {
+                position.line += 1;
+                position.column = 1;
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            '#' => {
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                    position.column += 1;
+                }
+                continue;
+            }
+            '.' => Token::Dot,
+            ',' => Token::Comma,
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            '~' => Token::Match,
+            '&' | '|' | '=' => match chars.peek() {
+                Some(&next) if next == c => {
+                    chars.next();
+                    position.column += 1;
+                    match c {
+                        '&' => Token::And,
+                        '|' => Token::Or,
+                        _ => Token::Equal,
+                    }
+                }
+                _ => return Err(token_position.error(format!("Expected '{}{}'", c, c))),
+            },
+            '!' | '<' | '>' => match chars.peek() {
+                Some('=') => {
+                    chars.next();
+                    position.column += 1;
+                    match c {
+                        '!' => Token::NotEqual,
+                        '<' => Token::LessEqual,
+                        _ => Token::GreaterEqual,
+                    }
+                }
+                _ => match c {
+                    '!' => Token::Not,
+                    '<' => Token::Less,
+                    _ => Token::Greater,
+                },
+            },
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    let c = chars
+                        .next()
+                        .ok_or_else(|| token_position.error("Unclosed string"))?;
+                    position.column += 1;
+                    match c {
+                        '"' => break,
+                        '\n' => return Err(token_position.error("Unclosed string")),
+                        '\\' => {
+                            let escaped = chars
+                                .next()
+                                .ok_or_else(|| token_position.error("Unclosed string"))?;
+                            position.column += 1;
+                            match escaped {
+                                '"' | '\\' => value.push(escaped),
+                                _ => {
+                                    let mut escape_position = position;
+                                    escape_position.column -= 2;
+                                    return Err(escape_position
+                                        .error(format!("Unknown escape '\\{}'", escaped)));
+                                }
+                            }
+                        }
+                        c => value.push(c),
+                    }
+                }
+                Token::Str(value)
+            }
+            c if c.is_ascii_digit() => {
+                let mut digits = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_digit() {
+                        break;
+                    }
+                    digits.push(c);
+                    chars.next();
+                    position.column += 1;
+                }
+                let value = digits
+                    .parse()
+                    .map_err(|_| token_position.error(format!("Number {} too big", digits)))?;
+                Token::Int(value)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                    position.column += 1;
+                }
+                Token::Ident(ident)
+            }
+            c => return Err(token_position.error(format!("Unexpected character '{}'", c))),
+        };
+
+        tokens.push((token, token_position));
+    }
+
+    Ok(tokens)
+}
@@ -0,0 +1,32 @@
+//! Text language to write rules, as `tcp.server_port == 80 && http.header("Host") ~ "example.com"`.
+//! The values that can be written are registered by each classifier crate in a [`ValueRegistry`].
+//!
+//! The same names are used to store rules: see [`ExprDefinition`] and the `serde` feature.
+
+mod definition;
+mod lexer;
+mod parser;
+mod registry;
+
+pub use definition::{ExprDefinition, RuleDefinition};
+pub use parser::{parse_expr, parse_rules};
+pub use registry::{Comparison, Literal, Operator, ValueBuilder, ValueCall, ValueRegistry};
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Starting at 1.
+    pub line: usize,
+    /// Starting at 1, in characters.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
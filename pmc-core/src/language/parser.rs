@@ -0,0 +1,273 @@
+use super::lexer::{self, Position, Token};
+use super::registry::{Comparison, Literal, Operator, ValueCall, ValueRegistry};
+use super::ParseError;
+
+use crate::base::config::Config;
+use crate::engine::Rule;
+use crate::expression::Expr;
+
+/// Parses an expression, as `tcp.server_port == 80 && http.header("Host") ~ "example.com"`.
+///
+/// From lowest to highest precedence the operators are: `||`, `&&`, `!` and comparisons.
+/// Parentheses can be used to group expressions.
+pub fn parse_expr<C: Config>(
+    source: &str,
+    registry: &ValueRegistry<C>,
+) -> Result<Expr<C>, ParseError> {
+    let tokens = lexer::tokenize(source, Position { line: 1, column: 1 })?;
+    Parser::new(tokens, registry, end_position(source)).parse()
+}
+
+/// Parses a rule per line, as `tag: expression`.
+/// Empty lines and lines starting with `#` are ignored.
+/// The rules keep the file order as priority.
+pub fn parse_rules<'a, C: Config>(
+    source: &'a str,
+    registry: &ValueRegistry<C>,
+) -> Result<Vec<Rule<&'a str, C>>, ParseError> {
+    let mut rules = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let start_column = line.chars().count() - trimmed.chars().count() + 1;
+        let (tag, expression) = match trimmed.split_once(':') {
+            Some((tag, expression)) => (tag.trim_end(), expression),
+            None => {
+                let position = Position {
+                    line: line_number,
+                    column: start_column,
+                };
+                return Err(position.error("Expected 'tag: expression'"));
+            }
+        };
+
+        if tag.is_empty() {
+            let position = Position {
+                line: line_number,
+                column: start_column,
+            };
+            return Err(position.error("Expected a tag before ':'"));
+        }
+
+        let expression_start = Position {
+            line: line_number,
+            column: start_column + trimmed.chars().take_while(|&c| c != ':').count() + 1,
+        };
+        let tokens = lexer::tokenize(expression, expression_start)?;
+        let end = Position {
+            line: line_number,
+            column: line.chars().count() + 1,
+        };
+        let expr = Parser::new(tokens, registry, end).parse()?;
+
+        rules.push(Rule::new(tag, expr));
+    }
+
+    Ok(rules)
+}
+
+fn end_position(source: &str) -> Position {
+    let line = source.lines().count().max(1);
+    let column = match source.ends_with('\n') {
+        true => 1,
+        false => {
+            source
+                .lines()
+                .last()
+                .map(|last| last.chars().count())
+                .unwrap_or(0)
+                + 1
+        }
+    };
+    Position {
+        line: line + source.ends_with('\n') as usize,
+        column,
+    }
+}
+
+struct Parser<'r, C: Config> {
+    tokens: Vec<(Token, Position)>,
+    next: usize,
+    end: Position,
+    registry: &'r ValueRegistry<C>,
+}
+
+impl<'r, C: Config> Parser<'r, C> {
+    fn new(tokens: Vec<(Token, Position)>, registry: &'r ValueRegistry<C>, end: Position) -> Self {
+        Self {
+            tokens,
+            next: 0,
+            end,
+            registry,
+        }
+    }
+
+    fn parse(mut self) -> Result<Expr<C>, ParseError> {
+        let expr = self.parse_or()?;
+        match self.tokens.get(self.next) {
+            Some((token, position)) => {
+                Err(position
+                    .error(format!("Unexpected {}, expected '&&' or '||'", token.describe())))
+            }
+            None => Ok(expr),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    fn position(&self) -> Position {
+        self.tokens
+            .get(self.next)
+            .map(|(_, position)| *position)
+            .unwrap_or(self.end)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(token, _)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        let position = self.position();
+        match self.advance() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(position.error(format!(
+                "Expected {}, found {}",
+                expected.describe(),
+                token.describe()
+            ))),
+            None => Err(position.error(format!("Expected {}, found the end", expected.describe()))),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr<C>, ParseError> {
+        let mut expr = self.parse_and()?;
+        while let Some(Token::Or) = self.peek() {
+            self.advance();
+            expr = expr | self.parse_and()?;
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr<C>, ParseError> {
+        let mut expr = self.parse_unary()?;
+        while let Some(Token::And) = self.peek() {
+            self.advance();
+            expr = expr & self.parse_unary()?;
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr<C>, ParseError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.advance();
+                Ok(!self.parse_unary()?)
+            }
+            Some(Token::LeftParen) => {
+                self.advance();
+                let expr = self.parse_or()?;
+                self.expect(Token::RightParen)?;
+                Ok(expr)
+            }
+            _ => self.parse_value(),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Expr<C>, ParseError> {
+        let position = self.position();
+        let mut name = self.parse_ident("a value name")?;
+        while let Some(Token::Dot) = self.peek() {
+            self.advance();
+            name.push('.');
+            name.push_str(&self.parse_ident("a name after '.'")?);
+        }
+
+        let mut args = Vec::new();
+        if let Some(Token::LeftParen) = self.peek() {
+            self.advance();
+            if let Some(Token::RightParen) = self.peek() {
+                self.advance();
+            } else {
+                loop {
+                    args.push(self.parse_literal()?);
+                    match self.peek() {
+                        Some(Token::Comma) => {
+                            self.advance();
+                        }
+                        _ => break self.expect(Token::RightParen)?,
+                    }
+                }
+            }
+        }
+
+        let (operator, negated) = match self.peek() {
+            Some(Token::Equal) => (Some(Operator::Equal), false),
+            Some(Token::NotEqual) => (Some(Operator::Equal), true),
+            Some(Token::Match) => (Some(Operator::Match), false),
+            Some(Token::Less) => (Some(Operator::Less), false),
+            Some(Token::LessEqual) => (Some(Operator::LessEqual), false),
+            Some(Token::Greater) => (Some(Operator::Greater), false),
+            Some(Token::GreaterEqual) => (Some(Operator::GreaterEqual), false),
+            _ => (None, false),
+        };
+
+        let comparison = match operator {
+            Some(operator) => {
+                self.advance();
+                Some(Comparison {
+                    operator,
+                    value: self.parse_literal()?,
+                })
+            }
+            None => None,
+        };
+
+        let call = ValueCall {
+            name,
+            args,
+            comparison,
+        };
+
+        let expr = self
+            .registry
+            .build(&call)
+            .map_err(|message| position.error(message))?;
+
+        Ok(match negated {
+            true => !expr,
+            false => expr,
+        })
+    }
+
+    fn parse_ident(&mut self, expected: &str) -> Result<String, ParseError> {
+        let position = self.position();
+        match self.advance() {
+            Some(Token::Ident(ident)) => Ok(ident),
+            Some(token) => {
+                Err(position.error(format!("Expected {}, found {}", expected, token.describe())))
+            }
+            None => Err(position.error(format!("Expected {}, found the end", expected))),
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<Literal, ParseError> {
+        let position = self.position();
+        match self.advance() {
+            Some(Token::Str(value)) => Ok(Literal::Str(value)),
+            Some(Token::Int(value)) => Ok(Literal::Int(value)),
+            Some(Token::Ident(value)) => Ok(Literal::Ident(value)),
+            Some(token) => {
+                Err(position.error(format!("Expected a literal, found {}", token.describe())))
+            }
+            None => Err(position.error("Expected a literal, found the end")),
+        }
+    }
+}
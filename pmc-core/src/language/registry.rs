@@ -0,0 +1,326 @@
+use crate::base::config::Config;
+use crate::expression::Expr;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// When deserialized, names are read as strings.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", untagged)
+)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Literal {
+    Str(String),
+    Int(u64),
+    Ident(String),
+}
+
+impl Literal {
+    pub fn as_str(&self) -> Result<&str, String> {
+        match self {
+            Literal::Str(value) => Ok(value),
+            _ => Err(format!("Expected a string, found {}", self)),
+        }
+    }
+
+    pub fn as_int(&self) -> Result<u64, String> {
+        match self {
+            Literal::Int(value) => Ok(*value),
+            _ => Err(format!("Expected a number, found {}", self)),
+        }
+    }
+
+    pub fn as_u8(&self) -> Result<u8, String> {
+        let value = self.as_int()?;
+        u8::try_from(value).map_err(|_| format!("The number {} does not fit in 8 bits", value))
+    }
+
+    pub fn as_u16(&self) -> Result<u16, String> {
+        let value = self.as_int()?;
+        u16::try_from(value).map_err(|_| format!("The number {} does not fit in 16 bits", value))
+    }
+
+    pub fn as_ident(&self) -> Result<&str, String> {
+        match self {
+            Literal::Ident(value) => Ok(value),
+            _ => Err(format!("Expected a name, found {}", self)),
+        }
+    }
+
+    /// Text of a string or a name.
+    pub fn as_text(&self) -> Result<&str, String> {
+        match self {
+            Literal::Str(value) | Literal::Ident(value) => Ok(value),
+            Literal::Int(_) => Err(format!("Expected a string or a name, found {}", self)),
+        }
+    }
+}
+
+impl From<&str> for Literal {
+    fn from(value: &str) -> Self {
+        Literal::Str(value.into())
+    }
+}
+
+impl From<String> for Literal {
+    fn from(value: String) -> Self {
+        Literal::Str(value)
+    }
+}
+
+impl From<u64> for Literal {
+    fn from(value: u64) -> Self {
+        Literal::Int(value)
+    }
+}
+
+impl From<u32> for Literal {
+    fn from(value: u32) -> Self {
+        Literal::Int(value.into())
+    }
+}
+
+impl From<u16> for Literal {
+    fn from(value: u16) -> Self {
+        Literal::Int(value.into())
+    }
+}
+
+impl From<u8> for Literal {
+    fn from(value: u8) -> Self {
+        Literal::Int(value.into())
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Str(value) => write!(f, "\"{}\"", value),
+            Literal::Int(value) => write!(f, "{}", value),
+            Literal::Ident(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Comparison operators.
+/// `!=` never reaches the value builders: it is built as the negation of `==`.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operator {
+    /// `==`
+    #[cfg_attr(feature = "serde", serde(rename = "=="))]
+    Equal,
+    /// `~`, the meaning depends on the value, for example, "contains".
+    #[cfg_attr(feature = "serde", serde(rename = "~"))]
+    Match,
+    /// `<`
+    #[cfg_attr(feature = "serde", serde(rename = "<"))]
+    Less,
+    /// `<=`
+    #[cfg_attr(feature = "serde", serde(rename = "<="))]
+    LessEqual,
+    /// `>`
+    #[cfg_attr(feature = "serde", serde(rename = ">"))]
+    Greater,
+    /// `>=`
+    #[cfg_attr(feature = "serde", serde(rename = ">="))]
+    GreaterEqual,
+}
+
+impl Operator {
+    /// Compares `left` against `right`. `Match` is considered as `Equal`.
+    pub fn compare<T: PartialOrd>(self, left: T, right: T) -> bool {
+        match self {
+            Operator::Equal | Operator::Match => left == right,
+            Operator::Less => left < right,
+            Operator::LessEqual => left <= right,
+            Operator::Greater => left > right,
+            Operator::GreaterEqual => left >= right,
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operator::Equal => write!(f, "=="),
+            Operator::Match => write!(f, "~"),
+            Operator::Less => write!(f, "<"),
+            Operator::LessEqual => write!(f, "<="),
+            Operator::Greater => write!(f, ">"),
+            Operator::GreaterEqual => write!(f, ">="),
+        }
+    }
+}
+
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Comparison {
+    pub operator: Operator,
+    pub value: Literal,
+}
+
+/// A value as written in a rule: `name(args) operator literal`,
+/// where the arguments and the comparison are optional.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ValueCall {
+    pub name: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub args: Vec<Literal>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub comparison: Option<Comparison>,
+}
+
+impl ValueCall {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            args: Vec::new(),
+            comparison: None,
+        }
+    }
+
+    pub fn with_arg(mut self, arg: impl Into<Literal>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn with_comparison(mut self, operator: Operator, value: impl Into<Literal>) -> Self {
+        self.comparison = Some(Comparison {
+            operator,
+            value: value.into(),
+        });
+        self
+    }
+
+    /// For values used as a condition by themselves, as `tcp.established`.
+    pub fn expect_alone(&self) -> Result<(), String> {
+        self.expect_args(0)?;
+        self.expect_no_comparison()
+    }
+
+    pub fn expect_no_comparison(&self) -> Result<(), String> {
+        match &self.comparison {
+            Some(comparison) => {
+                Err(format!("'{}' can not be compared with '{}'", self.name, comparison.operator))
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn expect_args(&self, len: usize) -> Result<(), String> {
+        match self.args.len() == len {
+            true => Ok(()),
+            false => {
+                Err(format!("'{}' expects {} arguments, found {}", self.name, len, self.args.len()))
+            }
+        }
+    }
+
+    pub fn arg(&self, index: usize) -> Result<&Literal, String> {
+        self.args
+            .get(index)
+            .ok_or_else(|| format!("'{}' expects an argument at position {}", self.name, index + 1))
+    }
+
+    pub fn comparison(&self) -> Result<(Operator, &Literal), String> {
+        match &self.comparison {
+            Some(comparison) => Ok((comparison.operator, &comparison.value)),
+            None => Err(format!("'{}' must be compared with a value", self.name)),
+        }
+    }
+
+    /// Comparison by `==`, `<`, `<=`, `>` or `>=`.
+    pub fn ordered_comparison(&self) -> Result<(Operator, &Literal), String> {
+        match self.comparison()? {
+            (Operator::Match, _) => {
+                Err(format!("'{}' can not be compared with '{}'", self.name, Operator::Match))
+            }
+            comparison => Ok(comparison),
+        }
+    }
+
+    /// Literal of a comparison that only accepts the operator `expected`.
+    pub fn compared_by(&self, expected: Operator) -> Result<&Literal, String> {
+        match self.comparison()? {
+            (operator, literal) if operator == expected => Ok(literal),
+            (operator, _) => Err(format!(
+                "'{}' must be compared with '{}', found '{}'",
+                self.name, expected, operator
+            )),
+        }
+    }
+}
+
+pub type ValueBuilder<C> = Box<dyn Fn(&ValueCall) -> Result<Expr<C>, String> + Send + Sync>;
+
+/// Expression values that can be written in the rule language, by name.
+pub struct ValueRegistry<C: Config> {
+    builders: HashMap<String, ValueBuilder<C>>,
+}
+
+impl<C: Config> Default for ValueRegistry<C> {
+    fn default() -> Self {
+        Self {
+            builders: HashMap::new(),
+        }
+    }
+}
+
+impl<C: Config> ValueRegistry<C> {
+    /// Registers a value by its name, as `tcp.server_port`.
+    /// The builder checks the arguments and the comparison written with the value.
+    pub fn with<F>(mut self, name: &str, builder: F) -> Self
+    where F: Fn(&ValueCall) -> Result<Expr<C>, String> + Send + Sync + 'static {
+        let previous = self.builders.insert(name.into(), Box::new(builder));
+        assert!(previous.is_none(), "The value '{}' is already registered", name);
+        self
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.builders.contains_key(name)
+    }
+
+    /// Registered names, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self
+            .builders
+            .keys()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+
+    pub(crate) fn build(&self, call: &ValueCall) -> Result<Expr<C>, String> {
+        match self.builders.get(&call.name) {
+            Some(builder) => builder(call),
+            None => Err(format!("Unknown value '{}'", call.name)),
+        }
+    }
+}
@@ -3,9 +3,17 @@ pub mod packet;
 
 pub mod engine;
 pub mod expression;
+pub mod language;
 pub mod loader;
+pub mod metrics;
+pub mod sharded_engine;
+pub mod trace;
+
+#[cfg(feature = "derive")]
+pub use pmc_derive::{expression_value, ClassifierId};
 
 mod analyzer_cache;
+mod compiler;
 mod controller;
 mod dependency_checker;
 mod flow_pool;
@@ -5,10 +5,55 @@ use crate::base::config::{ClassifierId, Config};
 use crate::controller::classifier::ClassifierController;
 use crate::dependency_checker::DependencyChecker;
 
+use std::fmt;
+
+/// Invalid configuration of the classifiers or the rules, found when the engine is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError<I: ClassifierId> {
+    /// Two classifiers with the same ID.
+    DuplicatedId(I),
+    /// The classifier depends on itself through this parent.
+    DependencyCycle(I, I),
+    /// The classifier can not be reached from [`ClassifierId::INITIAL`].
+    UnreachableClassifier(I),
+    /// The rule with this priority uses a value of a classifier not loaded.
+    MissingClassifier(usize, I),
+    /// The rule with this priority uses the default tag, reserved for not matching packets.
+    ReservedTag(usize),
+    /// The rule with this priority has an `all` or `any` expression without expressions.
+    EmptyExpressionList(usize),
+}
+
+impl<I: ClassifierId> fmt::Display for LoadError<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicatedId(id) => write!(f, "classifier {:?} loaded twice", id),
+            Self::DependencyCycle(id, prev_id) => {
+                write!(f, "classifier {:?} depends on itself through {:?}", id, prev_id)
+            }
+            Self::UnreachableClassifier(id) => {
+                write!(f, "classifier {:?} can not be reached from {:?}", id, I::INITIAL)
+            }
+            Self::MissingClassifier(rule, id) => {
+                write!(f, "rule {} uses the classifier {:?}, that is not loaded", rule, id)
+            }
+            Self::ReservedTag(rule) => {
+                write!(f, "rule {} uses the default tag, reserved for not matching packets", rule)
+            }
+            Self::EmptyExpressionList(rule) => {
+                write!(f, "rule {} has a list of expressions without expressions", rule)
+            }
+        }
+    }
+}
+
+impl<I: ClassifierId> std::error::Error for LoadError<I> {}
+
+type LoadedClassifiers<C> = (AnalyzerCache<C>, DependencyChecker<<C as Config>::ClassifierId>);
+
 pub struct ClassifierLoader<C: Config> {
     classifiers: Vec<Option<Box<dyn ClassifierController<C>>>>,
-    ids_relations: Vec<(C::ClassifierId, C::ClassifierId)>,
-    last_id: usize,
+    ids_relations: Vec<(C::ClassifierId, &'static [C::ClassifierId])>,
 }
 
 impl<C: Config> Default for ClassifierLoader<C> {
@@ -16,29 +61,33 @@ impl<C: Config> Default for ClassifierLoader<C> {
         Self {
             classifiers: (0..C::ClassifierId::TOTAL).map(|_| None).collect(),
             ids_relations: Vec::default(),
-            last_id: 0,
         }
     }
 }
 
 impl<C: Config> ClassifierLoader<C> {
-    pub fn with<B>(mut self, classifier: B) -> Self
+    pub fn with<B>(mut self, classifier: B) -> Result<Self, LoadError<C::ClassifierId>>
     where B: for<'a> Classifier<'a, C> + 'static {
-        assert!(
-            B::Analyzer::ID > self.last_id.into(),
-            "Expected ID with higher value than {:?}",
-            B::Analyzer::ID
-        );
-
-        self.classifiers[B::Analyzer::ID.inner()] =
-            Some(<dyn ClassifierController<C>>::new(classifier));
+        let slot = &mut self.classifiers[B::Analyzer::ID.inner()];
+        if slot.is_some() {
+            return Err(LoadError::DuplicatedId(B::Analyzer::ID));
+        }
+        *slot = Some(<dyn ClassifierController<C>>::new(classifier));
 
         self.ids_relations
-            .push((B::Analyzer::ID, B::Analyzer::PREV_ID));
-        self
+            .push((B::Analyzer::ID, B::Analyzer::PREV_IDS));
+        Ok(self)
     }
 
-    pub(crate) fn split(self) -> (AnalyzerCache<C>, DependencyChecker<C::ClassifierId>) {
-        (AnalyzerCache::new(self.classifiers), DependencyChecker::new(self.ids_relations))
+    pub(crate) fn split(self) -> Result<LoadedClassifiers<C>, LoadError<C::ClassifierId>> {
+        let dependency_checker = DependencyChecker::new(&self.ids_relations)?;
+
+        for &(id, _) in &self.ids_relations {
+            if !dependency_checker.is_reachable(id) {
+                return Err(LoadError::UnreachableClassifier(id));
+            }
+        }
+
+        Ok((AnalyzerCache::new(self.classifiers), dependency_checker))
     }
 }
@@ -0,0 +1,233 @@
+use crate::base::analyzer::{AnalyzerError, AnalyzerErrorKind};
+use crate::base::config::{ClassifierId, Config};
+use crate::engine::{Rule, RuleValueAction};
+use crate::flow_pool::FlowStats;
+
+use std::collections::HashMap;
+use std::ops::AddAssign;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrafficMetrics {
+    pub packets: usize,
+    pub bytes: usize,
+}
+
+impl AddAssign for TrafficMetrics {
+    fn add_assign(&mut self, other: Self) {
+        self.packets += other.packets;
+        self.bytes += other.bytes;
+    }
+}
+
+/// How the classification decisions were taken, see [`RuleValueAction`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecisionMetrics {
+    pub computed: usize,
+    pub computed_and_cached: usize,
+    pub cached: usize,
+}
+
+impl DecisionMetrics {
+    pub fn total(&self) -> usize {
+        self.computed + self.computed_and_cached + self.cached
+    }
+
+    fn add(&mut self, action: RuleValueAction) {
+        match action {
+            RuleValueAction::Computed => self.computed += 1,
+            RuleValueAction::ComputedAndCached => self.computed_and_cached += 1,
+            RuleValueAction::Cached => self.cached += 1,
+        }
+    }
+}
+
+impl AddAssign for DecisionMetrics {
+    fn add_assign(&mut self, other: Self) {
+        self.computed += other.computed;
+        self.computed_and_cached += other.computed_and_cached;
+        self.cached += other.cached;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleMetrics<T> {
+    pub tag: T,
+    /// Decisions of the packets matched by the rule.
+    pub decisions: DecisionMetrics,
+}
+
+impl<T> RuleMetrics<T> {
+    pub fn matches(&self) -> usize {
+        self.decisions.total()
+    }
+}
+
+/// Snapshot of the engine counters since its creation or the last reset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineMetrics<T, I: ClassifierId> {
+    /// Classified packets with their whole length.
+    pub packets: TrafficMetrics,
+    /// Rule counters, by rule priority.
+    pub rules: Vec<RuleMetrics<T>>,
+    /// Packets and payload bytes by tag, in order of appearance.
+    /// Not matching packets are counted in the default tag.
+    /// Classifying by all the rules, a packet is counted once in each matching tag.
+    pub tags: Vec<(T, TrafficMetrics)>,
+    /// Decisions of all the matches.
+    pub decisions: DecisionMetrics,
+    /// Number of analyzers built, by classifier.
+    pub analyzer_builds: HashMap<I, usize>,
+    /// Number of aborted analyses, by the classifier and the kind of the error.
+    pub aborts: HashMap<(I, AnalyzerErrorKind), usize>,
+    pub flows: FlowStats,
+}
+
+impl<T: Copy + Eq, I: ClassifierId> EngineMetrics<T, I> {
+    pub fn tag(&self, tag: T) -> TrafficMetrics {
+        self.tags
+            .iter()
+            .find(|(current, _)| *current == tag)
+            .map(|(_, traffic)| *traffic)
+            .unwrap_or_default()
+    }
+
+    pub fn aborts_by_classifier(&self, id: I) -> usize {
+        self.aborts
+            .iter()
+            .filter(|((current, _), _)| *current == id)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Adds the counters of an engine with the same rules.
+    pub fn merge(&mut self, other: &Self) {
+        assert_eq!(self.rules.len(), other.rules.len(), "The engines must have the same rules");
+
+        self.packets += other.packets;
+        for (rule, other_rule) in self.rules.iter_mut().zip(&other.rules) {
+            rule.decisions += other_rule.decisions;
+        }
+        for (tag, traffic) in &other.tags {
+            match self.tags.iter_mut().find(|(current, _)| current == tag) {
+                Some((_, current)) => *current += *traffic,
+                None => self.tags.push((*tag, *traffic)),
+            }
+        }
+        self.decisions += other.decisions;
+        for (id, count) in &other.analyzer_builds {
+            *self.analyzer_builds.entry(*id).or_default() += count;
+        }
+        for (reason, count) in &other.aborts {
+            *self.aborts.entry(*reason).or_default() += count;
+        }
+        self.flows += other.flows;
+    }
+}
+
+/// Counters updated by the engine for each packet.
+pub(crate) struct MetricsCounters<T, C: Config> {
+    packets: TrafficMetrics,
+    rules: Vec<RuleMetrics<T>>,
+    tags: Vec<(T, TrafficMetrics)>,
+    decisions: DecisionMetrics,
+    analyzer_builds: Vec<usize>,
+    aborts: HashMap<(C::ClassifierId, AnalyzerErrorKind), usize>,
+}
+
+impl<T: Copy + Eq, C: Config> MetricsCounters<T, C> {
+    pub fn new(rules: &[Rule<T, C>]) -> Self {
+        Self {
+            packets: TrafficMetrics::default(),
+            rules: rules.iter().map(Self::rule_metrics).collect(),
+            tags: Vec::new(),
+            decisions: DecisionMetrics::default(),
+            analyzer_builds: vec![0; C::ClassifierId::TOTAL],
+            aborts: HashMap::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.packets = TrafficMetrics::default();
+        for rule in &mut self.rules {
+            rule.decisions = DecisionMetrics::default();
+        }
+        self.tags.clear();
+        self.decisions = DecisionMetrics::default();
+        self.analyzer_builds.iter_mut().for_each(|count| *count = 0);
+        self.aborts.clear();
+    }
+
+    /// Keeps the counters of the rules that remain after a rule update.
+    /// `origins` contains the previous index of each rule or `None` for new rules.
+    pub fn update_rules(&mut self, rules: &[Rule<T, C>], origins: &[Option<usize>]) {
+        self.rules = rules
+            .iter()
+            .zip(origins)
+            .map(|(rule, origin)| match origin {
+                Some(origin) => self.rules[*origin].clone(),
+                None => Self::rule_metrics(rule),
+            })
+            .collect();
+    }
+
+    pub fn record_packet(&mut self, packet_len: usize) {
+        self.packets += TrafficMetrics {
+            packets: 1,
+            bytes: packet_len,
+        };
+    }
+
+    pub fn record_match(&mut self, priority: usize, action: RuleValueAction) {
+        self.rules[priority].decisions.add(action);
+        self.decisions.add(action);
+    }
+
+    pub fn record_tag(&mut self, tag: T, payload_bytes: usize) {
+        let traffic = TrafficMetrics {
+            packets: 1,
+            bytes: payload_bytes,
+        };
+        match self.tags.iter_mut().find(|(current, _)| *current == tag) {
+            Some((_, current)) => *current += traffic,
+            None => self.tags.push((tag, traffic)),
+        }
+    }
+
+    pub fn record_analyzers(&mut self, ids: &[C::ClassifierId]) {
+        for id in ids {
+            self.analyzer_builds[id.inner()] += 1;
+        }
+    }
+
+    pub fn record_abort(&mut self, error: &AnalyzerError<C::ClassifierId>) {
+        *self
+            .aborts
+            .entry((error.classifier_id, error.kind))
+            .or_default() += 1;
+    }
+
+    pub fn snapshot(&self, flows: FlowStats) -> EngineMetrics<T, C::ClassifierId> {
+        EngineMetrics {
+            packets: self.packets,
+            rules: self.rules.clone(),
+            tags: self.tags.clone(),
+            decisions: self.decisions,
+            analyzer_builds: self
+                .analyzer_builds
+                .iter()
+                .enumerate()
+                .filter(|(_, count)| **count > 0)
+                .map(|(id, count)| (C::ClassifierId::from(id), *count))
+                .collect(),
+            aborts: self.aborts.clone(),
+            flows,
+        }
+    }
+
+    fn rule_metrics(rule: &Rule<T, C>) -> RuleMetrics<T> {
+        RuleMetrics {
+            tag: rule.tag(),
+            decisions: DecisionMetrics::default(),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use std::fmt;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Direction {
@@ -6,6 +7,15 @@ pub enum Direction {
     Downlink,
 }
 
+impl Direction {
+    pub fn reverse(self) -> Self {
+        match self {
+            Self::Uplink => Self::Downlink,
+            Self::Downlink => Self::Uplink,
+        }
+    }
+}
+
 impl From<bool> for Direction {
     fn from(value: bool) -> Self {
         match value {
@@ -24,7 +34,105 @@ impl fmt::Display for Direction {
     }
 }
 
+/// Protocol at the start of the packet data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LinkType {
+    Ethernet,
+    /// Linux cooked capture, used when capturing from any interface.
+    LinuxSll,
+    /// IPv4 or IPv6, without link layer header.
+    RawIp,
+    /// Other pcap link type.
+    Other(u32),
+}
+
+impl LinkType {
+    /// Link type from its value in the pcap formats.
+    pub fn from_pcap(value: u32) -> Self {
+        match value {
+            1 => Self::Ethernet,
+            113 => Self::LinuxSll,
+            101 | 228 | 229 => Self::RawIp,
+            _ => Self::Other(value),
+        }
+    }
+
+    /// Value of the link type in the pcap formats.
+    pub fn pcap_value(self) -> u32 {
+        match self {
+            Self::Ethernet => 1,
+            Self::LinuxSll => 113,
+            Self::RawIp => 101,
+            Self::Other(value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Packet<'a> {
     pub data: &'a [u8],
     pub direction: Direction,
+    /// Capture time, used to expire idle flows.
+    pub timestamp: Option<Duration>,
+    /// Length of the packet in the wire, from the start of `data`.
+    /// It is higher than the length of `data` if the capture truncated the packet.
+    pub wire_len: usize,
+    /// Ingress interface, as the pcapng interface id.
+    pub interface: u32,
+    pub link_type: LinkType,
+    /// Number of the protocol of `data` declared by the header before it, as the IP protocol.
+    /// It is set by the engine as the packet is analyzed.
+    pub protocol: Option<u32>,
+}
+
+impl<'a> Packet<'a> {
+    /// Raw IP packet completely captured from the interface 0, without timestamp.
+    pub fn new(data: &'a [u8], direction: Direction) -> Self {
+        Self {
+            data,
+            direction,
+            timestamp: None,
+            wire_len: data.len(),
+            interface: 0,
+            link_type: LinkType::RawIp,
+            protocol: None,
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: Duration) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn with_wire_len(mut self, wire_len: usize) -> Self {
+        self.wire_len = wire_len;
+        self
+    }
+
+    pub fn with_interface(mut self, interface: u32) -> Self {
+        self.interface = interface;
+        self
+    }
+
+    pub fn with_link_type(mut self, link_type: LinkType) -> Self {
+        self.link_type = link_type;
+        self
+    }
+
+    /// Bytes of the packet not captured at the end of `data`.
+    pub fn truncated_len(&self) -> usize {
+        self.wire_len.saturating_sub(self.data.len())
+    }
+
+    /// The packet without its first `len` bytes, already parsed.
+    pub(crate) fn advance(&mut self, len: usize) {
+        self.data = &self.data[len..];
+        self.wire_len = self.wire_len.saturating_sub(len);
+    }
+
+    /// The packet limited to its first `len` bytes, its length in the wire.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.data = &self.data[..self.data.len().min(len)];
+        self.wire_len = len;
+    }
 }
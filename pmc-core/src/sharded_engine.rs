@@ -0,0 +1,444 @@
+use crate::analyzer_cache::AnalyzerCache;
+use crate::base::analyzer::UseFlow;
+use crate::base::config::{ClassifierId, Config};
+use crate::engine::{ClassificationResult, ClassifierEngine, FlowStats, Rule};
+use crate::loader::{ClassifierLoader, LoadError};
+use crate::metrics::EngineMetrics;
+use crate::packet::{Direction, LinkType, Packet};
+
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::Hasher;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Packets that can be queued in a shard before the dispatcher blocks.
+const SHARD_QUEUE_SIZE: usize = 4096;
+/// Results that can be queued before the shards block.
+const RESULT_QUEUE_SIZE: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct ShardedClassificationResult<T, I: ClassifierId> {
+    /// Dispatch order of the packet, starting at 0.
+    pub packet_id: u64,
+    pub shard: usize,
+    pub result: ClassificationResult<T, I>,
+}
+
+struct OwnedPacket {
+    data: Vec<u8>,
+    direction: Direction,
+    timestamp: Option<Duration>,
+    wire_len: usize,
+    interface: u32,
+    link_type: LinkType,
+    protocol: Option<u32>,
+}
+
+impl OwnedPacket {
+    fn as_packet(&self) -> Packet<'_> {
+        Packet {
+            data: &self.data,
+            direction: self.direction,
+            timestamp: self.timestamp,
+            wire_len: self.wire_len,
+            interface: self.interface,
+            link_type: self.link_type,
+            protocol: self.protocol,
+        }
+    }
+}
+
+type RuleUpdate<C, T> = Arc<dyn Fn(&mut ClassifierEngine<C, T>) + Send + Sync>;
+
+enum ShardCommand<C: Config, T> {
+    Classify(u64, OwnedPacket),
+    FlowStats,
+    Metrics,
+    UpdateRules(RuleUpdate<C, T>),
+}
+
+/// Answers of the shards, sent through the results queue so that a shard blocked by a full
+/// queue can be released while the dispatcher waits for a query.
+enum ShardOutput<C: Config, T> {
+    Classified(ShardedClassificationResult<T, C::ClassifierId>),
+    FlowStats(usize, FlowStats),
+    Metrics(usize, EngineMetrics<T, C::ClassifierId>),
+}
+
+struct Shard<C: Config, T> {
+    commands: SyncSender<ShardCommand<C, T>>,
+    thread: JoinHandle<()>,
+}
+
+/// Classification engine split into several [`ClassifierEngine`] running each one in its own
+/// thread. Packets are dispatched to a shard by the shard key of their first flow id, see
+/// [`Config::hash_shard_key()`], so all the packets of a flow are always classified by the same
+/// shard.
+/// Results not received are kept by the engine when the shards would block on a full results
+/// queue, so they should be received as the packets are dispatched.
+pub struct ShardedClassifierEngine<C: Config, T> {
+    config: C,
+    dispatcher_cache: AnalyzerCache<C>,
+    shards: Vec<Shard<C, T>>,
+    results: Receiver<ShardOutput<C, T>>,
+    /// Results received while waiting for the answer of a query.
+    pending_results: RefCell<VecDeque<ShardedClassificationResult<T, C::ClassifierId>>>,
+    next_packet_id: u64,
+}
+
+impl<C, T> ShardedClassifierEngine<C, T>
+where
+    T: fmt::Display + Default + Eq + Copy + Send + 'static,
+    C: Config + Clone + Send,
+{
+    /// Creates `shards` engines sharing the same rules.
+    /// The `loader` is called once per shard, from the shard thread, and once for the dispatcher.
+    /// The configuration is validated by the dispatcher, see [`ClassifierEngine::new()`].
+    pub fn new<L>(
+        shards: usize,
+        loader: L,
+        config: C,
+        rules: Vec<Rule<T, C>>,
+    ) -> Result<Self, LoadError<C::ClassifierId>>
+    where
+        L: Fn() -> ClassifierLoader<C> + Send + Sync + 'static,
+    {
+        assert!(shards > 0, "At least one shard is required");
+
+        let (dispatcher_cache, dependency_checker) = loader().split()?;
+        for (priority, rule) in rules.iter().enumerate() {
+            rule.validate(priority, &dependency_checker)?;
+        }
+
+        let loader = Arc::new(loader);
+        let (results_sender, results) = mpsc::sync_channel(RESULT_QUEUE_SIZE);
+        let shards = (0..shards)
+            .map(|index| {
+                let (commands, commands_receiver) = mpsc::sync_channel(SHARD_QUEUE_SIZE);
+                let engine = ShardWorker {
+                    index,
+                    loader: loader.clone(),
+                    config: config.clone(),
+                    rules: rules.clone(),
+                };
+                let results_sender = results_sender.clone();
+                let thread = thread::Builder::new()
+                    .name(format!("pmc-shard-{}", index))
+                    .spawn(move || engine.run(commands_receiver, results_sender))
+                    .expect("The shard thread must be created");
+
+                Shard { commands, thread }
+            })
+            .collect();
+
+        Ok(Self {
+            config,
+            dispatcher_cache,
+            shards,
+            results,
+            pending_results: RefCell::new(VecDeque::new()),
+            next_packet_id: 0,
+        })
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Shard that will classify the packet.
+    /// Data reassembled from several packets, such as IP fragments, is classified in the shard of
+    /// the packets that compose it, so the flows of the reassembled data must have the same shard
+    /// key as the flow of its parts.
+    pub fn shard_of(&mut self, packet: &Packet) -> usize {
+        let flow_id = self.first_flow_id(packet);
+
+        let mut hasher = DefaultHasher::new();
+        C::hash_shard_key(&flow_id, &mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// Sends a copy of the packet to its shard and returns the packet id used in the result.
+    /// While the shard queue is full, the results are moved to the engine to let the shard go on.
+    pub fn dispatch_packet(&mut self, packet: Packet) -> u64 {
+        let shard = self.shard_of(&packet);
+        let packet_id = self.next_packet_id;
+        self.next_packet_id += 1;
+
+        let packet = OwnedPacket {
+            data: packet.data.to_vec(),
+            direction: packet.direction,
+            timestamp: packet.timestamp,
+            wire_len: packet.wire_len,
+            interface: packet.interface,
+            link_type: packet.link_type,
+            protocol: packet.protocol,
+        };
+
+        self.send_command(shard, ShardCommand::Classify(packet_id, packet));
+        packet_id
+    }
+
+    /// Returns an already classified packet, if any.
+    /// Results of different shards are not ordered between them.
+    pub fn try_recv_result(&self) -> Option<ShardedClassificationResult<T, C::ClassifierId>> {
+        match self.pending_results.borrow_mut().pop_front() {
+            Some(result) => Some(result),
+            None => self.results.try_recv().ok().map(Self::classified),
+        }
+    }
+
+    /// Waits until a packet is classified.
+    pub fn recv_result(&self) -> ShardedClassificationResult<T, C::ClassifierId> {
+        match self.pending_results.borrow_mut().pop_front() {
+            Some(result) => result,
+            None => Self::classified(
+                self.results
+                    .recv()
+                    .expect("The shard threads must be running"),
+            ),
+        }
+    }
+
+    /// Flow statistics of all shards merged.
+    /// Packets dispatched before this call are classified before computing the statistics.
+    pub fn flow_stats(&self) -> FlowStats {
+        self.shard_flow_stats()
+            .into_iter()
+            .fold(FlowStats::default(), |mut total, stats| {
+                total += stats;
+                total
+            })
+    }
+
+    /// Flow statistics of each shard, by shard index.
+    pub fn shard_flow_stats(&self) -> Vec<FlowStats> {
+        self.query_shards(
+            || ShardCommand::FlowStats,
+            |output| match output {
+                ShardOutput::FlowStats(shard, stats) => (shard, stats),
+                _ => unreachable!("Only one query is answered at a time"),
+            },
+        )
+    }
+
+    /// Metrics of all shards merged.
+    /// Packets dispatched before this call are classified before computing the metrics.
+    pub fn metrics(&self) -> EngineMetrics<T, C::ClassifierId> {
+        let mut shard_metrics = self.shard_metrics().into_iter();
+        let mut total = shard_metrics.next().expect("At least one shard exists");
+        shard_metrics.for_each(|metrics| total.merge(&metrics));
+        total
+    }
+
+    /// Metrics of each shard, by shard index.
+    pub fn shard_metrics(&self) -> Vec<EngineMetrics<T, C::ClassifierId>> {
+        self.query_shards(
+            || ShardCommand::Metrics,
+            |output| match output {
+                ShardOutput::Metrics(shard, metrics) => (shard, metrics),
+                _ => unreachable!("Only one query is answered at a time"),
+            },
+        )
+    }
+
+    /// Applies the same rule changes to every shard, after the packets already dispatched.
+    /// See [`ClassifierEngine::insert_rule()`] and related methods.
+    pub fn update_rules(
+        &self,
+        update: impl Fn(&mut ClassifierEngine<C, T>) + Send + Sync + 'static,
+    ) {
+        let update: RuleUpdate<C, T> = Arc::new(update);
+        for shard in 0..self.shards.len() {
+            self.send_command(shard, ShardCommand::UpdateRules(update.clone()));
+        }
+    }
+
+    /// Waits until all dispatched packets are classified and stops the shards.
+    /// Returns the pending results sorted by packet id.
+    pub fn finish(mut self) -> Vec<ShardedClassificationResult<T, C::ClassifierId>> {
+        let mut results = self.stop_shards();
+        results.sort_by_key(|result| result.packet_id);
+        results
+    }
+
+    /// Computes the flow id until the first classifier that uses a flow.
+    /// Analyzers after it are not needed to know the shard, as the shard key of the flow id is
+    /// shared by the flows of the next analyzers.
+    fn first_flow_id(&mut self, packet: &Packet) -> C::FlowId {
+        let mut flow_id = C::FlowId::default();
+        let mut packet = *packet;
+        let mut cache = self.dispatcher_cache.prepare_for_packet();
+        let mut next_id = C::ClassifierId::INITIAL;
+
+        while next_id != C::ClassifierId::NONE {
+            match cache.update_flow_id(next_id, &mut flow_id, &packet) {
+                UseFlow::Yes | UseFlow::Abort(_) => break,
+                UseFlow::No => (),
+            }
+
+            match cache.build_analyzer(next_id, &self.config, &packet, None) {
+                Ok(info) => {
+                    if info.analyzer.quotes_packet() {
+                        packet.direction = packet.direction.reverse();
+                    }
+                    packet.advance(info.bytes_parsed);
+                    next_id = info.next_classifier_id;
+                }
+                Err(_) => break,
+            }
+        }
+
+        flow_id
+    }
+}
+
+impl<C: Config, T> ShardedClassifierEngine<C, T> {
+    /// Sends the command to all shards and waits for their answers, by shard index.
+    /// The results received meanwhile are kept for the next calls to receive them.
+    fn query_shards<R>(
+        &self,
+        command: impl Fn() -> ShardCommand<C, T>,
+        answer: impl Fn(ShardOutput<C, T>) -> (usize, R),
+    ) -> Vec<R> {
+        for shard in 0..self.shards.len() {
+            self.send_command(shard, command());
+        }
+
+        let mut answers = (0..self.shards.len()).map(|_| None).collect::<Vec<_>>();
+        let mut pending = self.shards.len();
+        while pending > 0 {
+            match self
+                .results
+                .recv()
+                .expect("The shard threads must be running")
+            {
+                ShardOutput::Classified(result) => {
+                    self.pending_results.borrow_mut().push_back(result)
+                }
+                output => {
+                    let (shard, value) = answer(output);
+                    answers[shard] = Some(value);
+                    pending -= 1;
+                }
+            }
+        }
+
+        answers.into_iter().flatten().collect()
+    }
+
+    /// Sends a command to the shard. While its queue is full, the results are moved to the pending
+    /// results, as the shard may be waiting for room in the results queue.
+    fn send_command(&self, shard: usize, mut command: ShardCommand<C, T>) {
+        loop {
+            match self.shards[shard].commands.try_send(command) {
+                Ok(()) => break,
+                Err(TrySendError::Full(not_sent)) => {
+                    command = not_sent;
+                    let output = self
+                        .results
+                        .recv()
+                        .expect("The shard threads must be running");
+                    self.pending_results
+                        .borrow_mut()
+                        .push_back(Self::classified(output));
+                }
+                Err(TrySendError::Disconnected(_)) => panic!("The shard thread must be running"),
+            }
+        }
+    }
+
+    /// Stops the shards once they classify the dispatched packets and returns the results not
+    /// received yet.
+    fn stop_shards(&mut self) -> Vec<ShardedClassificationResult<T, C::ClassifierId>> {
+        // Closing the channels finishes the shard loops.
+        let threads = self
+            .shards
+            .drain(..)
+            .map(|shard| shard.thread)
+            .collect::<Vec<_>>();
+
+        // The results queue is emptied until all the shards finish, as they block while it is full.
+        let mut results = self.pending_results.take().into_iter().collect::<Vec<_>>();
+        results.extend(self.results.iter().map(Self::classified));
+
+        for thread in threads {
+            if thread.join().is_err() {
+                log::error!("A shard thread finished with a panic");
+            }
+        }
+
+        results
+    }
+
+    /// Result of a classification. The answers to the queries are all received by the query.
+    fn classified(output: ShardOutput<C, T>) -> ShardedClassificationResult<T, C::ClassifierId> {
+        match output {
+            ShardOutput::Classified(result) => result,
+            _ => unreachable!("Only classifications are sent out of a query"),
+        }
+    }
+}
+
+impl<C: Config, T> Drop for ShardedClassifierEngine<C, T> {
+    fn drop(&mut self) {
+        self.stop_shards();
+    }
+}
+
+struct ShardWorker<C: Config, T, L> {
+    index: usize,
+    loader: Arc<L>,
+    config: C,
+    rules: Vec<Rule<T, C>>,
+}
+
+impl<C, T, L> ShardWorker<C, T, L>
+where
+    T: fmt::Display + Default + Eq + Copy,
+    C: Config,
+    L: Fn() -> ClassifierLoader<C>,
+{
+    fn run(self, commands: Receiver<ShardCommand<C, T>>, results: SyncSender<ShardOutput<C, T>>) {
+        let shard = self.index;
+        let mut engine = ClassifierEngine::new((self.loader)(), self.config, self.rules)
+            .expect("The configuration was validated by the dispatcher");
+
+        for command in commands {
+            match command {
+                ShardCommand::Classify(packet_id, packet) => {
+                    let result = engine.classify_packet(packet.as_packet());
+                    let result = ShardedClassificationResult {
+                        packet_id,
+                        shard,
+                        result,
+                    };
+                    if results.send(ShardOutput::Classified(result)).is_err() {
+                        break;
+                    }
+                }
+                ShardCommand::FlowStats => {
+                    if results
+                        .send(ShardOutput::FlowStats(shard, engine.flow_stats()))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                ShardCommand::Metrics => {
+                    if results
+                        .send(ShardOutput::Metrics(shard, engine.metrics()))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                ShardCommand::UpdateRules(update) => update(&mut engine),
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::base::analyzer::AnalyzerError;
+use crate::base::config::ClassifierId;
+use crate::engine::RuleValueAction;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueResult {
+    True,
+    False,
+    /// The analysis of the packet never reaches the classifier of the value.
+    Unreachable,
+    /// The rule granted by the flow is still valid, so the value is not checked.
+    Granted(usize),
+    /// The analysis was aborted before reaching the classifier of the value.
+    Aborted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueTrace<I: ClassifierId> {
+    pub classifier_id: I,
+    /// Debug representation of the expression value.
+    pub value: String,
+    pub result: ValueResult,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleResult {
+    Matched(RuleValueAction),
+    NotMatched,
+    /// The rule was not finished, see [`ClassificationEnd`].
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleTrace<T, I: ClassifierId> {
+    pub priority: usize,
+    pub tag: T,
+    /// Values in evaluation order. Values skipped by the `&&` and `||` short-circuits are not
+    /// listed.
+    pub values: Vec<ValueTrace<I>>,
+    pub result: RuleResult,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnalyzerTrace<I: ClassifierId> {
+    pub classifier_id: I,
+    pub bytes_parsed: usize,
+    /// The analyzer updated a flow.
+    pub flow: bool,
+}
+
+/// Check of a rule granted by the flow of the packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GrantTrace<I: ClassifierId> {
+    /// Classifier of the flow.
+    pub classifier_id: I,
+    pub rule: usize,
+    /// `false` if a rule with the same or more priority changed its result, removing the grant.
+    pub kept: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClassificationEnd<I: ClassifierId> {
+    /// The rule with this priority matched.
+    Matched(usize),
+    /// The rule with this priority was granted by the flow.
+    Granted(usize),
+    NoMatch,
+    Aborted(AnalyzerError<I>),
+}
+
+/// Decision path of a classification.
+/// See [`ClassifierEngine::classify_packet_explained()`](
+/// crate::engine::ClassifierEngine::classify_packet_explained).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassificationTrace<T, I: ClassifierId> {
+    /// Checked rules, in priority order.
+    pub rules: Vec<RuleTrace<T, I>>,
+    /// Built analyzers, in analysis order.
+    pub analyzers: Vec<AnalyzerTrace<I>>,
+    pub grants: Vec<GrantTrace<I>>,
+    pub end: ClassificationEnd<I>,
+}
+
+impl<T, I: ClassifierId> ClassificationTrace<T, I> {
+    /// Checked rule by its priority.
+    pub fn rule(&self, priority: usize) -> Option<&RuleTrace<T, I>> {
+        self.rules.iter().find(|rule| rule.priority == priority)
+    }
+}
+
+/// Events recorded while a packet is analyzed, before knowing the rule they belong to.
+pub(crate) struct AnalysisRecorder<I: ClassifierId> {
+    pub values: Vec<ValueTrace<I>>,
+    pub analyzers: Vec<AnalyzerTrace<I>>,
+    pub grants: Vec<GrantTrace<I>>,
+}
+
+impl<I: ClassifierId> Default for AnalysisRecorder<I> {
+    fn default() -> Self {
+        Self {
+            values: Vec::new(),
+            analyzers: Vec::new(),
+            grants: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use proc_macro2::{Literal, TokenStream};
+use quote::quote;
+use syn::{Data, DeriveInput, Error, Fields, Ident, Result};
+
+pub fn derive(input: DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => return Err(Error::new_spanned(name, "ClassifierId can only be derived for enums")),
+    };
+
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(&input.generics, "ClassifierId can not be generic"));
+    }
+
+    let mut none: Option<&Ident> = None;
+    let mut initial: Option<&Ident> = None;
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(Error::new_spanned(variant, "ClassifierId variants can not have fields"));
+        }
+        if let Some((_, discriminant)) = &variant.discriminant {
+            return Err(Error::new_spanned(
+                discriminant,
+                "ClassifierId variants can not have explicit discriminants",
+            ));
+        }
+
+        for attr in variant
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("classifier_id"))
+        {
+            attr.parse_nested_meta(|meta| {
+                let slot = if meta.path.is_ident("none") {
+                    &mut none
+                } else if meta.path.is_ident("initial") {
+                    &mut initial
+                } else {
+                    return Err(meta.error("Expected `none` or `initial`"));
+                };
+
+                match slot.replace(&variant.ident) {
+                    Some(_) => Err(meta.error("Only one variant can have this attribute")),
+                    None => Ok(()),
+                }
+            })?;
+        }
+    }
+
+    let none = none.ok_or_else(|| {
+        Error::new_spanned(name, "A variant must be marked with #[classifier_id(none)]")
+    })?;
+    let initial = initial.ok_or_else(|| {
+        Error::new_spanned(name, "A variant must be marked with #[classifier_id(initial)]")
+    })?;
+
+    let variants = data.variants.iter().map(|variant| &variant.ident);
+    let indices = (0..data.variants.len()).map(Literal::usize_unsuffixed);
+    let total = data.variants.len();
+
+    Ok(quote! {
+        impl ::std::convert::From<usize> for #name {
+            fn from(value: usize) -> Self {
+                match value {
+                    #(#indices => Self::#variants,)*
+                    _ => panic!(
+                        "The value {} must be a valid {}",
+                        value,
+                        ::std::stringify!(#name)
+                    ),
+                }
+            }
+        }
+
+        impl ::std::convert::From<#name> for usize {
+            fn from(id: #name) -> usize {
+                id as usize
+            }
+        }
+
+        impl ::pmc_core::base::config::ClassifierId for #name {
+            const NONE: Self = Self::#none;
+            const INITIAL: Self = Self::#initial;
+            const TOTAL: usize = #total;
+        }
+    })
+}
@@ -0,0 +1,213 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::{
+    Error, Expr, FnArg, Ident, Index, ItemFn, Lit, LitStr, Meta, Path, Result, Token, Type,
+    Visibility,
+};
+
+struct Args {
+    name: Ident,
+    classifier: Path,
+    config: Path,
+    grant_by_flow: bool,
+    break_grant: Option<Path>,
+    definition: Option<LitStr>,
+}
+
+impl Args {
+    fn parse(args: TokenStream) -> Result<Args> {
+        let metas = Punctuated::<Meta, Token![,]>::parse_terminated.parse2(args.clone())?;
+        let mut metas = metas.into_iter();
+
+        let name = match metas.next() {
+            Some(Meta::Path(path)) if path.get_ident().is_some() => {
+                path.get_ident().unwrap().clone()
+            }
+            _ => return Err(Error::new_spanned(args, "Expected the name of the value first")),
+        };
+
+        let mut classifier = None;
+        let mut config = None;
+        let mut grant_by_flow = false;
+        let mut break_grant = None;
+        let mut definition = None;
+        for meta in metas {
+            match &meta {
+                Meta::Path(path) if path.is_ident("grant_by_flow") => grant_by_flow = true,
+                Meta::NameValue(pair) if pair.path.is_ident("classifier") => {
+                    classifier = Some(expect_path(&pair.value)?);
+                }
+                Meta::NameValue(pair) if pair.path.is_ident("config") => {
+                    config = Some(expect_path(&pair.value)?);
+                }
+                Meta::NameValue(pair) if pair.path.is_ident("break_grant") => {
+                    break_grant = Some(expect_path(&pair.value)?);
+                }
+                Meta::NameValue(pair) if pair.path.is_ident("definition") => {
+                    definition = Some(match &pair.value {
+                        Expr::Lit(expr) => match &expr.lit {
+                            Lit::Str(name) => name.clone(),
+                            _ => return Err(Error::new_spanned(&pair.value, "Expected a string")),
+                        },
+                        _ => return Err(Error::new_spanned(&pair.value, "Expected a string")),
+                    });
+                }
+                _ => {
+                    return Err(Error::new_spanned(
+                        meta,
+                        "Expected `classifier`, `config`, `grant_by_flow`, `break_grant` or \
+                         `definition`",
+                    ))
+                }
+            }
+        }
+
+        Ok(Args {
+            classifier: classifier
+                .ok_or_else(|| Error::new_spanned(&name, "Expected `classifier = Path`"))?,
+            config: config.unwrap_or_else(|| syn::parse_quote!(Config)),
+            name,
+            grant_by_flow,
+            break_grant,
+            definition,
+        })
+    }
+}
+
+fn expect_path(expr: &Expr) -> Result<Path> {
+    match expr {
+        Expr::Path(expr) => Ok(expr.path.clone()),
+        _ => Err(Error::new_spanned(expr, "Expected a path")),
+    }
+}
+
+pub fn expand(args: TokenStream, mut item: ItemFn) -> Result<TokenStream> {
+    let Args {
+        name,
+        classifier,
+        config,
+        grant_by_flow,
+        break_grant,
+        definition,
+    } = Args::parse(args)?;
+
+    if !item.sig.generics.params.is_empty() {
+        return Err(Error::new_spanned(&item.sig.generics, "The predicate can not be generic"));
+    }
+
+    let inputs = item.sig.inputs.iter().collect::<Vec<_>>();
+    if inputs.len() < 2 {
+        return Err(Error::new_spanned(
+            &item.sig,
+            "The predicate must receive the analyzer and the flow as last parameters",
+        ));
+    }
+    let (fields, analysis) = inputs.split_at(inputs.len() - 2);
+
+    let mut field_types = Vec::new();
+    for input in fields {
+        match input {
+            FnArg::Typed(input) => match &*input.ty {
+                Type::Reference(reference) if reference.mutability.is_none() => {
+                    field_types.push(&*reference.elem)
+                }
+                _ => {
+                    return Err(Error::new_spanned(
+                        &input.ty,
+                        "Value parameters must be references, as `port: &u16`",
+                    ))
+                }
+            },
+            FnArg::Receiver(receiver) => {
+                return Err(Error::new_spanned(receiver, "The predicate can not have `self`"))
+            }
+        }
+    }
+
+    let mut analysis_types = Vec::new();
+    for input in analysis {
+        match input {
+            FnArg::Typed(input) => analysis_types.push(&*input.ty),
+            FnArg::Receiver(receiver) => {
+                return Err(Error::new_spanned(receiver, "The predicate can not have `self`"))
+            }
+        }
+    }
+    let (analyzer_type, flow_type) = (analysis_types[0], analysis_types[1]);
+
+    let break_grant = match break_grant {
+        None => quote! {},
+        Some(break_grant) => quote! {
+            fn should_break_grant(&self, analyzer: #analyzer_type) -> bool {
+                #break_grant(analyzer)
+            }
+        },
+    };
+
+    let definition = match definition {
+        None => quote! {},
+        Some(definition) => {
+            let call = match field_types.len() {
+                0 => quote! { ::pmc_core::language::ValueCall::new(#definition) },
+                1 => quote! {
+                    ::pmc_core::language::ValueCall::new(#definition).with_comparison(
+                        ::pmc_core::language::Operator::Equal,
+                        ::std::clone::Clone::clone(&self.0),
+                    )
+                },
+                _ => {
+                    return Err(Error::new_spanned(
+                        definition,
+                        "A definition is only generated for values with at most one parameter",
+                    ))
+                }
+            };
+
+            quote! {
+                fn definition(&self) -> ::std::option::Option<::pmc_core::language::ValueCall> {
+                    ::std::option::Option::Some(#call)
+                }
+            }
+        }
+    };
+
+    // The documentation belongs to the value, the function is only its implementation.
+    let (docs, attrs) = item
+        .attrs
+        .drain(..)
+        .partition::<Vec<_>, _>(|attr| attr.path().is_ident("doc"));
+    item.attrs = attrs;
+    let vis = std::mem::replace(&mut item.vis, Visibility::Inherited);
+
+    let function = &item.sig.ident;
+    let field_indices = (0..field_types.len()).map(Index::from);
+
+    let definition_struct = match field_types.is_empty() {
+        true => quote! { #vis struct #name; },
+        false => quote! { #vis struct #name(#(pub #field_types),*); },
+    };
+
+    Ok(quote! {
+        #(#docs)*
+        #[derive(Debug)]
+        #definition_struct
+
+        impl ::pmc_core::base::expression_value::ExpressionValue<#config> for #name {
+            type Classifier = #classifier;
+
+            const SHOULD_GRANT_BY_FLOW: bool = #grant_by_flow;
+
+            #break_grant
+
+            #definition
+
+            fn check(&self, analyzer: #analyzer_type, flow: #flow_type) -> bool {
+                #item
+
+                #function(#(&self.#field_indices,)* analyzer, flow)
+            }
+        }
+    })
+}
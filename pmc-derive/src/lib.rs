@@ -0,0 +1,65 @@
+//! Derive macros to reduce the boilerplate of writing classifiers with `pmc-core`.
+//! They are re-exported at the root of `pmc-core` by the `derive` feature.
+
+mod classifier_id;
+mod expression_value;
+
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, DeriveInput, ItemFn};
+
+/// Implements `pmc_core::base::config::ClassifierId` for an enum without fields,
+/// along with the `usize` conversions it requires.
+///
+/// The variant used as `NONE` is marked with `#[classifier_id(none)]` and the first classifier
+/// to analyze a packet, `INITIAL`, with `#[classifier_id(initial)]`.
+/// The enum must still derive `Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord`.
+///
+/// ```ignore
+/// #[derive(ClassifierId, Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+/// pub enum ClassifierId {
+///     #[classifier_id(none)]
+///     None,
+///     #[classifier_id(initial)]
+///     Ip,
+///     Tcp,
+/// }
+/// ```
+#[proc_macro_derive(ClassifierId, attributes(classifier_id))]
+pub fn derive_classifier_id(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    classifier_id::derive(input)
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
+}
+
+/// Creates an expression value from a predicate function.
+///
+/// The last two parameters of the function are the analyzer and the flow of the classifier.
+/// Any previous parameter, always a reference, becomes a public field of the value.
+/// The doc comments of the function are moved to the value.
+///
+/// Arguments, after the name of the value:
+/// - `classifier = Path`: the classifier of the value. Required.
+/// - `config = Path`: the configuration type. By default, `Config`.
+/// - `grant_by_flow`: sets `SHOULD_GRANT_BY_FLOW`.
+/// - `break_grant = function`: implements `should_break_grant` with a function of the analyzer.
+/// - `definition = "name"`: name of the value in the rule language.
+///   A value with a field is written as `name == field`.
+///
+/// ```ignore
+/// /// Packets with this destination port.
+/// #[expression_value(UdpDestPort, classifier = UdpClassifier, definition = "udp.dest_port")]
+/// fn udp_dest_port(port: &u16, packet: &UdpAnalyzer, _flow: &UdpFlow) -> bool {
+///     *port == packet.dest_port()
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn expression_value(args: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+    expression_value::expand(args.into(), item)
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,210 @@
+use crate::{classifier_id, expression_value};
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{parse_quote, Item, ItemImpl, ItemStruct};
+
+fn derive_error(input: TokenStream) -> String {
+    classifier_id::derive(syn::parse2(input).unwrap())
+        .unwrap_err()
+        .to_string()
+}
+
+fn expand(args: TokenStream, item: TokenStream) -> syn::Result<Vec<Item>> {
+    let expanded = expression_value::expand(args, syn::parse2(item).unwrap())?;
+    Ok(syn::parse2::<syn::File>(expanded).unwrap().items)
+}
+
+fn expand_error(args: TokenStream, item: TokenStream) -> String {
+    match expand(args, item) {
+        Ok(_) => panic!("Expected an error"),
+        Err(error) => error.to_string(),
+    }
+}
+
+/// Items of the impl block, as source text without spaces.
+fn impl_items(item: &Item) -> Vec<String> {
+    let item: &ItemImpl = match item {
+        Item::Impl(item) => item,
+        _ => panic!("Expected an impl block"),
+    };
+    item.items
+        .iter()
+        .map(|item| quote!(#item).to_string().replace(' ', ""))
+        .collect()
+}
+
+#[test]
+fn classifier_id_constants() {
+    let expanded = classifier_id::derive(parse_quote! {
+        enum Id {
+            #[classifier_id(none)]
+            None,
+            #[classifier_id(initial)]
+            Ip,
+            Tcp,
+        }
+    })
+    .unwrap();
+    let items = syn::parse2::<syn::File>(expanded).unwrap().items;
+
+    assert_eq!(items.len(), 3);
+    assert_eq!(
+        impl_items(&items[2]),
+        vec![
+            "constNONE:Self=Self::None;",
+            "constINITIAL:Self=Self::Ip;",
+            "constTOTAL:usize=3usize;",
+        ]
+    );
+}
+
+#[test]
+fn classifier_id_errors() {
+    assert_eq!(
+        derive_error(quote! { enum Id { #[classifier_id(initial)] Ip } }),
+        "A variant must be marked with #[classifier_id(none)]"
+    );
+    assert_eq!(
+        derive_error(quote! { enum Id { #[classifier_id(none)] None } }),
+        "A variant must be marked with #[classifier_id(initial)]"
+    );
+    assert_eq!(
+        derive_error(quote! {
+            enum Id {
+                #[classifier_id(none)]
+                None,
+                #[classifier_id(initial)]
+                Ip,
+                #[classifier_id(initial)]
+                Tcp,
+            }
+        }),
+        "Only one variant can have this attribute"
+    );
+    assert_eq!(
+        derive_error(quote! { enum Id { #[classifier_id(first)] Ip } }),
+        "Expected `none` or `initial`"
+    );
+    assert_eq!(
+        derive_error(quote! { enum Id { Ip(u8) } }),
+        "ClassifierId variants can not have fields"
+    );
+    assert_eq!(derive_error(quote! { struct Id; }), "ClassifierId can only be derived for enums");
+}
+
+#[test]
+fn expression_value_with_field() {
+    let items = expand(
+        quote! { UdpDestPort, classifier = UdpClassifier, grant_by_flow, definition = "udp.dest_port" },
+        quote! {
+            /// Packets with this destination port.
+            pub fn udp_dest_port(port: &u16, packet: &UdpAnalyzer, _flow: &UdpFlow) -> bool {
+                *port == packet.dest_port()
+            }
+        },
+    )
+    .unwrap();
+
+    let mut value: ItemStruct = match &items[0] {
+        Item::Struct(value) => value.clone(),
+        _ => panic!("Expected the value struct"),
+    };
+    assert!(value.attrs.iter().any(|attr| attr.path().is_ident("doc")));
+    value.attrs.retain(|attr| !attr.path().is_ident("doc"));
+    assert_eq!(
+        quote!(#value).to_string().replace(' ', ""),
+        "#[derive(Debug)]pubstructUdpDestPort(pubu16);"
+    );
+
+    let impl_items = impl_items(&items[1]);
+    assert_eq!(impl_items[0], "typeClassifier=UdpClassifier;");
+    assert_eq!(impl_items[1], "constSHOULD_GRANT_BY_FLOW:bool=true;");
+    assert!(impl_items[2].contains("ValueCall::new(\"udp.dest_port\").with_comparison("));
+    assert!(impl_items[2].contains("Clone::clone(&self.0)"));
+    assert!(impl_items[3].ends_with("udp_dest_port(&self.0,analyzer,flow)}"));
+}
+
+#[test]
+fn expression_value_without_field() {
+    let items = expand(
+        quote! { Udp, classifier = UdpClassifier },
+        quote! {
+            fn udp(_packet: &UdpAnalyzer, _flow: &UdpFlow) -> bool {
+                true
+            }
+        },
+    )
+    .unwrap();
+
+    let impl_items = impl_items(&items[1]);
+    assert_eq!(impl_items.len(), 3);
+    assert_eq!(impl_items[1], "constSHOULD_GRANT_BY_FLOW:bool=false;");
+    assert!(impl_items[2].ends_with("udp(analyzer,flow)}"));
+}
+
+#[test]
+fn expression_value_breaking_grant() {
+    let items = expand(
+        quote! { TcpEstablished, classifier = TcpClassifier, grant_by_flow, break_grant = is_fin },
+        quote! {
+            fn tcp_established(_packet: &TcpAnalyzer, flow: &TcpFlow) -> bool {
+                flow.is_established()
+            }
+        },
+    )
+    .unwrap();
+
+    let impl_items = impl_items(&items[1]);
+    assert_eq!(impl_items.len(), 4);
+    assert_eq!(
+        impl_items[2],
+        "fnshould_break_grant(&self,analyzer:&TcpAnalyzer)->bool{is_fin(analyzer)}"
+    );
+}
+
+#[test]
+fn expression_value_errors() {
+    let predicate = quote! {
+        fn udp(_packet: &UdpAnalyzer, _flow: &UdpFlow) -> bool {
+            true
+        }
+    };
+    assert_eq!(expand_error(quote! { Udp }, predicate.clone()), "Expected `classifier = Path`");
+    assert_eq!(
+        expand_error(quote! { classifier = UdpClassifier }, predicate.clone()),
+        "Expected the name of the value first"
+    );
+    assert_eq!(
+        expand_error(quote! { Udp, classifier = UdpClassifier, flow }, predicate.clone()),
+        "Expected `classifier`, `config`, `grant_by_flow`, `break_grant` or `definition`"
+    );
+    assert_eq!(
+        expand_error(quote! { Udp, classifier = UdpClassifier, definition = udp }, predicate),
+        "Expected a string"
+    );
+
+    assert_eq!(
+        expand_error(
+            quote! { Udp, classifier = UdpClassifier },
+            quote! { fn udp(_packet: &UdpAnalyzer) -> bool { true } },
+        ),
+        "The predicate must receive the analyzer and the flow as last parameters"
+    );
+    assert_eq!(
+        expand_error(
+            quote! { UdpPort, classifier = UdpClassifier },
+            quote! { fn udp(port: u16, _packet: &UdpAnalyzer, _flow: &UdpFlow) -> bool { true } },
+        ),
+        "Value parameters must be references, as `port: &u16`"
+    );
+    assert_eq!(
+        expand_error(
+            quote! { UdpPorts, classifier = UdpClassifier, definition = "udp.ports" },
+            quote! {
+                fn udp(a: &u16, b: &u16, _packet: &UdpAnalyzer, _flow: &UdpFlow) -> bool { true }
+            },
+        ),
+        "A definition is only generated for values with at most one parameter"
+    );
+}
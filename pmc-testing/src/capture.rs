@@ -1,19 +1,37 @@
+use pmc_core::packet::{LinkType, Packet};
+
+use std::time::Duration;
+
 pub struct CapturedPacket {
-    //TODO: timestamp
     pub id: usize,
+    pub timestamp: Duration,
     pub uplink: bool,
     pub data: Vec<u8>,
+    /// Length of the packet in the wire, higher than `data` if the capture truncated it.
+    pub wire_len: usize,
+    pub interface: u32,
+    pub link_type: LinkType,
+}
+
+impl CapturedPacket {
+    pub fn as_packet(&self) -> Packet<'_> {
+        Packet::new(&self.data, self.uplink.into())
+            .with_timestamp(self.timestamp)
+            .with_wire_len(self.wire_len)
+            .with_interface(self.interface)
+            .with_link_type(self.link_type)
+    }
 }
 
 pub trait Capture {
-    fn section(&self, first: usize, last: usize) -> CaptureIterator;
+    fn section(&self, first: usize, last: usize) -> CaptureIterator<'_>;
     fn len(&self) -> usize;
 
     fn is_empty(&self) -> bool {
         self.len() == 0
     }
 
-    fn iter(&self) -> CaptureIterator {
+    fn iter(&self) -> CaptureIterator<'_> {
         self.section(1, self.len())
     }
 }
@@ -3,7 +3,7 @@ use pmc_core::engine::{ClassifierEngine, Rule};
 use pmc_core::loader::ClassifierLoader;
 
 use crate::capture::Capture;
-use crate::injector::Injector;
+use crate::injector::{InjectionResult, Injector};
 use crate::logger::{self};
 use crate::summary::Summary;
 
@@ -22,7 +22,9 @@ pub struct TestConfig<C: Config, T, R: Capture> {
     pub expected_classification: Vec<T>,
 }
 
-pub fn run_classification_test<C, T, R>(test_config: TestConfig<C, T, R>)
+pub fn run_classification_test<C, T, R>(
+    test_config: TestConfig<C, T, R>,
+) -> InjectionResult<T, C::ClassifierId>
 where
     T: fmt::Debug + fmt::Display + Default + Copy + Eq,
     C: Config,
@@ -46,7 +48,8 @@ where
     }
 
     let mut classifier =
-        ClassifierEngine::<C, T>::new(test_config.loader, test_config.config, test_config.rules);
+        ClassifierEngine::<C, T>::new(test_config.loader, test_config.config, test_config.rules)
+            .unwrap_or_else(|error| panic!("Invalid engine configuration: {}", error));
     let mut injector = Injector::new(&test_config.expected_classification);
 
     for capture_data in test_config.captures {
@@ -56,8 +59,11 @@ where
     }
 
     log::info!("{}", Summary::new(classifier.rule_tags(), &injector.results().classifications));
+    log::info!("{:?}", classifier.flow_stats());
 
     for (index, classification) in injector.results().classifications.iter().enumerate() {
         assert_eq!(classification.rule_tag, test_config.expected_classification[index]);
     }
+
+    injector.results().clone()
 }
@@ -0,0 +1,107 @@
+use crate::capture::CapturedPacket;
+
+use pmc_core::base::config::Config;
+use pmc_core::engine::ClassifierEngine;
+use pmc_core::packet::{Direction, Packet};
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+pub struct FuzzConfig {
+    /// Seed of the random generator, the same seed produces the same packets.
+    pub seed: u64,
+    /// Random copies of each sample packet with some bytes changed.
+    pub mutations_per_sample: usize,
+    /// Bytes changed in each mutation, at most.
+    pub max_mutated_bytes: usize,
+    /// Packets with random content.
+    pub random_packets: usize,
+    /// Length of the random packets, at most.
+    pub max_random_len: usize,
+}
+
+impl Default for FuzzConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            mutations_per_sample: 20,
+            max_mutated_bytes: 8,
+            random_packets: 1000,
+            max_random_len: 128,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FuzzResult {
+    pub packets: usize,
+    /// Packets whose analysis was aborted by an analyzer error.
+    pub aborted: usize,
+}
+
+/// Classifies, in both directions, every truncation of each sample, as a short packet and as a
+/// packet truncated by the capture, mutated copies of the samples and random packets.
+/// Panics with the packet content if the engine panics classifying a packet.
+pub fn run_fuzz_test<C, T>(
+    engine: &mut ClassifierEngine<C, T>,
+    samples: &[&CapturedPacket],
+    config: &FuzzConfig,
+) -> FuzzResult
+where
+    T: fmt::Display + Default + Eq + Copy,
+    C: Config,
+{
+    let rng = fastrand::Rng::with_seed(config.seed);
+    let mut result = FuzzResult::default();
+
+    for sample in samples {
+        for len in 0..=sample.data.len() {
+            classify(engine, &sample.data[..len], len, &mut result);
+            classify(engine, &sample.data[..len], sample.wire_len, &mut result);
+        }
+
+        for _ in 0..config.mutations_per_sample {
+            let mut data = sample.data.clone();
+            if !data.is_empty() {
+                for _ in 0..rng.usize(1..=config.max_mutated_bytes) {
+                    let index = rng.usize(..data.len());
+                    data[index] = rng.u8(..);
+                }
+            }
+            classify(engine, &data, data.len(), &mut result);
+        }
+    }
+
+    for _ in 0..config.random_packets {
+        let data = (0..rng.usize(..=config.max_random_len))
+            .map(|_| rng.u8(..))
+            .collect::<Vec<_>>();
+        classify(engine, &data, data.len(), &mut result);
+    }
+
+    result
+}
+
+fn classify<C, T>(
+    engine: &mut ClassifierEngine<C, T>,
+    data: &[u8],
+    wire_len: usize,
+    result: &mut FuzzResult,
+) where
+    T: fmt::Display + Default + Eq + Copy,
+    C: Config,
+{
+    for direction in [Direction::Uplink, Direction::Downlink] {
+        let packet = Packet::new(data, direction).with_wire_len(wire_len);
+        let classification =
+            panic::catch_unwind(AssertUnwindSafe(|| engine.classify_packet(packet)))
+                .unwrap_or_else(|_| {
+                    panic!("Panic classifying the {} packet {:02x?}", direction, data)
+                });
+
+        result.packets += 1;
+        if classification.abort.is_some() {
+            result.aborted += 1;
+        }
+    }
+}
@@ -1,18 +1,17 @@
 use crate::capture::CaptureIterator;
 use crate::logger::{self, PacketProps};
 
-use pmc_core::base::config::Config;
+use pmc_core::base::config::{ClassifierId, Config};
 use pmc_core::engine::{ClassificationResult, ClassifierEngine, RuleValueAction};
-use pmc_core::packet::{Direction, Packet};
 
 use colored::Colorize;
 
-pub struct Injector<T> {
-    total_results: InjectionResult<T>,
+pub struct Injector<T, I: ClassifierId> {
+    total_results: InjectionResult<T, I>,
     expected_classification_tags: Vec<T>,
 }
 
-impl<T: std::fmt::Display + Default + Copy + Eq> Injector<T> {
+impl<T: std::fmt::Display + Default + Copy + Eq, I: ClassifierId> Injector<T, I> {
     pub fn new(expected_classification_tags: &[T]) -> Self {
         Self {
             total_results: InjectionResult::default(),
@@ -20,11 +19,11 @@ impl<T: std::fmt::Display + Default + Copy + Eq> Injector<T> {
         }
     }
 
-    pub fn inject_packets<C: Config>(
+    pub fn inject_packets<C: Config<ClassifierId = I>>(
         &mut self,
         classifier: &mut ClassifierEngine<C, T>,
         capture_section: CaptureIterator,
-    ) -> InjectionResult<T> {
+    ) -> InjectionResult<T, I> {
         let mut current_injection_result = InjectionResult::default();
 
         for captured_packet in capture_section {
@@ -33,12 +32,7 @@ impl<T: std::fmt::Display + Default + Copy + Eq> Injector<T> {
                 uplink: captured_packet.uplink,
             }));
 
-            let packet = Packet {
-                data: &captured_packet.data,
-                direction: Direction::from(captured_packet.uplink),
-            };
-
-            let classification_result = classifier.classify_packet(packet);
+            let classification_result = classifier.classify_packet(captured_packet.as_packet());
 
             self.log(
                 classifier.rule_tags(),
@@ -55,14 +49,14 @@ impl<T: std::fmt::Display + Default + Copy + Eq> Injector<T> {
         current_injection_result
     }
 
-    pub fn results(&self) -> &InjectionResult<T> {
+    pub fn results(&self) -> &InjectionResult<T, I> {
         &self.total_results
     }
 
     fn log(
         &self,
         rule_tags: Vec<T>,
-        classification_result: &ClassificationResult<T>,
+        classification_result: &ClassificationResult<T, I>,
         current_injection_packet: usize,
     ) {
         let expected_rule_tag = self
@@ -71,7 +65,7 @@ impl<T: std::fmt::Display + Default + Copy + Eq> Injector<T> {
             .expect("The number of processed packet must be equals to expected");
 
         log::info!(
-            "{} bytes classified as {} -> {} {}",
+            "{} bytes classified as {} -> {} {} {}",
             format!("{:>4}", classification_result.payload_bytes).bright_magenta(),
             format!(
                 "{:<tag_width$}",
@@ -94,25 +88,36 @@ impl<T: std::fmt::Display + Default + Copy + Eq> Injector<T> {
             },
             match classification_result.rule_value_action {
                 RuleValueAction::Computed => String::new().bright_white(),
-                //TODO: enable when grant feature is done
-                RuleValueAction::ComputedAndCached => String::new().bright_white(), //"(granted now)".bright_black(),
-                RuleValueAction::Cached => String::new().bright_white(), //"(granted)".bright_black(),
+                RuleValueAction::ComputedAndCached => "(granted now)".bright_black(),
+                RuleValueAction::Cached => "(granted)".bright_black(),
+            },
+            match &classification_result.abort {
+                Some(error) => format!("(aborted: {})", error).bright_yellow(),
+                None => String::new().bright_white(),
             }
         );
     }
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct InjectionResult<T> {
-    pub classifications: Vec<ClassificationResult<T>>,
+#[derive(Debug, Clone)]
+pub struct InjectionResult<T, I: ClassifierId> {
+    pub classifications: Vec<ClassificationResult<T, I>>,
 }
 
-impl<T: Copy> InjectionResult<T> {
-    pub fn chain(&mut self, other: &InjectionResult<T>) {
+impl<T, I: ClassifierId> Default for InjectionResult<T, I> {
+    fn default() -> Self {
+        Self {
+            classifications: Vec::new(),
+        }
+    }
+}
+
+impl<T: Copy, I: ClassifierId> InjectionResult<T, I> {
+    pub fn chain(&mut self, other: &InjectionResult<T, I>) {
         self.classifications.extend(other.classifications.clone());
     }
 
-    pub fn add_packet_result(&mut self, classification: ClassificationResult<T>) {
+    pub fn add_packet_result(&mut self, classification: ClassificationResult<T, I>) {
         self.classifications.push(classification);
     }
 
@@ -130,4 +135,11 @@ impl<T: Copy> InjectionResult<T> {
             .map(|result| result.rule_tag)
             .collect::<Vec<T>>()
     }
+
+    pub fn actions(&self) -> Vec<RuleValueAction> {
+        self.classifications
+            .iter()
+            .map(|result| result.rule_value_action)
+            .collect::<Vec<RuleValueAction>>()
+    }
 }
@@ -1,5 +1,6 @@
 pub mod capture;
 pub mod common;
+pub mod fuzz;
 pub mod injector;
 pub mod logger;
 pub mod summary;
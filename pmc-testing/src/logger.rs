@@ -63,17 +63,14 @@ fn configure_logger() -> Result<(), fern::InitError> {
                     .unwrap()
                     .clone()
                     .map(|PacketProps { number, uplink }| format!(
-                        "[{}]",
-                        format!(
-                            "{} {}",
-                            if uplink { "->".yellow() } else { "<-".cyan() },
-                            number.to_string().bright_yellow()
-                        )
+                        "[{} {}]",
+                        if uplink { "->".yellow() } else { "<-".cyan() },
+                        number.to_string().bright_yellow()
                     ))
                     .unwrap_or_default(),
                 w = if PACKET_PROPS.read().unwrap().is_some() {
                     // The console color adds extra characters that must be contemplated
-                    format!("{}", format!("").white()).len() * 2 + 7
+                    format!("{}", String::new().white()).len() * 2 + 7
                 } else {
                     0
                 }
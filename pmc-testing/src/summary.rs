@@ -1,3 +1,4 @@
+use pmc_core::base::config::ClassifierId;
 use pmc_core::engine::ClassificationResult;
 
 use colored::Colorize;
@@ -31,7 +32,10 @@ pub struct Summary<T> {
 }
 
 impl<T: fmt::Display + Eq + Copy + Default> Summary<T> {
-    pub fn new(mut rule_tags: Vec<T>, classifications: &[ClassificationResult<T>]) -> Summary<T> {
+    pub fn new<I: ClassifierId>(
+        mut rule_tags: Vec<T>,
+        classifications: &[ClassificationResult<T, I>],
+    ) -> Summary<T> {
         rule_tags.push(T::default());
 
         let results = rule_tags